@@ -0,0 +1,699 @@
+// Keyfinitum/src/capture.rs
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::device::SourceDevice;
+use crate::keycode::KeyCode;
+use crate::remapping::KeyRemapping;
+
+/// How long the capture loop waits for input before re-checking its stop signal
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Whether this platform's input source tells which device each event came from. The Windows
+/// hooks cannot: they run before Raw Input reports the device, which never sees suppressed events.
+pub const ATTRIBUTES_DEVICES: bool = cfg!(target_os = "linux");
+
+/// A physical key event delivered by an input source
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputEvent {
+    pub key: u32,
+    /// Set 1 scan code of the physical key, with 0xE0 in the high byte for extended keys; 0 if unknown
+    pub scan: u16,
+    pub pressed: bool,
+    pub time: Instant,
+    /// Generated by Keyfinitum's own output backend; passed through without remapping
+    pub injected: bool,
+    /// Device the event came from, where the source can tell (see `ATTRIBUTES_DEVICES`)
+    pub device: Option<SourceDevice>,
+}
+
+#[allow(dead_code)]
+impl InputEvent {
+    /// A press of the key, with the scan code the key table gives it
+    pub fn press(key: u32) -> Self {
+        Self { key, scan: default_scan_code(key), pressed: true, time: Instant::now(), injected: false, device: None }
+    }
+
+    /// A release of the key, with the scan code the key table gives it
+    pub fn release(key: u32) -> Self {
+        Self { key, scan: default_scan_code(key), pressed: false, time: Instant::now(), injected: false, device: None }
+    }
+}
+
+fn default_scan_code(key: u32) -> u16 {
+    KeyCode::from_vk(key).map_or(0, KeyCode::scan_code)
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+    /// The source has no more events and will never produce any
+    Closed,
+    Platform(String),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::Closed => write!(f, "Input source closed"),
+            CaptureError::Platform(msg) => write!(f, "Input capture failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+/// A platform hook or device reader that delivers physical input events
+pub trait InputSource: Send {
+    /// Wait up to `timeout` for the next physical event; `Ok(None)` means the wait timed out
+    fn next_event(&mut self, timeout: Duration) -> Result<Option<InputEvent>, CaptureError>;
+
+    /// Report what happened to the event last returned by `next_event`:
+    /// suppressed events are swallowed, all others reach the system unchanged
+    fn complete_event(&mut self, event: &InputEvent, suppress: bool) -> Result<(), CaptureError>;
+}
+
+/// Open the native input source for the current platform
+pub fn platform_source() -> Result<Box<dyn InputSource>, CaptureError> {
+    #[cfg(windows)]
+    {
+        Ok(Box::new(windows_hook::HookSource::install()?))
+    }
+    #[cfg(target_os = "linux")]
+    {
+        Ok(Box::new(crate::linux_input::EvdevSource::open()?))
+    }
+    #[cfg(not(any(windows, target_os = "linux")))]
+    {
+        Err(CaptureError::Platform("Input capture is not supported on this platform".to_string()))
+    }
+}
+
+/// Runs an input source on its own thread and routes every event through the remapping engine
+pub struct InputCapture {
+    capture_thread: Option<thread::JoinHandle<()>>,
+    stop_signal: Arc<AtomicBool>,
+}
+
+#[allow(dead_code)]
+impl InputCapture {
+    /// Start capturing from `source`, feeding events into `engine`
+    pub fn start(source: Box<dyn InputSource>, engine: Arc<Mutex<KeyRemapping>>) -> Self {
+        let stop_signal = Arc::new(AtomicBool::new(false));
+        let thread_stop_signal = Arc::clone(&stop_signal);
+
+        let capture_thread = thread::spawn(move || {
+            capture_loop(source, engine, thread_stop_signal);
+        });
+
+        Self {
+            capture_thread: Some(capture_thread),
+            stop_signal,
+        }
+    }
+
+    /// Whether the capture thread is still running
+    pub fn is_running(&self) -> bool {
+        self.capture_thread.as_ref().is_some_and(|handle| !handle.is_finished())
+    }
+
+    /// Signal the capture thread to stop and wait for it to exit
+    pub fn stop(&mut self) {
+        if let Some(handle) = self.capture_thread.take() {
+            self.stop_signal.store(true, Ordering::SeqCst);
+            let _ = handle.join();
+        }
+    }
+
+    /// Wait for the capture thread to exit on its own, e.g. because its source closed
+    pub fn wait(&mut self) {
+        if let Some(handle) = self.capture_thread.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for InputCapture {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn capture_loop(mut source: Box<dyn InputSource>, engine: Arc<Mutex<KeyRemapping>>, stop_signal: Arc<AtomicBool>) {
+    while !stop_signal.load(Ordering::SeqCst) {
+        // Wake up in time for pending engine decisions such as tap-hold timeouts
+        let timeout = engine.lock().unwrap()
+            .next_deadline()
+            .map_or(POLL_INTERVAL, |deadline| {
+                deadline.saturating_duration_since(Instant::now()).min(POLL_INTERVAL)
+            });
+
+        match source.next_event(timeout) {
+            // Our own output must never be remapped again, or swapped keys would loop forever
+            Ok(Some(event)) if event.injected => {
+                if let Err(e) = source.complete_event(&event, false) {
+                    eprintln!("{}", e);
+                }
+            }
+            Ok(Some(event)) => {
+                let consumed = match engine.lock().unwrap().handle_event(&event) {
+                    Ok(consumed) => consumed,
+                    Err(e) => {
+                        eprintln!("Failed to handle key {:#04x}: {:?}", event.key, e);
+                        false
+                    }
+                };
+
+                if let Err(e) = source.complete_event(&event, consumed) {
+                    eprintln!("{}", e);
+                }
+            }
+            Ok(None) => {}
+            Err(CaptureError::Closed) => break,
+            Err(e) => {
+                eprintln!("{}", e);
+                break;
+            }
+        }
+
+        if let Err(e) = engine.lock().unwrap().tick(Instant::now()) {
+            eprintln!("Failed to process timers: {:?}", e);
+        }
+    }
+
+    // Nothing will deliver the releases once capture ends
+    if let Err(e) = engine.lock().unwrap().release_held_keys() {
+        eprintln!("Failed to release held keys: {:?}", e);
+    }
+}
+
+/// Source that replays a fixed list of events, for tests
+#[allow(dead_code)]
+pub struct ScriptedSource {
+    events: VecDeque<InputEvent>,
+    results: Arc<Mutex<Vec<(InputEvent, bool)>>>,
+}
+
+#[allow(dead_code)]
+impl ScriptedSource {
+    pub fn new(events: Vec<InputEvent>) -> Self {
+        Self {
+            events: events.into(),
+            results: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Shared log of every completed event and whether it was suppressed
+    pub fn results(&self) -> Arc<Mutex<Vec<(InputEvent, bool)>>> {
+        Arc::clone(&self.results)
+    }
+}
+
+impl InputSource for ScriptedSource {
+    fn next_event(&mut self, _timeout: Duration) -> Result<Option<InputEvent>, CaptureError> {
+        self.events.pop_front().map(Some).ok_or(CaptureError::Closed)
+    }
+
+    fn complete_event(&mut self, event: &InputEvent, suppress: bool) -> Result<(), CaptureError> {
+        self.results.lock().unwrap().push((event.clone(), suppress));
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod windows_hook {
+    use std::cell::RefCell;
+    use std::ptr;
+    use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use winapi::shared::minwindef::{LPARAM, LRESULT, WPARAM};
+    use winapi::shared::windef::HHOOK;
+    use winapi::um::libloaderapi::GetModuleHandleW;
+    use winapi::um::processthreadsapi::GetCurrentThreadId;
+    use winapi::um::winuser::{
+        CallNextHookEx, GetMessageW, PostThreadMessageW, SetWindowsHookExW, UnhookWindowsHookEx,
+        HC_ACTION, KBDLLHOOKSTRUCT, LLKHF_EXTENDED, MSG, MSLLHOOKSTRUCT, WH_KEYBOARD_LL, WH_MOUSE_LL,
+        WM_KEYDOWN, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_MBUTTONDOWN, WM_MBUTTONUP, WM_MOUSEHWHEEL,
+        WM_MOUSEWHEEL, WM_QUIT, WM_RBUTTONDOWN, WM_RBUTTONUP, WM_SYSKEYDOWN, WM_XBUTTONDOWN,
+        WM_XBUTTONUP, XBUTTON1,
+    };
+    use super::{CaptureError, InputEvent, InputSource};
+    use crate::output::INJECTED_TAG;
+    use crate::remapping::{mouse_button_key, wheel_key, MouseButton, WheelDirection};
+
+    /// How long the hook waits for the engine before letting an event through
+    const DECISION_TIMEOUT: Duration = Duration::from_millis(200);
+
+    struct HookChannels {
+        hook: HHOOK,
+        mouse_hook: HHOOK,
+        next_id: u64,
+        events: Sender<(u64, InputEvent)>,
+        decisions: Receiver<(u64, bool)>,
+    }
+
+    thread_local! {
+        static HOOK_CHANNELS: RefCell<Option<HookChannels>> = const { RefCell::new(None) };
+    }
+
+    /// Low-level keyboard and mouse hooks running on a dedicated message-loop thread
+    pub struct HookSource {
+        events: Receiver<(u64, InputEvent)>,
+        decisions: Sender<(u64, bool)>,
+        current_id: u64,
+        hook_thread_id: u32,
+        hook_thread: Option<thread::JoinHandle<()>>,
+    }
+
+    impl HookSource {
+        pub fn install() -> Result<Self, CaptureError> {
+            let (event_tx, event_rx) = mpsc::channel();
+            let (decision_tx, decision_rx) = mpsc::channel();
+            let (ready_tx, ready_rx) = mpsc::channel();
+
+            let hook_thread = thread::spawn(move || unsafe {
+                let hook = SetWindowsHookExW(WH_KEYBOARD_LL, Some(keyboard_hook), GetModuleHandleW(ptr::null()), 0);
+                if hook.is_null() {
+                    let _ = ready_tx.send(Err(CaptureError::Platform("SetWindowsHookExW failed".to_string())));
+                    return;
+                }
+                let mouse_hook = SetWindowsHookExW(WH_MOUSE_LL, Some(mouse_hook), GetModuleHandleW(ptr::null()), 0);
+                if mouse_hook.is_null() {
+                    UnhookWindowsHookEx(hook);
+                    let _ = ready_tx.send(Err(CaptureError::Platform("SetWindowsHookExW failed for the mouse".to_string())));
+                    return;
+                }
+
+                HOOK_CHANNELS.with(|channels| {
+                    *channels.borrow_mut() = Some(HookChannels {
+                        hook,
+                        mouse_hook,
+                        next_id: 0,
+                        events: event_tx,
+                        decisions: decision_rx,
+                    });
+                });
+                let _ = ready_tx.send(Ok(GetCurrentThreadId()));
+
+                let mut msg: MSG = std::mem::zeroed();
+                while GetMessageW(&mut msg, ptr::null_mut(), 0, 0) > 0 {}
+
+                UnhookWindowsHookEx(mouse_hook);
+                UnhookWindowsHookEx(hook);
+                HOOK_CHANNELS.with(|channels| channels.borrow_mut().take());
+            });
+
+            let hook_thread_id = ready_rx
+                .recv()
+                .map_err(|_| CaptureError::Platform("Keyboard hook thread exited".to_string()))??;
+
+            Ok(Self {
+                events: event_rx,
+                decisions: decision_tx,
+                current_id: 0,
+                hook_thread_id,
+                hook_thread: Some(hook_thread),
+            })
+        }
+    }
+
+    impl InputSource for HookSource {
+        fn next_event(&mut self, timeout: Duration) -> Result<Option<InputEvent>, CaptureError> {
+            match self.events.recv_timeout(timeout) {
+                Ok((id, event)) => {
+                    self.current_id = id;
+                    Ok(Some(event))
+                }
+                Err(RecvTimeoutError::Timeout) => Ok(None),
+                Err(RecvTimeoutError::Disconnected) => Err(CaptureError::Closed),
+            }
+        }
+
+        fn complete_event(&mut self, _event: &InputEvent, suppress: bool) -> Result<(), CaptureError> {
+            self.decisions
+                .send((self.current_id, suppress))
+                .map_err(|_| CaptureError::Closed)
+        }
+    }
+
+    impl Drop for HookSource {
+        fn drop(&mut self) {
+            unsafe {
+                PostThreadMessageW(self.hook_thread_id, WM_QUIT, 0, 0);
+            }
+            if let Some(handle) = self.hook_thread.take() {
+                let _ = handle.join();
+            }
+        }
+    }
+
+    unsafe extern "system" fn keyboard_hook(code: i32, w_param: WPARAM, l_param: LPARAM) -> LRESULT {
+        let mut hook = ptr::null_mut();
+        let suppress = HOOK_CHANNELS.with(|channels| {
+            let mut channels = channels.borrow_mut();
+            let channels = channels.as_mut()?;
+            hook = channels.hook;
+            if code != HC_ACTION {
+                return None;
+            }
+
+            let info = &*(l_param as *const KBDLLHOOKSTRUCT);
+            // Let our own injected events through without a round trip to the capture thread
+            if info.dwExtraInfo == INJECTED_TAG {
+                return None;
+            }
+            let pressed = w_param as u32 == WM_KEYDOWN || w_param as u32 == WM_SYSKEYDOWN;
+            let extended = if info.flags & LLKHF_EXTENDED != 0 { 0xE000 } else { 0 };
+            let event = InputEvent {
+                key: info.vkCode,
+                scan: info.scanCode as u16 | extended,
+                pressed,
+                time: Instant::now(),
+                injected: false,
+                device: None,
+            };
+            decide(channels, event)
+        });
+
+        if suppress == Some(true) {
+            1
+        } else {
+            CallNextHookEx(hook, code, w_param, l_param)
+        }
+    }
+
+    unsafe extern "system" fn mouse_hook(code: i32, w_param: WPARAM, l_param: LPARAM) -> LRESULT {
+        let mut hook = ptr::null_mut();
+        let suppress = HOOK_CHANNELS.with(|channels| {
+            let mut channels = channels.borrow_mut();
+            let channels = channels.as_mut()?;
+            hook = channels.mouse_hook;
+            if code != HC_ACTION {
+                return None;
+            }
+
+            let info = &*(l_param as *const MSLLHOOKSTRUCT);
+            if info.dwExtraInfo == INJECTED_TAG {
+                return None;
+            }
+            // Movement never reaches the engine, so it costs no round trip
+            let (key, pressed) = mouse_trigger(w_param as u32, info.mouseData)?;
+            let event = InputEvent {
+                key,
+                scan: 0,
+                pressed,
+                time: Instant::now(),
+                injected: false,
+                device: None,
+            };
+            decide(channels, event)
+        });
+
+        if suppress == Some(true) {
+            1
+        } else {
+            CallNextHookEx(hook, code, w_param, l_param)
+        }
+    }
+
+    /// The engine key and press state of a mouse message, if it can trigger bindings
+    fn mouse_trigger(message: u32, mouse_data: u32) -> Option<(u32, bool)> {
+        let high_word = (mouse_data >> 16) as u16;
+        let button = |button: MouseButton, pressed: bool| Some((mouse_button_key(&button), pressed));
+        match message {
+            WM_LBUTTONDOWN => button(MouseButton::Left, true),
+            WM_LBUTTONUP => button(MouseButton::Left, false),
+            WM_RBUTTONDOWN => button(MouseButton::Right, true),
+            WM_RBUTTONUP => button(MouseButton::Right, false),
+            WM_MBUTTONDOWN => button(MouseButton::Middle, true),
+            WM_MBUTTONUP => button(MouseButton::Middle, false),
+            WM_XBUTTONDOWN | WM_XBUTTONUP => {
+                let side = if high_word == XBUTTON1 { MouseButton::Back } else { MouseButton::Forward };
+                button(side, message == WM_XBUTTONDOWN)
+            }
+            WM_MOUSEWHEEL | WM_MOUSEHWHEEL => {
+                let positive = (high_word as i16) > 0;
+                let direction = match (message == WM_MOUSEWHEEL, positive) {
+                    (true, true) => WheelDirection::Up,
+                    (true, false) => WheelDirection::Down,
+                    (false, true) => WheelDirection::Right,
+                    (false, false) => WheelDirection::Left,
+                };
+                Some((wheel_key(direction), true))
+            }
+            _ => None,
+        }
+    }
+
+    /// Hand an event to the capture thread and wait for its decision
+    fn decide(channels: &mut HookChannels, event: InputEvent) -> Option<bool> {
+        let id = channels.next_id;
+        channels.next_id += 1;
+        channels.events.send((id, event)).ok()?;
+
+        // Skip stale answers for events that previously timed out
+        let deadline = Instant::now() + DECISION_TIMEOUT;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match channels.decisions.recv_timeout(remaining) {
+                Ok((decision_id, suppress)) if decision_id == id => return Some(suppress),
+                Ok(_) => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hotstring::Hotstring;
+    use crate::output::{OutputBackend, OutputError, OutputEvent, RecordingBackend};
+    use crate::remapping::{Action, Combo, KeyBinding, KeyModifiers, TapHoldPolicy};
+
+    #[test]
+    fn capture_thread_routes_events_and_suppresses_bound_keys() {
+        let recorder = Arc::new(RecordingBackend::new());
+        let mut engine = KeyRemapping::new();
+        engine.set_output_backend(recorder.clone());
+        engine.add_binding(KeyBinding {
+            key: 0x41,
+            modifiers: KeyModifiers::default(),
+            action: Action::KeyPress(0x42),
+        }).unwrap();
+
+        let source = ScriptedSource::new(vec![
+            InputEvent::press(0x41),
+            InputEvent::release(0x41),
+            InputEvent::press(0x43),
+            InputEvent::release(0x43),
+        ]);
+        let results = source.results();
+
+        let mut capture = InputCapture::start(Box::new(source), Arc::new(Mutex::new(engine)));
+        capture.wait();
+
+        let suppressed: Vec<(u32, bool)> = results.lock().unwrap()
+            .iter()
+            .map(|(event, suppress)| (event.key, *suppress))
+            .collect();
+        assert_eq!(suppressed, vec![(0x41, true), (0x41, true), (0x43, false), (0x43, false)]);
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: 0x42, up: false },
+            OutputEvent::Key { key: 0x42, up: true },
+        ]);
+    }
+
+    /// Shared queue standing in for the OS input stream
+    type InputQueue = Arc<Mutex<VecDeque<InputEvent>>>;
+
+    /// Backend that behaves like the OS: every injected key comes back as tagged input
+    #[derive(Debug)]
+    struct LoopbackBackend {
+        queue: InputQueue,
+        sent: Mutex<Vec<OutputEvent>>,
+    }
+
+    impl OutputBackend for LoopbackBackend {
+        fn send(&self, events: &[OutputEvent]) -> Result<(), OutputError> {
+            let mut queue = self.queue.lock().unwrap();
+            for event in events {
+                let (input, scan) = match event {
+                    OutputEvent::Key { key, up } => (if *up { InputEvent::release(*key) } else { InputEvent::press(*key) }, None),
+                    OutputEvent::ScanCode { scan, up } => {
+                        let key = KeyCode::from_scan_code(*scan).map_or(0, KeyCode::vk);
+                        (if *up { InputEvent::release(key) } else { InputEvent::press(key) }, Some(*scan))
+                    },
+                    _ => continue,
+                };
+                queue.push_back(InputEvent { injected: true, scan: scan.unwrap_or(input.scan), ..input });
+            }
+            self.sent.lock().unwrap().extend_from_slice(events);
+            Ok(())
+        }
+    }
+
+    /// Source reading the shared queue, giving up after a fixed number of events
+    struct LoopbackSource {
+        queue: InputQueue,
+        /// Input typed only once everything already queued has been delivered, as a person would
+        physical: VecDeque<InputEvent>,
+        remaining: usize,
+        results: Arc<Mutex<Vec<(InputEvent, bool)>>>,
+    }
+
+    impl InputSource for LoopbackSource {
+        fn next_event(&mut self, _timeout: Duration) -> Result<Option<InputEvent>, CaptureError> {
+            if self.remaining == 0 {
+                return Err(CaptureError::Closed);
+            }
+            self.remaining -= 1;
+            let queued = self.queue.lock().unwrap().pop_front();
+            queued.or_else(|| self.physical.pop_front()).map(Some).ok_or(CaptureError::Closed)
+        }
+
+        fn complete_event(&mut self, event: &InputEvent, suppress: bool) -> Result<(), CaptureError> {
+            self.results.lock().unwrap().push((event.clone(), suppress));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn swapped_keys_do_not_feed_back_into_the_engine() {
+        let queue: InputQueue = Arc::new(Mutex::new(VecDeque::from(vec![
+            InputEvent::press(0x41),
+            InputEvent::release(0x41),
+        ])));
+        let backend = Arc::new(LoopbackBackend { queue: Arc::clone(&queue), sent: Mutex::new(Vec::new()) });
+
+        let mut engine = KeyRemapping::new();
+        engine.set_output_backend(backend.clone());
+        engine.add_binding(KeyBinding::from_chord("A", Action::KeyPress(0x42)).unwrap()).unwrap();
+        engine.add_binding(KeyBinding::from_chord("B", Action::KeyPress(0x41)).unwrap()).unwrap();
+
+        let results = Arc::new(Mutex::new(Vec::new()));
+        let source = LoopbackSource { queue, physical: VecDeque::new(), remaining: 100, results: Arc::clone(&results) };
+
+        let mut capture = InputCapture::start(Box::new(source), Arc::new(Mutex::new(engine)));
+        capture.wait();
+
+        let completed: Vec<(u32, bool, bool)> = results.lock().unwrap()
+            .iter()
+            .map(|(event, suppress)| (event.key, event.injected, *suppress))
+            .collect();
+        assert_eq!(completed, vec![
+            (0x41, false, true),
+            (0x41, false, true),
+            (0x42, true, false),
+            (0x42, true, false),
+        ]);
+        assert_eq!(*backend.sent.lock().unwrap(), vec![
+            OutputEvent::Key { key: 0x42, up: false },
+            OutputEvent::Key { key: 0x42, up: true },
+        ]);
+    }
+
+    /// Run physical events through a loopback capture, returning what the system sees:
+    /// every event not suppressed, in the order it was completed
+    fn delivered_keys(events: Vec<InputEvent>, configure: impl FnOnce(&mut KeyRemapping)) -> Vec<(u32, bool)> {
+        let queue: InputQueue = Arc::new(Mutex::new(VecDeque::new()));
+        let backend = Arc::new(LoopbackBackend { queue: Arc::clone(&queue), sent: Mutex::new(Vec::new()) });
+
+        let mut engine = KeyRemapping::new();
+        engine.set_output_backend(backend);
+        configure(&mut engine);
+
+        let results = Arc::new(Mutex::new(Vec::new()));
+        let source = LoopbackSource { queue, physical: events.into(), remaining: 100, results: Arc::clone(&results) };
+
+        let mut capture = InputCapture::start(Box::new(source), Arc::new(Mutex::new(engine)));
+        capture.wait();
+
+        let delivered = results.lock().unwrap()
+            .iter()
+            .filter(|(_, suppress)| !suppress)
+            .map(|(event, _)| (event.key, event.pressed))
+            .collect();
+        delivered
+    }
+
+    #[test]
+    fn decided_events_reach_the_system_after_the_output_they_triggered() {
+        const VK_CAPITAL: u32 = 0x14;
+        const VK_CONTROL: u32 = 0x11;
+        let events = vec![
+            InputEvent::press(VK_CAPITAL),
+            InputEvent::press(0x41),
+            InputEvent::release(0x41),
+            InputEvent::release(VK_CAPITAL),
+        ];
+
+        let delivered = delivered_keys(events, |engine| {
+            engine.add_binding(KeyBinding::from_chord("CapsLock", Action::TapHold {
+                tap: Box::new(Action::KeyPress(0x1B)),
+                hold: Box::new(Action::KeyPress(VK_CONTROL)),
+                timeout: Duration::from_secs(5),
+                policy: TapHoldPolicy::PermissiveHold,
+            }).unwrap()).unwrap();
+        });
+        assert_eq!(delivered, vec![
+            (VK_CONTROL, true),
+            (0x41, true),
+            (0x41, false),
+            (VK_CONTROL, false),
+        ]);
+    }
+
+    #[test]
+    fn keys_interrupting_a_combo_reach_the_system_after_the_replayed_presses() {
+        let events = vec![
+            InputEvent::press(0x4A),
+            InputEvent::press(0x4C),
+            InputEvent::release(0x4A),
+            InputEvent::release(0x4C),
+        ];
+
+        let delivered = delivered_keys(events, |engine| {
+            engine.add_combo(Combo {
+                keys: vec![0x4A, 0x4B],
+                action: Action::KeyPress(0x1B),
+                window: Duration::from_secs(5),
+            }).unwrap();
+        });
+        assert_eq!(delivered, vec![(0x4A, true), (0x4C, true), (0x4A, false), (0x4C, false)]);
+    }
+
+    #[test]
+    fn hotstring_terminators_reach_the_system_after_the_replacement() {
+        let [semicolon, s, space, backspace] = [KeyCode::Semicolon, KeyCode::S, KeyCode::Space, KeyCode::Backspace]
+            .map(|code| code.vk());
+        let events = vec![
+            InputEvent::press(semicolon),
+            InputEvent::release(semicolon),
+            InputEvent::press(s),
+            InputEvent::release(s),
+            InputEvent::press(space),
+            InputEvent::release(space),
+        ];
+
+        let delivered = delivered_keys(events, |engine| {
+            engine.set_hotstrings(vec![Hotstring::new(";s", "Hi")]);
+        });
+        assert_eq!(delivered, vec![
+            (semicolon, true),
+            (semicolon, false),
+            (s, true),
+            (s, false),
+            (backspace, true),
+            (backspace, false),
+            (backspace, true),
+            (backspace, false),
+            (space, true),
+            (space, false),
+        ]);
+    }
+}
@@ -0,0 +1,501 @@
+// Keyfinitum/src/command.rs
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a running command is checked for having exited
+const WAIT_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A program to run, with its arguments passed directly instead of through a shell
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct CommandSpec {
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub working_dir: Option<PathBuf>,
+    /// Variables added to the inherited environment
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Kill the command if it is still running after this long
+    #[serde(default)]
+    pub timeout: Option<Duration>,
+    /// Skip the launch while the program is already running, whether launched from here or not.
+    /// Processes are told apart by executable path, so this suits programs run directly rather
+    /// than through a shell, and only sees processes the user may inspect.
+    #[serde(default)]
+    pub single_instance: bool,
+}
+
+#[allow(dead_code)]
+impl CommandSpec {
+    pub fn new(program: &str, args: &[&str]) -> Self {
+        Self {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            ..Self::default()
+        }
+    }
+
+    /// A command line run by the platform's default shell
+    pub fn shell(command_line: &str) -> Self {
+        let (shell, flag) = default_shell();
+        Self::new(shell, &[flag, command_line])
+    }
+
+    /// The program and its arguments as one line, for logs and allowlists
+    pub fn command_line(&self) -> String {
+        std::iter::once(&self.program).chain(&self.args).cloned().collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// The shell and the flag that makes it run one command line
+pub fn default_shell() -> (&'static str, &'static str) {
+    if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    }
+}
+
+/// The canonical absolute path of the program a command runs, searching `PATH` for bare names
+pub fn resolve_program(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.is_absolute() || path.components().count() > 1 {
+        return fs::canonicalize(path).ok();
+    }
+    let extensions: Vec<String> = if cfg!(windows) {
+        let pathext = env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string());
+        std::iter::once(String::new()).chain(pathext.split(';').map(str::to_string)).collect()
+    } else {
+        vec![String::new()]
+    };
+    env::split_paths(&env::var_os("PATH")?).find_map(|dir| {
+        extensions.iter()
+            .map(|extension| dir.join(format!("{}{}", program, extension)))
+            .find(|candidate| is_executable(candidate))
+            .and_then(|candidate| fs::canonicalize(candidate).ok())
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Whether two paths name the same existing file or directory
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// A command a policy lets run. The program is compared by canonical path, never by name, so a
+/// program of the same name elsewhere on `PATH` does not match. A working directory or
+/// environment variable set by a command must be listed in the entry for it to match.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct AllowedCommand {
+    /// Absolute path of the program
+    pub program: PathBuf,
+    /// The exact arguments, or any arguments if `None`
+    #[serde(default)]
+    pub args: Option<Vec<String>>,
+    #[serde(default)]
+    pub working_dir: Option<PathBuf>,
+    /// Variables the command may set, with the values it may set them to
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+#[allow(dead_code)]
+impl AllowedCommand {
+    /// A program with any arguments, but no working directory or environment of its own
+    pub fn program(path: impl Into<PathBuf>) -> Self {
+        Self { program: path.into(), ..Self::default() }
+    }
+
+    /// Exactly this command, or `None` if its program cannot be found
+    pub fn exact(spec: &CommandSpec) -> Option<Self> {
+        Some(Self {
+            program: resolve_program(&spec.program)?,
+            args: Some(spec.args.clone()),
+            working_dir: spec.working_dir.clone(),
+            env: spec.env.clone(),
+        })
+    }
+
+    /// Whether this entry covers a command whose program resolved to `program`
+    fn matches(&self, spec: &CommandSpec, program: &Path) -> bool {
+        let program_matches = self.program.is_absolute() && same_path(&self.program, program);
+        let args_match = self.args.as_ref().is_none_or(|args| *args == spec.args);
+        let dir_matches = spec.working_dir.as_ref().is_none_or(|dir| {
+            self.working_dir.as_ref().is_some_and(|allowed| same_path(allowed, dir))
+        });
+        let env_matches = spec.env.iter().all(|(name, value)| self.env.get(name) == Some(value));
+        program_matches && args_match && dir_matches && env_matches
+    }
+}
+
+/// Which commands bindings may run
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum CommandPolicy {
+    AllowAll,
+    /// Run only the listed commands
+    Allowlist(Vec<AllowedCommand>),
+    /// Run the listed commands, and ask before running any other
+    Confirm(Vec<AllowedCommand>),
+    DenyAll,
+}
+
+impl Default for CommandPolicy {
+    fn default() -> Self {
+        CommandPolicy::Confirm(Vec::new())
+    }
+}
+
+#[allow(dead_code)]
+impl CommandPolicy {
+    /// Whether a command whose program resolved to `program` may run, or `None` to ask the user
+    fn allows(&self, spec: &CommandSpec, program: &Path) -> Option<bool> {
+        let listed = |entries: &[AllowedCommand]| entries.iter().any(|entry| entry.matches(spec, program));
+        match self {
+            CommandPolicy::AllowAll => Some(true),
+            CommandPolicy::Allowlist(entries) => Some(listed(entries)),
+            CommandPolicy::Confirm(entries) => listed(entries).then_some(true),
+            CommandPolicy::DenyAll => Some(false),
+        }
+    }
+
+    /// Add an entry to the list of an allowlist or confirm policy; other policies are unchanged
+    pub fn allow(&mut self, entry: AllowedCommand) {
+        if let CommandPolicy::Allowlist(entries) | CommandPolicy::Confirm(entries) = self {
+            if !entries.contains(&entry) {
+                entries.push(entry);
+            }
+        }
+    }
+}
+
+/// Asks the user whether a command outside the allowlist may run
+pub type Confirmation = Arc<dyn Fn(&CommandSpec) -> bool + Send + Sync>;
+
+#[derive(Debug, PartialEq)]
+pub enum CommandError {
+    NotFound(String),
+    Denied(String),
+    AlreadyRunning(String),
+    SpawnFailed(String),
+    WaitFailed(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::NotFound(program) => write!(f, "Program not found: {}", program),
+            CommandError::Denied(command) => write!(f, "Command not allowed: {}", command),
+            CommandError::AlreadyRunning(command) => write!(f, "Command already running: {}", command),
+            CommandError::SpawnFailed(msg) => write!(f, "Failed to start command: {}", msg),
+            CommandError::WaitFailed(msg) => write!(f, "Failed to wait for command: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// How a finished command ended
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandOutcome {
+    /// Exit code, or `None` if the command was killed or ended by a signal
+    pub code: Option<i32>,
+    pub stderr: String,
+    pub timed_out: bool,
+}
+
+impl CommandOutcome {
+    pub fn success(&self) -> bool {
+        self.code == Some(0) && !self.timed_out
+    }
+}
+
+/// Runs commands under a policy, each on its own thread so input handling never waits for them
+#[derive(Clone, Default)]
+pub struct CommandRunner {
+    policy: CommandPolicy,
+    confirmation: Option<Confirmation>,
+    /// Programs of single-instance launches in progress, covering the moment before they show up as processes
+    running: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl fmt::Debug for CommandRunner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommandRunner")
+            .field("policy", &self.policy)
+            .field("confirmation", &self.confirmation.is_some())
+            .finish()
+    }
+}
+
+#[allow(dead_code)]
+impl CommandRunner {
+    pub fn new(policy: CommandPolicy) -> Self {
+        Self { policy, ..Self::default() }
+    }
+
+    pub fn policy(&self) -> &CommandPolicy {
+        &self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: CommandPolicy) {
+        self.policy = policy;
+    }
+
+    /// Set how the user is asked about commands under `CommandPolicy::Confirm`; without it they are denied
+    pub fn set_confirmation(&mut self, confirmation: Confirmation) {
+        self.confirmation = Some(confirmation);
+    }
+
+    /// Run a command in the background, logging how it ends
+    pub fn spawn(&self, spec: CommandSpec) {
+        let runner = self.clone();
+        thread::spawn(move || {
+            match runner.run(&spec) {
+                Ok(outcome) if outcome.timed_out => {
+                    eprintln!("Command timed out and was killed: {}", spec.command_line());
+                },
+                Ok(outcome) if !outcome.success() => {
+                    let status = outcome.code.map_or("a signal".to_string(), |code| format!("code {}", code));
+                    eprintln!("Command exited with {}: {}", status, spec.command_line());
+                    if !outcome.stderr.trim().is_empty() {
+                        eprintln!("{}", outcome.stderr.trim_end());
+                    }
+                },
+                Ok(_) => {},
+                Err(e) => eprintln!("{}", e),
+            }
+        });
+    }
+
+    /// Run a command and wait for it to exit or time out. The program is resolved once, and the
+    /// path the policy allowed is the one run.
+    pub fn run(&self, spec: &CommandSpec) -> Result<CommandOutcome, CommandError> {
+        let program = resolve_program(&spec.program).ok_or_else(|| CommandError::NotFound(spec.program.clone()))?;
+        let allowed = self.policy.allows(spec, &program).unwrap_or_else(|| {
+            self.confirmation.as_ref().is_some_and(|confirm| confirm(spec))
+        });
+        if !allowed {
+            return Err(CommandError::Denied(spec.command_line()));
+        }
+
+        if spec.single_instance {
+            let mut running = self.running.lock().unwrap();
+            if running.contains(&program) || program_running(&program) {
+                return Err(CommandError::AlreadyRunning(spec.command_line()));
+            }
+            running.insert(program.clone());
+        }
+        let result = execute(spec, &program);
+        if spec.single_instance {
+            self.running.lock().unwrap().remove(&program);
+        }
+        result
+    }
+}
+
+/// Whether any process the user may inspect runs the program at this canonical path
+#[cfg(target_os = "linux")]
+fn program_running(program: &Path) -> bool {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return false;
+    };
+    entries.flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().bytes().all(|byte| byte.is_ascii_digit()))
+        .any(|entry| fs::read_link(entry.path().join("exe")).is_ok_and(|exe| exe == program))
+}
+
+/// Whether any process the user may inspect runs the program at this canonical path
+#[cfg(windows)]
+fn program_running(program: &Path) -> bool {
+    use std::mem;
+    use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+    use winapi::um::tlhelp32::{CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS};
+
+    let Some(file_name) = program.file_name().map(|name| name.to_string_lossy().to_lowercase()) else {
+        return false;
+    };
+    // SAFETY: the snapshot handle is checked before use and closed once, and the entry's size is set as required
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+        if snapshot == INVALID_HANDLE_VALUE {
+            return false;
+        }
+        let mut entry: PROCESSENTRY32W = mem::zeroed();
+        entry.dwSize = mem::size_of::<PROCESSENTRY32W>() as u32;
+        let mut found = false;
+        let mut more = Process32FirstW(snapshot, &mut entry) != 0;
+        while more && !found {
+            let len = entry.szExeFile.iter().position(|unit| *unit == 0).unwrap_or(entry.szExeFile.len());
+            // Only processes with the right file name are worth opening for their full path
+            if String::from_utf16_lossy(&entry.szExeFile[..len]).to_lowercase() == file_name {
+                found = process_image(entry.th32ProcessID).is_some_and(|image| same_path(&image, program));
+            }
+            more = Process32NextW(snapshot, &mut entry) != 0;
+        }
+        CloseHandle(snapshot);
+        found
+    }
+}
+
+/// Full path of a process's executable
+#[cfg(windows)]
+fn process_image(process_id: u32) -> Option<PathBuf> {
+    use std::ffi::OsString;
+    use std::os::windows::ffi::OsStringExt;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::OpenProcess;
+    use winapi::um::winbase::QueryFullProcessImageNameW;
+    use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+
+    let mut buffer = [0u16; 1024];
+    let mut len = buffer.len() as u32;
+    // SAFETY: the handle is checked before use and closed once, and the buffer length passed matches it
+    let queried = unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, process_id);
+        if process.is_null() {
+            return None;
+        }
+        let queried = QueryFullProcessImageNameW(process, 0, buffer.as_mut_ptr(), &mut len);
+        CloseHandle(process);
+        queried
+    };
+    (queried != 0).then(|| PathBuf::from(OsString::from_wide(&buffer[..len as usize])))
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn program_running(_program: &Path) -> bool {
+    false
+}
+
+fn execute(spec: &CommandSpec, program: &Path) -> Result<CommandOutcome, CommandError> {
+    let mut command = Command::new(program);
+    command.args(&spec.args)
+        .envs(&spec.env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    if let Some(dir) = &spec.working_dir {
+        command.current_dir(dir);
+    }
+    let mut child = command.spawn()
+        .map_err(|e| CommandError::SpawnFailed(format!("{}: {}", spec.command_line(), e)))?;
+
+    // Drain stderr while waiting so a chatty command cannot fill the pipe and stall
+    let stderr_reader = child.stderr.take().map(|mut stderr| thread::spawn(move || {
+        let mut output = String::new();
+        let _ = stderr.read_to_string(&mut output);
+        output
+    }));
+
+    let deadline = spec.timeout.map(|timeout| Instant::now() + timeout);
+    let mut timed_out = false;
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| CommandError::WaitFailed(e.to_string()))? {
+            break status;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            timed_out = true;
+            let _ = child.kill();
+            break child.wait().map_err(|e| CommandError::WaitFailed(e.to_string()))?;
+        }
+        thread::sleep(WAIT_INTERVAL);
+    };
+
+    // A killed shell can leave children holding the pipe open, so its stderr is not awaited
+    let stderr = match stderr_reader {
+        Some(reader) if !timed_out => reader.join().unwrap_or_default(),
+        _ => String::new(),
+    };
+    Ok(CommandOutcome { code: status.code(), stderr, timed_out })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_matches_canonical_programs_and_the_environment_they_get() {
+        let exe = std::env::current_exe().unwrap();
+        let dir = std::env::temp_dir();
+        let spec = CommandSpec::new(&exe.to_string_lossy(), &["--list"]);
+        let policy = CommandPolicy::Allowlist(vec![AllowedCommand::program(&exe)]);
+
+        assert_eq!(policy.allows(&spec, &exe), Some(true));
+        assert_eq!(CommandPolicy::Confirm(Vec::new()).allows(&spec, &exe), None);
+
+        // A program of the same name elsewhere is a different program
+        let impostor_dir = dir.join(format!("keyfinitum-impostor-{}", std::process::id()));
+        fs::create_dir_all(&impostor_dir).unwrap();
+        let impostor = impostor_dir.join(exe.file_name().unwrap());
+        fs::write(&impostor, "").unwrap();
+        let impostor = fs::canonicalize(&impostor).unwrap();
+        assert_eq!(policy.allows(&CommandSpec::new(&impostor.to_string_lossy(), &[]), &impostor), Some(false));
+        fs::remove_dir_all(&impostor_dir).unwrap();
+
+        // A working directory or variable the entry does not list is not allowed
+        let mut with_env = spec.clone();
+        with_env.env.insert("RUST_LOG".to_string(), "trace".to_string());
+        with_env.working_dir = Some(dir.clone());
+        assert_eq!(policy.allows(&with_env, &exe), Some(false));
+        let exact = CommandPolicy::Allowlist(vec![AllowedCommand::exact(&with_env).unwrap()]);
+        assert_eq!(exact.allows(&with_env, &exe), Some(true));
+        assert_eq!(exact.allows(&spec, &exe), Some(true));
+        with_env.env.insert("RUST_LOG".to_string(), "off".to_string());
+        assert_eq!(exact.allows(&with_env, &exe), Some(false));
+
+        let runner = CommandRunner::default();
+        assert_eq!(runner.run(&spec), Err(CommandError::Denied(spec.command_line())));
+        let missing = CommandSpec::new("keyfinitum-no-such-program", &[]);
+        assert_eq!(runner.run(&missing), Err(CommandError::NotFound(missing.program.clone())));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_report_exit_status_stderr_and_timeouts() {
+        let mut runner = CommandRunner::default();
+        runner.set_confirmation(Arc::new(|spec| matches!(spec.program.as_str(), "sh" | "sleep")));
+        assert_eq!(runner.run(&CommandSpec::new("true", &[])), Err(CommandError::Denied("true".to_string())));
+
+        let mut failing = CommandSpec::shell("echo \"$GREETING\" >&2; exit 3");
+        failing.env.insert("GREETING".to_string(), "oops".to_string());
+        assert_eq!(runner.run(&failing), Ok(CommandOutcome {
+            code: Some(3),
+            stderr: "oops\n".to_string(),
+            timed_out: false,
+        }));
+
+        let mut slow = CommandSpec::new("sleep", &["5"]);
+        slow.timeout = Some(Duration::from_millis(50));
+        slow.single_instance = true;
+        // A copy started elsewhere counts as running too
+        let mut elsewhere = Command::new("sleep").arg("5").spawn().unwrap();
+        assert_eq!(runner.run(&slow), Err(CommandError::AlreadyRunning(slow.command_line())));
+        elsewhere.kill().unwrap();
+        elsewhere.wait().unwrap();
+        assert!(runner.run(&slow).unwrap().timed_out);
+    }
+}
@@ -1,7 +1,10 @@
 // Keyfinitum/src/device.rs
 
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+#[cfg(windows)]
 use winapi::ctypes::c_void;
+#[cfg(windows)]
 use winapi::shared::hidpi::{HIDP_PREPARSED_DATA, HidP_GetCaps};
 
 /// Represents a connected input device
@@ -20,7 +23,7 @@ pub struct InputDevice {
 }
 
 /// Type of input device
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub enum DeviceType {
     Keyboard,
@@ -28,6 +31,48 @@ pub enum DeviceType {
     Other,
 }
 
+/// The device an input event came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceDevice {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub device_type: DeviceType,
+}
+
+/// Which devices a layer applies to; every given part must match
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct DeviceFilter {
+    #[serde(default)]
+    pub vendor_id: Option<u16>,
+    #[serde(default)]
+    pub product_id: Option<u16>,
+    #[serde(default)]
+    pub device_type: Option<DeviceType>,
+}
+
+#[allow(dead_code)]
+impl DeviceFilter {
+    /// One product, such as a particular macro pad
+    pub fn product(vendor_id: u16, product_id: u16) -> Self {
+        Self { vendor_id: Some(vendor_id), product_id: Some(product_id), device_type: None }
+    }
+
+    /// Every device of a class, such as all mice
+    pub fn device_type(device_type: DeviceType) -> Self {
+        Self { device_type: Some(device_type), ..Self::default() }
+    }
+
+    /// Whether an event from `device` matches; events from an unknown device only match an empty filter
+    pub fn matches(&self, device: Option<&SourceDevice>) -> bool {
+        let Some(device) = device else {
+            return *self == Self::default();
+        };
+        self.vendor_id.is_none_or(|id| id == device.vendor_id)
+            && self.product_id.is_none_or(|id| id == device.product_id)
+            && self.device_type.as_ref().is_none_or(|device_type| *device_type == device.device_type)
+    }
+}
+
 /// Capabilities of an input device
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
@@ -53,6 +98,7 @@ impl DeviceManager {
     }
 
     /// Detect and register connected devices
+    #[cfg(windows)]
     pub fn detect_devices(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         use std::mem::zeroed;
         use winapi::shared::hidsdi::{HidD_GetAttributes, HidD_GetPreparsedData, HidD_FreePreparsedData};
@@ -155,7 +201,14 @@ impl DeviceManager {
         Ok(())
     }
 
+    /// Detect and register connected devices
+    #[cfg(not(windows))]
+    pub fn detect_devices(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        Err("Device detection is not supported on this platform".into())
+    }
+
     /// Determine the type of device based on its capabilities
+    #[cfg(windows)]
     fn determine_device_type(&self, preparsed_data: *mut c_void) -> DeviceType {
         use winapi::shared::hidusage::{HID_USAGE_GENERIC_KEYBOARD, HID_USAGE_GENERIC_MOUSE};
         
@@ -174,6 +227,7 @@ impl DeviceManager {
     }
 
     /// Determine device capabilities based on its features
+    #[cfg(windows)]
     fn determine_capabilities(&self, preparsed_data: *mut c_void) -> DeviceCapabilities {
         unsafe {
             let mut caps: winapi::shared::hidpi::HIDP_CAPS = std::mem::zeroed();
@@ -205,6 +259,7 @@ impl DeviceManager {
     }
 
     /// Set DPI for a mouse device
+    #[cfg(windows)]
     pub fn set_dpi(&mut self, device_id: &str, new_dpi: u16) -> Result<(), Box<dyn std::error::Error>> {
         if let Some(device) = self.devices.get_mut(device_id) {
             if device.device_type != DeviceType::Mouse || !device.capabilities.has_dpi_switch {
@@ -237,7 +292,7 @@ impl DeviceManager {
                     buffer[2] = (new_dpi & 0xFF) as u8;
 
                     let success = winapi::shared::hidsdi::HidD_SetFeature(
-                        device_handle as *mut c_void,
+                        device_handle,
                         buffer.as_mut_ptr() as *mut c_void,
                         buffer.len() as u32,
                     );
@@ -259,6 +314,12 @@ impl DeviceManager {
         }
     }
 
+    /// Set DPI for a mouse device
+    #[cfg(not(windows))]
+    pub fn set_dpi(&mut self, _device_id: &str, _new_dpi: u16) -> Result<(), Box<dyn std::error::Error>> {
+        Err("DPI adjustment is not supported on this platform".into())
+    }
+
     /// Increase DPI for a mouse device
     pub fn increase_dpi(&mut self, device_id: &str) -> Result<(), Box<dyn std::error::Error>> {
         if let Some(device) = self.devices.get(device_id) {
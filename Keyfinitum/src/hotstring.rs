@@ -0,0 +1,240 @@
+// Keyfinitum/src/hotstring.rs
+
+use serde::{Deserialize, Serialize};
+
+/// How many typed characters are kept for matching abbreviations
+const BUFFER_LIMIT: usize = 64;
+
+/// Characters that end a word and trigger hotstrings that wait for a terminator
+const TERMINATORS: &str = " \t\n-()[]{}':;\"/\\,.?!";
+
+/// Text typed in place of an abbreviation, e.g. `;sig` expanding to a signature
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Hotstring {
+    pub abbreviation: String,
+    pub replacement: String,
+    #[serde(default)]
+    pub options: HotstringOptions,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HotstringOptions {
+    /// Expand as soon as the last character is typed instead of waiting for a terminator
+    #[serde(default)]
+    pub immediate: bool,
+    #[serde(default)]
+    pub case: CaseMatching,
+    /// Backspace over the typed abbreviation before typing the replacement
+    #[serde(default = "default_erase")]
+    pub erase: bool,
+    /// Also match when the abbreviation directly follows other word characters
+    #[serde(default)]
+    pub inside_word: bool,
+}
+
+fn default_erase() -> bool {
+    true
+}
+
+impl Default for HotstringOptions {
+    fn default() -> Self {
+        Self {
+            immediate: false,
+            case: CaseMatching::default(),
+            erase: true,
+            inside_word: false,
+        }
+    }
+}
+
+/// How the case of the typed abbreviation is matched and carried into the replacement
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub enum CaseMatching {
+    /// Match in any case; a capitalized or all-caps abbreviation capitalizes or uppercases the replacement
+    #[default]
+    Conform,
+    /// Match in any case and type the replacement exactly as written
+    Insensitive,
+    /// Match only the exact case of the abbreviation
+    Sensitive,
+}
+
+#[allow(dead_code)]
+impl Hotstring {
+    pub fn new(abbreviation: &str, replacement: &str) -> Self {
+        Self {
+            abbreviation: abbreviation.to_string(),
+            replacement: replacement.to_string(),
+            options: HotstringOptions::default(),
+        }
+    }
+
+    /// Whether the characters typed for the abbreviation match it
+    fn matches(&self, typed: &[char]) -> bool {
+        let expected: Vec<char> = self.abbreviation.chars().collect();
+        expected.len() == typed.len() && expected.iter().zip(typed).all(|(expected, typed)| {
+            match self.options.case {
+                CaseMatching::Sensitive => expected == typed,
+                _ => expected.to_lowercase().eq(typed.to_lowercase()),
+            }
+        })
+    }
+
+    /// The replacement, with its case following the typed abbreviation where configured
+    fn replacement_for(&self, typed: &[char]) -> String {
+        if self.options.case != CaseMatching::Conform {
+            return self.replacement.clone();
+        }
+        let letters: Vec<char> = typed.iter().copied().filter(|c| c.is_alphabetic()).collect();
+        if letters.len() > 1 && letters.iter().all(|c| c.is_uppercase()) {
+            self.replacement.to_uppercase()
+        } else if letters.first().is_some_and(|c| c.is_uppercase()) {
+            let mut chars = self.replacement.chars();
+            chars.next()
+                .map(|first| first.to_uppercase().chain(chars).collect())
+                .unwrap_or_default()
+        } else {
+            self.replacement.clone()
+        }
+    }
+}
+
+/// Edit the engine performs when a hotstring fires
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expansion {
+    /// Characters to backspace over before typing
+    pub erase: usize,
+    pub text: String,
+    /// Whether the key that completed the abbreviation is dropped instead of typed after the replacement
+    pub consume_key: bool,
+}
+
+/// Rolling buffer of recently typed characters, matched against a hotstring table
+#[derive(Debug, Clone, Default)]
+pub struct HotstringMatcher {
+    hotstrings: Vec<Hotstring>,
+    buffer: Vec<char>,
+}
+
+#[allow(dead_code)]
+impl HotstringMatcher {
+    pub fn set_hotstrings(&mut self, hotstrings: Vec<Hotstring>) {
+        self.hotstrings = hotstrings;
+        self.buffer.clear();
+    }
+
+    pub fn hotstrings(&self) -> &[Hotstring] {
+        &self.hotstrings
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hotstrings.is_empty()
+    }
+
+    /// Forget what was typed, e.g. after the cursor moved
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+    }
+
+    pub fn backspace(&mut self) {
+        self.buffer.pop();
+    }
+
+    /// Record a typed character, returning the expansion it triggers
+    pub fn type_char(&mut self, ch: char) -> Option<Expansion> {
+        if is_terminator(ch) {
+            if let Some((hotstring, typed)) = self.find_match(false) {
+                let expansion = Expansion {
+                    erase: if hotstring.options.erase { typed.len() } else { 0 },
+                    text: hotstring.replacement_for(&typed),
+                    consume_key: false,
+                };
+                self.buffer = vec![ch];
+                return Some(expansion);
+            }
+        }
+
+        self.buffer.push(ch);
+        if self.buffer.len() > BUFFER_LIMIT {
+            self.buffer.remove(0);
+        }
+
+        let (hotstring, typed) = self.find_match(true)?;
+        // The completing key is suppressed, so only the characters before it are on screen
+        let expansion = if hotstring.options.erase {
+            Expansion { erase: typed.len() - 1, text: hotstring.replacement_for(&typed), consume_key: true }
+        } else {
+            let text = std::iter::once(ch).chain(hotstring.replacement_for(&typed).chars()).collect();
+            Expansion { erase: 0, text, consume_key: true }
+        };
+        self.buffer.clear();
+        Some(expansion)
+    }
+
+    /// The longest hotstring of the given kind that the buffer ends with
+    fn find_match(&self, immediate: bool) -> Option<(&Hotstring, Vec<char>)> {
+        self.hotstrings.iter()
+            .filter(|hotstring| hotstring.options.immediate == immediate)
+            .filter_map(|hotstring| {
+                let length = hotstring.abbreviation.chars().count();
+                let start = self.buffer.len().checked_sub(length)?;
+                let typed = &self.buffer[start..];
+                let at_word_start = start == 0 || is_terminator(self.buffer[start - 1]);
+                (length > 0 && hotstring.matches(typed) && (at_word_start || hotstring.options.inside_word))
+                    .then(|| (hotstring, typed.to_vec()))
+            })
+            .max_by_key(|(_, typed)| typed.len())
+    }
+}
+
+fn is_terminator(ch: char) -> bool {
+    TERMINATORS.contains(ch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_str(matcher: &mut HotstringMatcher, text: &str) -> Vec<Expansion> {
+        text.chars().filter_map(|ch| matcher.type_char(ch)).collect()
+    }
+
+    #[test]
+    fn terminator_hotstrings_wait_for_word_end_at_word_start() {
+        let mut matcher = HotstringMatcher::default();
+        matcher.set_hotstrings(vec![Hotstring::new("btw", "by the way")]);
+
+        assert!(type_str(&mut matcher, "abtw ").is_empty());
+        assert_eq!(type_str(&mut matcher, "Btw."), vec![Expansion {
+            erase: 3,
+            text: "By the way".to_string(),
+            consume_key: false,
+        }]);
+        assert_eq!(type_str(&mut matcher, "BTW ")[0].text, "BY THE WAY");
+    }
+
+    #[test]
+    fn immediate_hotstrings_fire_on_the_last_character() {
+        let mut at = Hotstring::new("@@", "me@example.com");
+        at.options.immediate = true;
+        at.options.inside_word = true;
+        let mut sig = Hotstring::new(";sig", "Regards");
+        sig.options.immediate = true;
+        sig.options.erase = false;
+        sig.options.case = CaseMatching::Sensitive;
+        let mut matcher = HotstringMatcher::default();
+        matcher.set_hotstrings(vec![at, sig]);
+
+        assert_eq!(type_str(&mut matcher, "x@@"), vec![Expansion {
+            erase: 1,
+            text: "me@example.com".to_string(),
+            consume_key: true,
+        }]);
+        assert!(type_str(&mut matcher, " ;SIG").is_empty());
+        assert_eq!(type_str(&mut matcher, " ;sig"), vec![Expansion {
+            erase: 0,
+            text: "gRegards".to_string(),
+            consume_key: true,
+        }]);
+    }
+}
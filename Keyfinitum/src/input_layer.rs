@@ -1,12 +1,15 @@
 // Keyfinitum/src/input_layers.rs
 
-use serde::{Serialize, Deserialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use crate::remapping::Action;
 
-#[derive(Clone, Serialize, Deserialize)]
+/// A profile layer that the engine stacks while its modifier key is held
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InputLayer {
     pub name: String,
     pub modifier_key: u32,
-    pub key_mappings: Vec<(u32, u32)>, // (from_key, to_key)
+    #[serde(deserialize_with = "deserialize_mappings")]
+    pub key_mappings: Vec<(u32, Action)>, // (from_key, action)
 }
 
 #[allow(dead_code)]
@@ -19,11 +22,17 @@ impl InputLayer {
         }
     }
 
-    pub fn add_mapping(&mut self, from: u32, to: u32) {
-        self.key_mappings.push((from, to));
+    pub fn add_mapping(&mut self, from: u32, action: Action) {
+        self.remove_mapping(from);
+        self.key_mappings.push((from, action));
     }
 
-    pub fn remove_mapping(&mut self, from: u32) -> Option<u32> {
+    /// Map a key to a plain key press
+    pub fn add_key_mapping(&mut self, from: u32, to: u32) {
+        self.add_mapping(from, Action::KeyPress(to));
+    }
+
+    pub fn remove_mapping(&mut self, from: u32) -> Option<Action> {
         if let Some(index) = self.key_mappings.iter().position(|(f, _)| *f == from) {
             Some(self.key_mappings.remove(index).1)
         } else {
@@ -31,3 +40,36 @@ impl InputLayer {
         }
     }
 }
+
+/// Mapping target as stored on disk; older profiles map keys to bare key codes
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredTarget {
+    Key(u32),
+    Action(Action),
+}
+
+fn deserialize_mappings<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<(u32, Action)>, D::Error> {
+    let stored: Vec<(u32, StoredTarget)> = Vec::deserialize(deserializer)?;
+    Ok(stored
+        .into_iter()
+        .map(|(from, target)| match target {
+            StoredTarget::Key(to) => (from, Action::KeyPress(to)),
+            StoredTarget::Action(action) => (from, action),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_legacy_key_to_key_mappings() {
+        let json = r#"{"name":"Nav","modifier_key":20,"key_mappings":[[72,37],[74,{"KeyPress":40}]]}"#;
+        let layer: InputLayer = serde_json::from_str(json).unwrap();
+
+        assert!(matches!(layer.key_mappings[0], (72, Action::KeyPress(37))));
+        assert!(matches!(layer.key_mappings[1], (74, Action::KeyPress(40))));
+    }
+}
@@ -0,0 +1,506 @@
+// Keyfinitum/src/keycode.rs
+
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::remapping::{KeyModifiers, ModifierMatch};
+
+/// A USB HID usage, identified by its usage page and usage ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HidUsage {
+    pub page: u16,
+    pub id: u16,
+}
+
+/// HID keyboard/keypad page
+const fn kb(id: u16) -> HidUsage {
+    HidUsage { page: 0x07, id }
+}
+
+/// HID consumer page, used by media and application launch keys
+pub const fn consumer(id: u16) -> HidUsage {
+    HidUsage { page: 0x0C, id }
+}
+
+/// A modifier group; each has a left and a right physical key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Modifier {
+    Ctrl,
+    Shift,
+    Alt,
+    /// The Windows, Super or Command key
+    Meta,
+}
+
+impl Modifier {
+    /// All groups, in the order chords are printed
+    pub const ALL: [Modifier; 4] = [Modifier::Ctrl, Modifier::Shift, Modifier::Alt, Modifier::Meta];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Modifier::Ctrl => "Ctrl",
+            Modifier::Shift => "Shift",
+            Modifier::Alt => "Alt",
+            Modifier::Meta => "Meta",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// One row of the translation table
+#[derive(Debug)]
+struct KeyInfo {
+    code: KeyCode,
+    name: &'static str,
+    /// Windows virtual key code, or 0 if Windows has none
+    vk: u32,
+    /// Set 1 make code, with 0xE0 in the high byte for extended keys; 0 if the key has none
+    scan: u16,
+    evdev: u16,
+    hid: HidUsage,
+}
+
+/// Declares `KeyCode` and its translation table together so that each variant's
+/// discriminant is its row index in the table.
+macro_rules! key_codes {
+    ($($code:ident => $name:literal, $vk:literal, $scan:literal, $evdev:literal, $hid:expr;)*) => {
+        /// A physical key, independent of any platform's numbering
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum KeyCode {
+            $($code,)*
+        }
+
+        const KEY_TABLE: &[KeyInfo] = &[
+            $(KeyInfo { code: KeyCode::$code, name: $name, vk: $vk, scan: $scan, evdev: $evdev, hid: $hid },)*
+        ];
+    };
+}
+
+// Reverse lookups return the first matching row, so side-specific modifiers come
+// before the generic ones and the main Enter key before the keypad one.
+key_codes! {
+    A => "A", 0x41, 0x1E, 30, kb(0x04);
+    B => "B", 0x42, 0x30, 48, kb(0x05);
+    C => "C", 0x43, 0x2E, 46, kb(0x06);
+    D => "D", 0x44, 0x20, 32, kb(0x07);
+    E => "E", 0x45, 0x12, 18, kb(0x08);
+    F => "F", 0x46, 0x21, 33, kb(0x09);
+    G => "G", 0x47, 0x22, 34, kb(0x0A);
+    H => "H", 0x48, 0x23, 35, kb(0x0B);
+    I => "I", 0x49, 0x17, 23, kb(0x0C);
+    J => "J", 0x4A, 0x24, 36, kb(0x0D);
+    K => "K", 0x4B, 0x25, 37, kb(0x0E);
+    L => "L", 0x4C, 0x26, 38, kb(0x0F);
+    M => "M", 0x4D, 0x32, 50, kb(0x10);
+    N => "N", 0x4E, 0x31, 49, kb(0x11);
+    O => "O", 0x4F, 0x18, 24, kb(0x12);
+    P => "P", 0x50, 0x19, 25, kb(0x13);
+    Q => "Q", 0x51, 0x10, 16, kb(0x14);
+    R => "R", 0x52, 0x13, 19, kb(0x15);
+    S => "S", 0x53, 0x1F, 31, kb(0x16);
+    T => "T", 0x54, 0x14, 20, kb(0x17);
+    U => "U", 0x55, 0x16, 22, kb(0x18);
+    V => "V", 0x56, 0x2F, 47, kb(0x19);
+    W => "W", 0x57, 0x11, 17, kb(0x1A);
+    X => "X", 0x58, 0x2D, 45, kb(0x1B);
+    Y => "Y", 0x59, 0x15, 21, kb(0x1C);
+    Z => "Z", 0x5A, 0x2C, 44, kb(0x1D);
+    Digit1 => "1", 0x31, 0x02, 2, kb(0x1E);
+    Digit2 => "2", 0x32, 0x03, 3, kb(0x1F);
+    Digit3 => "3", 0x33, 0x04, 4, kb(0x20);
+    Digit4 => "4", 0x34, 0x05, 5, kb(0x21);
+    Digit5 => "5", 0x35, 0x06, 6, kb(0x22);
+    Digit6 => "6", 0x36, 0x07, 7, kb(0x23);
+    Digit7 => "7", 0x37, 0x08, 8, kb(0x24);
+    Digit8 => "8", 0x38, 0x09, 9, kb(0x25);
+    Digit9 => "9", 0x39, 0x0A, 10, kb(0x26);
+    Digit0 => "0", 0x30, 0x0B, 11, kb(0x27);
+    Enter => "Enter", 0x0D, 0x1C, 28, kb(0x28);
+    Escape => "Escape", 0x1B, 0x01, 1, kb(0x29);
+    Backspace => "Backspace", 0x08, 0x0E, 14, kb(0x2A);
+    Tab => "Tab", 0x09, 0x0F, 15, kb(0x2B);
+    Space => "Space", 0x20, 0x39, 57, kb(0x2C);
+    Minus => "Minus", 0xBD, 0x0C, 12, kb(0x2D);
+    Equal => "Equal", 0xBB, 0x0D, 13, kb(0x2E);
+    LeftBracket => "LeftBracket", 0xDB, 0x1A, 26, kb(0x2F);
+    RightBracket => "RightBracket", 0xDD, 0x1B, 27, kb(0x30);
+    Backslash => "Backslash", 0xDC, 0x2B, 43, kb(0x31);
+    Semicolon => "Semicolon", 0xBA, 0x27, 39, kb(0x33);
+    Quote => "Quote", 0xDE, 0x28, 40, kb(0x34);
+    Grave => "Grave", 0xC0, 0x29, 41, kb(0x35);
+    Comma => "Comma", 0xBC, 0x33, 51, kb(0x36);
+    Period => "Period", 0xBE, 0x34, 52, kb(0x37);
+    Slash => "Slash", 0xBF, 0x35, 53, kb(0x38);
+    CapsLock => "CapsLock", 0x14, 0x3A, 58, kb(0x39);
+    F1 => "F1", 0x70, 0x3B, 59, kb(0x3A);
+    F2 => "F2", 0x71, 0x3C, 60, kb(0x3B);
+    F3 => "F3", 0x72, 0x3D, 61, kb(0x3C);
+    F4 => "F4", 0x73, 0x3E, 62, kb(0x3D);
+    F5 => "F5", 0x74, 0x3F, 63, kb(0x3E);
+    F6 => "F6", 0x75, 0x40, 64, kb(0x3F);
+    F7 => "F7", 0x76, 0x41, 65, kb(0x40);
+    F8 => "F8", 0x77, 0x42, 66, kb(0x41);
+    F9 => "F9", 0x78, 0x43, 67, kb(0x42);
+    F10 => "F10", 0x79, 0x44, 68, kb(0x43);
+    F11 => "F11", 0x7A, 0x57, 87, kb(0x44);
+    F12 => "F12", 0x7B, 0x58, 88, kb(0x45);
+    F13 => "F13", 0x7C, 0x64, 183, kb(0x68);
+    F14 => "F14", 0x7D, 0x65, 184, kb(0x69);
+    F15 => "F15", 0x7E, 0x66, 185, kb(0x6A);
+    F16 => "F16", 0x7F, 0x67, 186, kb(0x6B);
+    F17 => "F17", 0x80, 0x68, 187, kb(0x6C);
+    F18 => "F18", 0x81, 0x69, 188, kb(0x6D);
+    F19 => "F19", 0x82, 0x6A, 189, kb(0x6E);
+    F20 => "F20", 0x83, 0x6B, 190, kb(0x6F);
+    F21 => "F21", 0x84, 0x6C, 191, kb(0x70);
+    F22 => "F22", 0x85, 0x6D, 192, kb(0x71);
+    F23 => "F23", 0x86, 0x6E, 193, kb(0x72);
+    F24 => "F24", 0x87, 0x76, 194, kb(0x73);
+    PrintScreen => "PrintScreen", 0x2C, 0xE037, 99, kb(0x46);
+    ScrollLock => "ScrollLock", 0x91, 0x46, 70, kb(0x47);
+    Pause => "Pause", 0x13, 0x45, 119, kb(0x48);
+    Insert => "Insert", 0x2D, 0xE052, 110, kb(0x49);
+    Home => "Home", 0x24, 0xE047, 102, kb(0x4A);
+    PageUp => "PageUp", 0x21, 0xE049, 104, kb(0x4B);
+    Delete => "Delete", 0x2E, 0xE053, 111, kb(0x4C);
+    End => "End", 0x23, 0xE04F, 107, kb(0x4D);
+    PageDown => "PageDown", 0x22, 0xE051, 109, kb(0x4E);
+    Right => "Right", 0x27, 0xE04D, 106, kb(0x4F);
+    Left => "Left", 0x25, 0xE04B, 105, kb(0x50);
+    Down => "Down", 0x28, 0xE050, 108, kb(0x51);
+    Up => "Up", 0x26, 0xE048, 103, kb(0x52);
+    NumLock => "NumLock", 0x90, 0xE045, 69, kb(0x53);
+    NumpadDivide => "NumpadDivide", 0x6F, 0xE035, 98, kb(0x54);
+    NumpadMultiply => "NumpadMultiply", 0x6A, 0x37, 55, kb(0x55);
+    NumpadSubtract => "NumpadSubtract", 0x6D, 0x4A, 74, kb(0x56);
+    NumpadAdd => "NumpadAdd", 0x6B, 0x4E, 78, kb(0x57);
+    NumpadEnter => "NumpadEnter", 0x0D, 0xE01C, 96, kb(0x58);
+    Numpad1 => "Numpad1", 0x61, 0x4F, 79, kb(0x59);
+    Numpad2 => "Numpad2", 0x62, 0x50, 80, kb(0x5A);
+    Numpad3 => "Numpad3", 0x63, 0x51, 81, kb(0x5B);
+    Numpad4 => "Numpad4", 0x64, 0x4B, 75, kb(0x5C);
+    Numpad5 => "Numpad5", 0x65, 0x4C, 76, kb(0x5D);
+    Numpad6 => "Numpad6", 0x66, 0x4D, 77, kb(0x5E);
+    Numpad7 => "Numpad7", 0x67, 0x47, 71, kb(0x5F);
+    Numpad8 => "Numpad8", 0x68, 0x48, 72, kb(0x60);
+    Numpad9 => "Numpad9", 0x69, 0x49, 73, kb(0x61);
+    Numpad0 => "Numpad0", 0x60, 0x52, 82, kb(0x62);
+    NumpadDecimal => "NumpadDecimal", 0x6E, 0x53, 83, kb(0x63);
+    IntlBackslash => "IntlBackslash", 0xE2, 0x56, 86, kb(0x64);
+    Menu => "Menu", 0x5D, 0xE05D, 127, kb(0x65);
+    LeftCtrl => "LeftCtrl", 0xA2, 0x1D, 29, kb(0xE0);
+    LeftShift => "LeftShift", 0xA0, 0x2A, 42, kb(0xE1);
+    LeftAlt => "LeftAlt", 0xA4, 0x38, 56, kb(0xE2);
+    LeftMeta => "LeftMeta", 0x5B, 0xE05B, 125, kb(0xE3);
+    RightCtrl => "RightCtrl", 0xA3, 0xE01D, 97, kb(0xE4);
+    RightShift => "RightShift", 0xA1, 0x36, 54, kb(0xE5);
+    RightAlt => "RightAlt", 0xA5, 0xE038, 100, kb(0xE6);
+    RightMeta => "RightMeta", 0x5C, 0xE05C, 126, kb(0xE7);
+    Ctrl => "Ctrl", 0x11, 0x1D, 29, kb(0xE0);
+    Shift => "Shift", 0x10, 0x2A, 42, kb(0xE1);
+    Alt => "Alt", 0x12, 0x38, 56, kb(0xE2);
+    VolumeMute => "VolumeMute", 0xAD, 0xE020, 113, consumer(0xE2);
+    VolumeDown => "VolumeDown", 0xAE, 0xE02E, 114, consumer(0xEA);
+    VolumeUp => "VolumeUp", 0xAF, 0xE030, 115, consumer(0xE9);
+    MediaNextTrack => "MediaNextTrack", 0xB0, 0xE019, 163, consumer(0xB5);
+    MediaPrevTrack => "MediaPrevTrack", 0xB1, 0xE010, 165, consumer(0xB6);
+    MediaStop => "MediaStop", 0xB2, 0xE024, 166, consumer(0xB7);
+    MediaPlayPause => "MediaPlayPause", 0xB3, 0xE022, 164, consumer(0xCD);
+    LaunchMail => "LaunchMail", 0xB4, 0xE06C, 155, consumer(0x18A);
+    LaunchMediaSelect => "LaunchMediaSelect", 0xB5, 0xE06D, 226, consumer(0x183);
+    LaunchApp1 => "LaunchApp1", 0xB6, 0xE06B, 157, consumer(0x194);
+    LaunchApp2 => "LaunchApp2", 0xB7, 0xE021, 140, consumer(0x192);
+    BrowserBack => "BrowserBack", 0xA6, 0xE06A, 158, consumer(0x224);
+    BrowserForward => "BrowserForward", 0xA7, 0xE069, 159, consumer(0x225);
+    BrowserRefresh => "BrowserRefresh", 0xA8, 0xE067, 173, consumer(0x227);
+    BrowserStop => "BrowserStop", 0xA9, 0xE068, 128, consumer(0x226);
+    BrowserSearch => "BrowserSearch", 0xAA, 0xE065, 217, consumer(0x221);
+    BrowserFavorites => "BrowserFavorites", 0xAB, 0xE066, 156, consumer(0x22A);
+    BrowserHome => "BrowserHome", 0xAC, 0xE032, 172, consumer(0x223);
+    // Consumer keys without a Windows virtual key or scan code
+    MediaPlay => "MediaPlay", 0, 0, 200, consumer(0xB0);
+    MediaPause => "MediaPause", 0, 0, 201, consumer(0xB1);
+    MediaRecord => "MediaRecord", 0, 0, 167, consumer(0xB2);
+    MediaFastForward => "MediaFastForward", 0, 0, 208, consumer(0xB3);
+    MediaRewind => "MediaRewind", 0, 0, 168, consumer(0xB4);
+    Eject => "Eject", 0, 0, 161, consumer(0xB8);
+    BrightnessUp => "BrightnessUp", 0, 0, 225, consumer(0x6F);
+    BrightnessDown => "BrightnessDown", 0, 0, 224, consumer(0x70);
+}
+
+/// Alternative spellings accepted when parsing, compared case-insensitively
+const ALIASES: &[(&str, KeyCode)] = &[
+    ("Esc", KeyCode::Escape),
+    ("Return", KeyCode::Enter),
+    ("Del", KeyCode::Delete),
+    ("Ins", KeyCode::Insert),
+    ("PgUp", KeyCode::PageUp),
+    ("PgDn", KeyCode::PageDown),
+    ("PrtSc", KeyCode::PrintScreen),
+    ("Control", KeyCode::Ctrl),
+    ("LeftControl", KeyCode::LeftCtrl),
+    ("RightControl", KeyCode::RightCtrl),
+    ("Win", KeyCode::LeftMeta),
+    ("LeftWin", KeyCode::LeftMeta),
+    ("RightWin", KeyCode::RightMeta),
+    ("LeftSuper", KeyCode::LeftMeta),
+    ("RightSuper", KeyCode::RightMeta),
+    ("AltGr", KeyCode::RightAlt),
+    ("Apps", KeyCode::Menu),
+    ("-", KeyCode::Minus),
+    ("=", KeyCode::Equal),
+    ("[", KeyCode::LeftBracket),
+    ("]", KeyCode::RightBracket),
+    ("\\", KeyCode::Backslash),
+    (";", KeyCode::Semicolon),
+    ("'", KeyCode::Quote),
+    ("`", KeyCode::Grave),
+    (",", KeyCode::Comma),
+    (".", KeyCode::Period),
+    ("/", KeyCode::Slash),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyParseError {
+    UnknownKey(String),
+    InvalidChord(String),
+}
+
+impl fmt::Display for KeyParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyParseError::UnknownKey(name) => write!(f, "Unknown key name '{}'", name),
+            KeyParseError::InvalidChord(chord) => write!(f, "Invalid key chord '{}'", chord),
+        }
+    }
+}
+
+impl std::error::Error for KeyParseError {}
+
+#[allow(dead_code)]
+impl KeyCode {
+    const fn info(self) -> &'static KeyInfo {
+        &KEY_TABLE[self as usize]
+    }
+
+    /// Every known key, in table order
+    pub fn all() -> impl Iterator<Item = KeyCode> {
+        KEY_TABLE.iter().map(|info| info.code)
+    }
+
+    /// Canonical name, e.g. "LeftCtrl" or "F13"
+    pub const fn name(self) -> &'static str {
+        self.info().name
+    }
+
+    /// Look up a key by canonical name or alias, ignoring case
+    pub fn from_name(name: &str) -> Option<KeyCode> {
+        KEY_TABLE.iter()
+            .find(|info| info.name.eq_ignore_ascii_case(name))
+            .map(|info| info.code)
+            .or_else(|| ALIASES.iter().find(|(alias, _)| alias.eq_ignore_ascii_case(name)).map(|(_, code)| *code))
+    }
+
+    /// The modifier group of a modifier key, with its side unless the code is side-neutral
+    pub fn modifier(self) -> Option<(Modifier, Option<Side>)> {
+        match self {
+            KeyCode::LeftCtrl => Some((Modifier::Ctrl, Some(Side::Left))),
+            KeyCode::RightCtrl => Some((Modifier::Ctrl, Some(Side::Right))),
+            KeyCode::Ctrl => Some((Modifier::Ctrl, None)),
+            KeyCode::LeftShift => Some((Modifier::Shift, Some(Side::Left))),
+            KeyCode::RightShift => Some((Modifier::Shift, Some(Side::Right))),
+            KeyCode::Shift => Some((Modifier::Shift, None)),
+            KeyCode::LeftAlt => Some((Modifier::Alt, Some(Side::Left))),
+            KeyCode::RightAlt => Some((Modifier::Alt, Some(Side::Right))),
+            KeyCode::Alt => Some((Modifier::Alt, None)),
+            KeyCode::LeftMeta => Some((Modifier::Meta, Some(Side::Left))),
+            KeyCode::RightMeta => Some((Modifier::Meta, Some(Side::Right))),
+            _ => None,
+        }
+    }
+
+    /// Windows virtual key code
+    pub const fn vk(self) -> u32 {
+        self.info().vk
+    }
+
+    pub fn from_vk(vk: u32) -> Option<KeyCode> {
+        KEY_TABLE.iter().find(|info| info.vk == vk && vk != 0).map(|info| info.code)
+    }
+
+    /// Set 1 scan code, with 0xE0 in the high byte for extended keys
+    pub const fn scan_code(self) -> u16 {
+        self.info().scan
+    }
+
+    pub fn from_scan_code(scan: u16) -> Option<KeyCode> {
+        KEY_TABLE.iter().find(|info| info.scan == scan && scan != 0).map(|info| info.code)
+    }
+
+    /// Linux evdev key code
+    pub const fn evdev(self) -> u16 {
+        self.info().evdev
+    }
+
+    pub fn from_evdev(code: u16) -> Option<KeyCode> {
+        KEY_TABLE.iter().find(|info| info.evdev == code).map(|info| info.code)
+    }
+
+    /// USB HID usage
+    pub const fn hid(self) -> HidUsage {
+        self.info().hid
+    }
+
+    pub fn from_hid(usage: HidUsage) -> Option<KeyCode> {
+        KEY_TABLE.iter().find(|info| info.hid == usage).map(|info| info.code)
+    }
+}
+
+impl fmt::Display for KeyCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for KeyCode {
+    type Err = KeyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        KeyCode::from_name(s.trim()).ok_or_else(|| KeyParseError::UnknownKey(s.to_string()))
+    }
+}
+
+impl Serialize for KeyCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A key together with the modifiers held for it, written like "Ctrl+Shift+K".
+/// "Ctrl" accepts either side while "LeftCtrl" or "RightCtrl" require that key,
+/// "Super" and "Win" are spellings of Meta, and "Hyper" stands for all four groups.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyChord {
+    pub modifiers: KeyModifiers,
+    pub key: KeyCode,
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if Modifier::ALL.iter().all(|m| self.modifiers.get(*m) == ModifierMatch::Either) {
+            return write!(f, "Hyper+{}", self.key.name());
+        }
+        for modifier in Modifier::ALL {
+            match self.modifiers.get(modifier) {
+                ModifierMatch::Off => {}
+                ModifierMatch::Either => write!(f, "{}+", modifier.name())?,
+                ModifierMatch::Left => write!(f, "Left{}+", modifier.name())?,
+                ModifierMatch::Right => write!(f, "Right{}+", modifier.name())?,
+            }
+        }
+        f.write_str(self.key.name())
+    }
+}
+
+impl FromStr for KeyChord {
+    type Err = KeyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let Some((key, modifier_names)) = parts.split_last() else {
+            return Err(KeyParseError::InvalidChord(s.to_string()));
+        };
+        if key.is_empty() {
+            return Err(KeyParseError::InvalidChord(s.to_string()));
+        }
+
+        let mut modifiers = KeyModifiers::default();
+        for name in modifier_names {
+            let matches: Vec<(Modifier, ModifierMatch)> = if name.eq_ignore_ascii_case("Hyper") {
+                Modifier::ALL.iter().map(|m| (*m, ModifierMatch::Either)).collect()
+            } else if ["Meta", "Super", "Win", "Cmd"].iter().any(|n| n.eq_ignore_ascii_case(name)) {
+                vec![(Modifier::Meta, ModifierMatch::Either)]
+            } else {
+                let Some((modifier, side)) = KeyCode::from_name(name).and_then(KeyCode::modifier) else {
+                    return Err(KeyParseError::InvalidChord(s.to_string()));
+                };
+                let side = match side {
+                    None => ModifierMatch::Either,
+                    Some(Side::Left) => ModifierMatch::Left,
+                    Some(Side::Right) => ModifierMatch::Right,
+                };
+                vec![(modifier, side)]
+            };
+
+            for (modifier, side) in matches {
+                modifiers.set(modifier, side);
+            }
+        }
+
+        Ok(KeyChord { modifiers, key: key.parse()? })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_rows_match_variant_order() {
+        for (index, info) in KEY_TABLE.iter().enumerate() {
+            assert_eq!(info.code as usize, index, "{} is out of order", info.name);
+        }
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for code in KeyCode::all() {
+            assert_eq!(KeyCode::from_name(code.name()), Some(code));
+        }
+        assert_eq!("esc".parse::<KeyCode>(), Ok(KeyCode::Escape));
+        assert_eq!("MediaPlayPause".parse::<KeyCode>(), Ok(KeyCode::MediaPlayPause));
+        assert!("Hyperdrive".parse::<KeyCode>().is_err());
+    }
+
+    #[test]
+    fn translation_tables_prefer_sided_modifiers() {
+        assert_eq!(KeyCode::from_evdev(29), Some(KeyCode::LeftCtrl));
+        assert_eq!(KeyCode::from_vk(0x11), Some(KeyCode::Ctrl));
+        assert_eq!(KeyCode::from_vk(0x0D), Some(KeyCode::Enter));
+        assert_eq!(KeyCode::from_scan_code(0xE01C), Some(KeyCode::NumpadEnter));
+        assert_eq!(KeyCode::from_hid(consumer(0xCD)), Some(KeyCode::MediaPlayPause));
+        assert_eq!(KeyCode::F13.evdev(), 183);
+        assert_eq!(KeyCode::from_vk(0), None);
+        assert_eq!(KeyCode::from_scan_code(0), None);
+    }
+
+    #[test]
+    fn chords_parse_and_print() {
+        let chord: KeyChord = "shift + ctrl + k".parse().unwrap();
+        assert_eq!(chord.key, KeyCode::K);
+        assert_eq!(chord.modifiers.ctrl, ModifierMatch::Either);
+        assert_eq!(chord.modifiers.shift, ModifierMatch::Either);
+        assert_eq!(chord.modifiers.alt, ModifierMatch::Off);
+        assert_eq!(chord.to_string(), "Ctrl+Shift+K");
+
+        assert_eq!("AltGr+Super+E".parse::<KeyChord>().unwrap().to_string(), "RightAlt+Meta+E");
+        assert_eq!("hyper+h".parse::<KeyChord>().unwrap().to_string(), "Hyper+H");
+        assert_eq!("LeftShift+Win+1".parse::<KeyChord>().unwrap().to_string(), "LeftShift+Meta+1");
+
+        assert_eq!("Ctrl".parse::<KeyChord>().unwrap().key, KeyCode::Ctrl);
+        assert!("Ctrl+".parse::<KeyChord>().is_err());
+        assert!("A+K".parse::<KeyChord>().is_err());
+    }
+}
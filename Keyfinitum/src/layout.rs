@@ -0,0 +1,254 @@
+// Keyfinitum/src/layout.rs
+
+use crate::keycode::KeyCode;
+
+/// Keys whose character is the same on every layout, but which layouts report as control codes
+const CONTROL_KEYS: [(KeyCode, char); 3] = [
+    (KeyCode::Enter, '\n'),
+    (KeyCode::NumpadEnter, '\n'),
+    (KeyCode::Tab, '\t'),
+];
+
+/// A key and the character it types without and with Shift
+type KeyChars = (KeyCode, Option<char>, Option<char>);
+
+/// The characters a keyboard layout types, used to follow what is typed and to type text with plain keys
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyboardLayout {
+    /// In key table order
+    keys: Vec<KeyChars>,
+    /// Whether this is the built-in US layout standing in for a system layout that could not be read
+    fallback: bool,
+}
+
+/// The US layout, standing in for a system layout that has not been or could not be read
+impl Default for KeyboardLayout {
+    fn default() -> Self {
+        Self { fallback: true, ..Self::us() }
+    }
+}
+
+#[allow(dead_code)]
+impl KeyboardLayout {
+    pub fn us() -> Self {
+        let keys = KeyCode::all()
+            .filter_map(|key| us_chars(key).map(|(plain, shifted)| (key, Some(plain), Some(shifted))))
+            .collect();
+        Self { keys, fallback: false }
+    }
+
+    /// The layout of the focused window on Windows or the X11 keymap on Linux, or the US layout
+    /// if neither can be read. Only the first layout group is read, so AltGr characters are unknown.
+    pub fn current() -> Self {
+        match system_layout() {
+            Some(keys) => {
+                let control = CONTROL_KEYS.iter().map(|(key, ch)| (*key, Some(*ch), Some(*ch)));
+                let keys = control.chain(keys.into_iter().filter(|(key, _, _)| {
+                    !CONTROL_KEYS.iter().any(|(control, _)| control == key)
+                })).collect();
+                Self { keys, fallback: false }
+            },
+            None => Self::default(),
+        }
+    }
+
+    pub fn is_fallback(&self) -> bool {
+        self.fallback
+    }
+
+    /// The character a key types, if it types exactly one. Caps Lock shifts letters only.
+    pub fn typed_char(&self, key: KeyCode, shift: bool, caps_lock: bool) -> Option<char> {
+        let (_, plain, shifted) = self.keys.iter().find(|(code, _, _)| *code == key)?;
+        let letter = plain.is_some_and(char::is_lowercase);
+        if shift != (caps_lock && letter) { *shifted } else { *plain }
+    }
+
+    /// The key that types a character and whether it needs Shift, preferring unshifted keys
+    pub fn key_for(&self, ch: char) -> Option<(KeyCode, bool)> {
+        let find = |shift: bool| self.keys.iter().find_map(|(key, plain, shifted)| {
+            (if shift { *shifted } else { *plain } == Some(ch)).then_some(*key)
+        });
+        find(false).map(|key| (key, false)).or_else(|| find(true).map(|key| (key, true)))
+    }
+}
+
+/// What a key types on a US layout, without and with Shift
+fn us_chars(key: KeyCode) -> Option<(char, char)> {
+    let chars = match key {
+        KeyCode::Digit1 => ('1', '!'),
+        KeyCode::Digit2 => ('2', '@'),
+        KeyCode::Digit3 => ('3', '#'),
+        KeyCode::Digit4 => ('4', '$'),
+        KeyCode::Digit5 => ('5', '%'),
+        KeyCode::Digit6 => ('6', '^'),
+        KeyCode::Digit7 => ('7', '&'),
+        KeyCode::Digit8 => ('8', '*'),
+        KeyCode::Digit9 => ('9', '('),
+        KeyCode::Digit0 => ('0', ')'),
+        KeyCode::Enter | KeyCode::NumpadEnter => ('\n', '\n'),
+        KeyCode::Tab => ('\t', '\t'),
+        KeyCode::Space => (' ', ' '),
+        KeyCode::Minus => ('-', '_'),
+        KeyCode::Equal => ('=', '+'),
+        KeyCode::LeftBracket => ('[', '{'),
+        KeyCode::RightBracket => (']', '}'),
+        KeyCode::Backslash => ('\\', '|'),
+        KeyCode::Semicolon => (';', ':'),
+        KeyCode::Quote => ('\'', '"'),
+        KeyCode::Grave => ('`', '~'),
+        KeyCode::Comma => (',', '<'),
+        KeyCode::Period => ('.', '>'),
+        KeyCode::Slash => ('/', '?'),
+        KeyCode::NumpadDivide => ('/', '/'),
+        KeyCode::NumpadMultiply => ('*', '*'),
+        KeyCode::NumpadSubtract => ('-', '-'),
+        KeyCode::NumpadAdd => ('+', '+'),
+        _ => {
+            let name = key.name();
+            let letter = name.chars().next().filter(|c| name.len() == 1 && c.is_ascii_alphabetic())?;
+            (letter.to_ascii_lowercase(), letter)
+        }
+    };
+    Some(chars)
+}
+
+/// Whether Caps Lock is on, if the system says
+#[cfg(windows)]
+pub fn caps_lock_on() -> Option<bool> {
+    use winapi::um::winuser::{GetKeyState, VK_CAPITAL};
+
+    // SAFETY: GetKeyState takes and returns plain values
+    Some(unsafe { GetKeyState(VK_CAPITAL) } & 1 != 0)
+}
+
+/// Whether Caps Lock is on, if the system says
+#[cfg(target_os = "linux")]
+pub fn caps_lock_on() -> Option<bool> {
+    use x11rb::protocol::xproto::ConnectionExt;
+
+    let (connection, _) = x11rb::connect(None).ok()?;
+    let control = connection.get_keyboard_control().ok()?.reply().ok()?;
+    // The Caps Lock LED is LED 1, the lowest bit of the mask
+    Some(control.led_mask & 1 != 0)
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn caps_lock_on() -> Option<bool> {
+    None
+}
+
+/// Ask the keyboard layout of the focused window's thread what each key types
+#[cfg(windows)]
+fn system_layout() -> Option<Vec<KeyChars>> {
+    use std::ptr;
+    use winapi::um::winuser::{
+        GetForegroundWindow, GetKeyboardLayout, GetWindowThreadProcessId, MapVirtualKeyExW, ToUnicodeEx,
+        MAPVK_VK_TO_VSC, VK_SHIFT,
+    };
+    /// Leave the layout's dead key state alone (Windows 10 1607 and later)
+    const KEEP_KEYBOARD_STATE: u32 = 0x4;
+
+    // SAFETY: a null window yields thread 0, which stands for the calling thread
+    let layout = unsafe { GetKeyboardLayout(GetWindowThreadProcessId(GetForegroundWindow(), ptr::null_mut())) };
+    if layout.is_null() {
+        return None;
+    }
+    let typed = |key: KeyCode, shift: bool| {
+        let mut state = [0u8; 256];
+        if shift {
+            state[VK_SHIFT as usize] = 0x80;
+        }
+        let mut buffer = [0u16; 8];
+        // SAFETY: the state array has the 256 entries required and the buffer length passed matches it
+        let len = unsafe {
+            let scan = MapVirtualKeyExW(key.vk(), MAPVK_VK_TO_VSC, layout);
+            ToUnicodeEx(key.vk(), scan, state.as_ptr(), buffer.as_mut_ptr(), buffer.len() as i32, KEEP_KEYBOARD_STATE, layout)
+        };
+        // Dead keys report a negative length and ligatures more than one unit
+        (len == 1).then(|| char::from_u32(buffer[0] as u32)).flatten().filter(|ch| !ch.is_control())
+    };
+    Some(KeyCode::all().filter(|key| key.vk() != 0).map(|key| (key, typed(key, false), typed(key, true))).collect())
+}
+
+/// Read the X11 server's keymap, which XWayland keeps in step with the compositor's
+#[cfg(target_os = "linux")]
+fn system_layout() -> Option<Vec<KeyChars>> {
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::ConnectionExt;
+
+    let (connection, _) = x11rb::connect(None).ok()?;
+    let (min, max) = (connection.setup().min_keycode, connection.setup().max_keycode);
+    let mapping = connection.get_keyboard_mapping(min, max - min + 1).ok()?.reply().ok()?;
+    let per_key = mapping.keysyms_per_keycode as usize;
+    if per_key == 0 {
+        return None;
+    }
+
+    let keys = KeyCode::all().filter_map(|key| {
+        // X keycodes are evdev codes offset by 8
+        let keycode = key.evdev() as usize + 8;
+        if key.evdev() == 0 || keycode < min as usize || keycode > max as usize {
+            return None;
+        }
+        let keysyms = &mapping.keysyms[(keycode - min as usize) * per_key..][..per_key];
+        let plain = keysym_char(keysyms[0]);
+        // A key without a shifted keysym of its own types the upper case of its plain one
+        let shifted = match keysyms.get(1).copied().filter(|keysym| *keysym != 0) {
+            Some(keysym) => keysym_char(keysym),
+            None => plain.map(|ch| ch.to_uppercase().next().unwrap_or(ch)),
+        };
+        Some((key, plain, shifted))
+    }).collect();
+    Some(keys)
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn system_layout() -> Option<Vec<KeyChars>> {
+    None
+}
+
+/// The character an X keysym types, for Latin-1, Unicode and keypad operator keysyms
+#[cfg(target_os = "linux")]
+fn keysym_char(keysym: u32) -> Option<char> {
+    match keysym {
+        0x20..=0x7E | 0xA0..=0xFF => char::from_u32(keysym),
+        0x0100_0100..=0x0110_FFFF => char::from_u32(keysym - 0x0100_0000),
+        0xFFAA => Some('*'),
+        0xFFAB => Some('+'),
+        0xFFAD => Some('-'),
+        0xFFAF => Some('/'),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn us_layout_types_characters_and_finds_their_keys() {
+        let layout = KeyboardLayout::us();
+
+        assert!(!layout.is_fallback());
+        assert!(KeyboardLayout::default().is_fallback());
+        assert_eq!(layout.typed_char(KeyCode::A, false, false), Some('a'));
+        assert_eq!(layout.typed_char(KeyCode::Digit2, true, false), Some('@'));
+        assert_eq!(layout.typed_char(KeyCode::F1, false, false), None);
+        // Caps Lock shifts letters, and Shift undoes it, but digits and symbols ignore it
+        assert_eq!(layout.typed_char(KeyCode::A, false, true), Some('A'));
+        assert_eq!(layout.typed_char(KeyCode::A, true, true), Some('a'));
+        assert_eq!(layout.typed_char(KeyCode::Digit2, false, true), Some('2'));
+        assert_eq!(layout.key_for('/'), Some((KeyCode::Slash, false)));
+        assert_eq!(layout.key_for('Q'), Some((KeyCode::Q, true)));
+        assert_eq!(layout.key_for('é'), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn keysyms_convert_to_the_characters_they_type() {
+        assert_eq!(keysym_char(0x61), Some('a'));
+        assert_eq!(keysym_char(0xE9), Some('é'));
+        assert_eq!(keysym_char(0x0100_20AC), Some('€'));
+        assert_eq!(keysym_char(0xFE52), None);
+    }
+}
@@ -0,0 +1,643 @@
+// Keyfinitum/src/linux_input.rs
+
+use std::collections::VecDeque;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use evdev::uinput::{VirtualDevice, VirtualDeviceBuilder};
+use evdev::{AttributeSet, Device, EventType, InputEvent, Key, RelativeAxisType};
+use crate::capture::{CaptureError, InputEvent as CaptureEvent, InputSource};
+use crate::device::{DeviceType, SourceDevice};
+use crate::keycode::KeyCode;
+use crate::layout::{caps_lock_on, KeyboardLayout};
+use crate::output::{consumer_key, text_control_key, OutputBackend, OutputError, OutputEvent};
+use crate::remapping::{mouse_button_key, wheel_key, MouseButton, WheelDirection};
+
+/// Name of the uinput device Keyfinitum injects through. It is never grabbed for
+/// capture, so injected events cannot re-enter the engine.
+pub const VIRTUAL_DEVICE_NAME: &str = "Keyfinitum Virtual Input";
+
+/// Translate a Windows virtual key code to an evdev key code
+pub fn vk_to_evdev(vk: u32) -> Option<u16> {
+    KeyCode::from_vk(vk).map(KeyCode::evdev)
+}
+
+/// Translate an evdev key code to a Windows virtual key code.
+/// Physical modifiers are always reported with their side.
+#[allow(dead_code)]
+pub fn evdev_to_vk(code: u16) -> Option<u32> {
+    KeyCode::from_evdev(code).map(KeyCode::vk)
+}
+
+/// Highest button number with an evdev code in the mouse button range
+const MAX_MOUSE_BUTTON: u8 = 16;
+
+fn mouse_button_code(button: &MouseButton) -> Option<Key> {
+    match button {
+        MouseButton::Left => Some(Key::BTN_LEFT),
+        MouseButton::Right => Some(Key::BTN_RIGHT),
+        MouseButton::Middle => Some(Key::BTN_MIDDLE),
+        MouseButton::Back => Some(Key::BTN_SIDE),
+        MouseButton::Forward => Some(Key::BTN_EXTRA),
+        // Buttons 6 and up follow on from BTN_EXTRA: BTN_FORWARD, BTN_BACK, BTN_TASK, ...
+        MouseButton::Extra(number @ 6..=MAX_MOUSE_BUTTON) => Some(Key::new(Key::BTN_LEFT.code() + *number as u16 - 1)),
+        MouseButton::Extra(_) => None,
+    }
+}
+
+fn mouse_button_from_code(code: u16) -> Option<MouseButton> {
+    let offset = code.checked_sub(Key::BTN_LEFT.code())?;
+    if offset < MAX_MOUSE_BUTTON as u16 {
+        MouseButton::from_number(offset as u8 + 1)
+    } else {
+        None
+    }
+}
+
+/// The shared uinput device, created on first use
+fn virtual_device() -> Result<&'static Mutex<VirtualDevice>, OutputError> {
+    static DEVICE: OnceLock<Result<Mutex<VirtualDevice>, String>> = OnceLock::new();
+
+    DEVICE
+        .get_or_init(|| create_virtual_device().map(Mutex::new).map_err(|e| e.to_string()))
+        .as_ref()
+        .map_err(|e| OutputError::SendFailed(format!("Cannot open /dev/uinput: {}", e)))
+}
+
+fn create_virtual_device() -> io::Result<VirtualDevice> {
+    let mut keys = AttributeSet::<Key>::new();
+    // Every regular keyboard key, so unmapped keys can be passed through unchanged
+    for code in 1..=255 {
+        keys.insert(Key::new(code));
+    }
+    for number in 1..=MAX_MOUSE_BUTTON {
+        if let Some(button) = MouseButton::from_number(number).as_ref().and_then(mouse_button_code) {
+            keys.insert(button);
+        }
+    }
+
+    let mut axes = AttributeSet::<RelativeAxisType>::new();
+    for axis in [
+        RelativeAxisType::REL_X,
+        RelativeAxisType::REL_Y,
+        RelativeAxisType::REL_WHEEL,
+        RelativeAxisType::REL_HWHEEL,
+        RelativeAxisType::REL_WHEEL_HI_RES,
+        RelativeAxisType::REL_HWHEEL_HI_RES,
+    ] {
+        axes.insert(axis);
+    }
+
+    VirtualDeviceBuilder::new()?
+        .name(VIRTUAL_DEVICE_NAME)
+        .with_keys(&keys)?
+        .with_relative_axes(&axes)?
+        .build()
+}
+
+/// Press and release a key as its own report, so consumers see every keystroke
+fn push_tap(raw: &mut Vec<InputEvent>, code: u16) {
+    raw.push(InputEvent::new(EventType::KEY, code, 1));
+    raw.push(InputEvent::new(EventType::SYNCHRONIZATION, 0, 0));
+    raw.push(InputEvent::new(EventType::KEY, code, 0));
+    raw.push(InputEvent::new(EventType::SYNCHRONIZATION, 0, 0));
+}
+
+/// Tap a key, holding Shift around it if asked to
+fn push_shifted_tap(raw: &mut Vec<InputEvent>, key: KeyCode, shift: bool) {
+    let shift_code = KeyCode::LeftShift.evdev();
+    if shift {
+        raw.push(InputEvent::new(EventType::KEY, shift_code, 1));
+    }
+    push_tap(raw, key.evdev());
+    if shift {
+        raw.push(InputEvent::new(EventType::KEY, shift_code, 0));
+        raw.push(InputEvent::new(EventType::SYNCHRONIZATION, 0, 0));
+    }
+}
+
+/// Type text as key presses. Characters the layout has a key for are typed with that key, which
+/// works in every application but, like any key press, is affected by held modifiers and Caps Lock,
+/// so `push_text_plainly` lifts those first. Other characters, and all of them while the layout is
+/// unknown, go through `push_unicode_char`.
+fn push_text(raw: &mut Vec<InputEvent>, layout: &KeyboardLayout, text: &str) {
+    for ch in text.chars().filter(|ch| *ch != '\r') {
+        if let Some(code) = text_control_key(ch).and_then(vk_to_evdev) {
+            push_tap(raw, code);
+            continue;
+        }
+        match layout.key_for(ch).filter(|_| !layout.is_fallback()) {
+            Some((key, shift)) => push_shifted_tap(raw, key, shift),
+            None => push_unicode_char(raw, layout, ch),
+        }
+    }
+}
+
+/// Type one character through the Ctrl+Shift+U code point entry. Only GTK applications and IBus
+/// understand it; elsewhere the hex digits are typed as they are. The U and the digits are typed
+/// through the layout, falling back to their US keys, so layouts like AZERTY enter the right digits.
+fn push_unicode_char(raw: &mut Vec<InputEvent>, layout: &KeyboardLayout, ch: char) {
+    let ctrl = KeyCode::LeftCtrl.evdev();
+    let shift = KeyCode::LeftShift.evdev();
+    let u = layout.key_for('u').map_or(KeyCode::U, |(key, _)| key);
+    raw.push(InputEvent::new(EventType::KEY, ctrl, 1));
+    raw.push(InputEvent::new(EventType::KEY, shift, 1));
+    push_tap(raw, u.evdev());
+    raw.push(InputEvent::new(EventType::KEY, shift, 0));
+    raw.push(InputEvent::new(EventType::KEY, ctrl, 0));
+    raw.push(InputEvent::new(EventType::SYNCHRONIZATION, 0, 0));
+
+    for digit in format!("{:x}", ch as u32).chars() {
+        let key = layout.key_for(digit).or_else(|| KeyboardLayout::us().key_for(digit));
+        if let Some((key, shift)) = key {
+            push_shifted_tap(raw, key, shift);
+        }
+    }
+    push_tap(raw, KeyCode::Space.evdev());
+}
+
+/// Modifiers held down and the Caps Lock state on the virtual device. Captured devices are grabbed,
+/// so every key the system sees goes through the virtual device and this is what the system sees too.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct VirtualKeys {
+    /// Evdev codes of the held modifier keys, in press order
+    modifiers: Vec<u16>,
+    caps_lock: bool,
+}
+
+impl VirtualKeys {
+    /// Update the state with raw events about to be emitted
+    fn follow(&mut self, raw: &[InputEvent]) {
+        for event in raw.iter().filter(|event| event.event_type() == EventType::KEY) {
+            let code = event.code();
+            match event.value() {
+                1 if code == KeyCode::CapsLock.evdev() => self.caps_lock = !self.caps_lock,
+                1 if KeyCode::from_evdev(code).and_then(KeyCode::modifier).is_some() && !self.modifiers.contains(&code) => {
+                    self.modifiers.push(code);
+                }
+                0 => self.modifiers.retain(|held| *held != code),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The virtual device's key state, starting from the system's Caps Lock state
+fn virtual_keys() -> &'static Mutex<VirtualKeys> {
+    static KEYS: OnceLock<Mutex<VirtualKeys>> = OnceLock::new();
+
+    KEYS.get_or_init(|| Mutex::new(VirtualKeys { caps_lock: caps_lock_on().unwrap_or(false), ..VirtualKeys::default() }))
+}
+
+/// Type text with the held modifiers released and Caps Lock off, then put both back as they were
+fn push_text_plainly(raw: &mut Vec<InputEvent>, keys: &VirtualKeys, layout: &KeyboardLayout, text: &str) {
+    for code in &keys.modifiers {
+        raw.push(InputEvent::new(EventType::KEY, *code, 0));
+    }
+    raw.push(InputEvent::new(EventType::SYNCHRONIZATION, 0, 0));
+    if keys.caps_lock {
+        push_tap(raw, KeyCode::CapsLock.evdev());
+    }
+
+    push_text(raw, layout, text);
+
+    if keys.caps_lock {
+        push_tap(raw, KeyCode::CapsLock.evdev());
+    }
+    for code in &keys.modifiers {
+        raw.push(InputEvent::new(EventType::KEY, *code, 1));
+    }
+    raw.push(InputEvent::new(EventType::SYNCHRONIZATION, 0, 0));
+}
+
+/// Emit raw evdev events through the shared virtual device
+fn emit_raw(events: &[InputEvent]) -> Result<(), OutputError> {
+    virtual_device()?
+        .lock()
+        .unwrap()
+        .emit(events)
+        .map_err(|e| OutputError::SendFailed(e.to_string()))
+}
+
+/// Backend that injects events through a uinput virtual device
+#[derive(Debug, Default)]
+pub struct UinputBackend {
+    /// High-resolution wheel movement not yet reported as a whole notch, horizontal then vertical
+    wheel_remainder: Mutex<(i32, i32)>,
+    /// Layout that text is typed with, read when the backend is created and then kept current by the engine
+    layout: Mutex<KeyboardLayout>,
+}
+
+impl UinputBackend {
+    pub fn new() -> Self {
+        Self { layout: Mutex::new(KeyboardLayout::current()), ..Self::default() }
+    }
+}
+
+/// Add high-resolution wheel movement to a remainder and take out its whole notches
+fn take_notches(remainder: &mut i32, delta: i32) -> i32 {
+    *remainder += delta;
+    let notches = *remainder / 120;
+    *remainder -= notches * 120;
+    notches
+}
+
+impl OutputBackend for UinputBackend {
+    fn send(&self, events: &[OutputEvent]) -> Result<(), OutputError> {
+        let mut raw = Vec::with_capacity(events.len());
+        // Held for the whole batch, so batches sent from other threads cannot interleave with its state
+        let mut state = virtual_keys().lock().unwrap();
+        let mut keys = state.clone();
+        let mut followed = 0;
+
+        for event in events {
+            match event {
+                OutputEvent::Key { key, up } => {
+                    let code = vk_to_evdev(*key).ok_or_else(|| {
+                        OutputError::SendFailed(format!("No evdev code for virtual key {:#04x}", key))
+                    })?;
+                    raw.push(InputEvent::new(EventType::KEY, code, if *up { 0 } else { 1 }));
+                }
+                OutputEvent::ScanCode { scan, up } => {
+                    let code = KeyCode::from_scan_code(*scan).map(KeyCode::evdev).ok_or_else(|| {
+                        OutputError::SendFailed(format!("No evdev code for scan code {:#06x}", scan))
+                    })?;
+                    raw.push(InputEvent::new(EventType::KEY, code, if *up { 0 } else { 1 }));
+                }
+                OutputEvent::MouseButton { button, up } => {
+                    let code = mouse_button_code(button).ok_or_else(|| {
+                        OutputError::SendFailed(format!("No evdev code for mouse button {}", button.number()))
+                    })?.code();
+                    raw.push(InputEvent::new(EventType::KEY, code, if *up { 0 } else { 1 }));
+                }
+                OutputEvent::MouseMove { dx, dy } => {
+                    raw.push(InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_X.0, *dx));
+                    raw.push(InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_Y.0, *dy));
+                }
+                OutputEvent::Consumer { usage, up } => {
+                    let code = consumer_key(*usage).map(KeyCode::evdev).ok_or_else(|| {
+                        OutputError::SendFailed(format!("No evdev code for consumer usage {:#05x}", usage))
+                    })?;
+                    raw.push(InputEvent::new(EventType::KEY, code, if *up { 0 } else { 1 }));
+                    raw.push(InputEvent::new(EventType::SYNCHRONIZATION, 0, 0));
+                }
+                OutputEvent::Text { text } => {
+                    keys.follow(&raw[followed..]);
+                    push_text_plainly(&mut raw, &keys, &self.layout.lock().unwrap(), text);
+                    followed = raw.len();
+                }
+                OutputEvent::MouseWheel { delta } => {
+                    // Clients without high-resolution support only see whole notches
+                    let notches = take_notches(&mut self.wheel_remainder.lock().unwrap().1, *delta);
+                    raw.push(InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_WHEEL_HI_RES.0, *delta));
+                    if notches != 0 {
+                        raw.push(InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_WHEEL.0, notches));
+                    }
+                }
+                OutputEvent::MouseHWheel { delta } => {
+                    let notches = take_notches(&mut self.wheel_remainder.lock().unwrap().0, *delta);
+                    raw.push(InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_HWHEEL_HI_RES.0, *delta));
+                    if notches != 0 {
+                        raw.push(InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_HWHEEL.0, notches));
+                    }
+                }
+            }
+        }
+
+        if raw.is_empty() {
+            return Ok(());
+        }
+        emit_raw(&raw)?;
+        keys.follow(&raw[followed..]);
+        *state = keys;
+        Ok(())
+    }
+
+    fn set_keyboard_layout(&self, layout: &KeyboardLayout) {
+        *self.layout.lock().unwrap() = layout.clone();
+    }
+
+    fn supports_consumer(&self, usage: u16) -> bool {
+        consumer_key(usage).is_some()
+    }
+}
+
+/// Whether a device looks like a keyboard or mouse worth grabbing
+fn is_capture_candidate(device: &Device) -> bool {
+    if device.name() == Some(VIRTUAL_DEVICE_NAME) {
+        return false;
+    }
+    let Some(keys) = device.supported_keys() else {
+        return false;
+    };
+    let is_keyboard = keys.contains(Key::KEY_A) && keys.contains(Key::KEY_Z);
+    let is_mouse = keys.contains(Key::BTN_LEFT)
+        && device
+            .supported_relative_axes()
+            .is_some_and(|axes| axes.contains(RelativeAxisType::REL_X));
+    is_keyboard || is_mouse
+}
+
+/// Identity of a grabbed device, attached to every event it produces
+fn source_device(device: &Device) -> SourceDevice {
+    let id = device.input_id();
+    let is_keyboard = device.supported_keys().is_some_and(|keys| keys.contains(Key::KEY_A));
+    SourceDevice {
+        vendor_id: id.vendor(),
+        product_id: id.product(),
+        device_type: if is_keyboard { DeviceType::Keyboard } else { DeviceType::Mouse },
+    }
+}
+
+/// Input source that grabs physical keyboards and mice through evdev.
+/// Keys, mouse buttons and wheel notches are delivered to the engine; everything
+/// else, and every event the engine does not suppress, is passed through the virtual device.
+pub struct EvdevSource {
+    devices: Vec<(Device, SourceDevice)>,
+    queued: VecDeque<(CaptureEvent, Vec<InputEvent>)>,
+    pending: Vec<InputEvent>,
+    current: Vec<InputEvent>,
+}
+
+impl EvdevSource {
+    /// Grab all keyboards and mice
+    pub fn open() -> Result<Self, CaptureError> {
+        // Open the virtual device before grabbing so pass-through works immediately
+        virtual_device().map_err(|e| CaptureError::Platform(e.to_string()))?;
+
+        let mut devices = Vec::new();
+        for (path, mut device) in evdev::enumerate() {
+            if !is_capture_candidate(&device) {
+                continue;
+            }
+            match device.grab() {
+                Ok(()) => {
+                    let source = source_device(&device);
+                    devices.push((device, source));
+                },
+                Err(e) => eprintln!("Failed to grab {}: {}", path.display(), e),
+            }
+        }
+
+        if devices.is_empty() {
+            return Err(CaptureError::Platform("No keyboards or mice could be grabbed".to_string()));
+        }
+
+        Ok(Self {
+            devices,
+            queued: VecDeque::new(),
+            pending: Vec::new(),
+            current: Vec::new(),
+        })
+    }
+
+    /// Wait for readable devices and read their events into the queue
+    fn read_devices(&mut self, timeout: Duration) -> Result<(), CaptureError> {
+        if self.devices.is_empty() {
+            return Err(CaptureError::Closed);
+        }
+
+        let mut fds: Vec<libc::pollfd> = self.devices
+            .iter()
+            .map(|(device, _)| libc::pollfd { fd: device.as_raw_fd(), events: libc::POLLIN, revents: 0 })
+            .collect();
+
+        let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout.as_millis() as i32) };
+        if ready <= 0 {
+            return Ok(());
+        }
+
+        let mut disconnected = Vec::new();
+        for (index, fd) in fds.iter().enumerate() {
+            if fd.revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) != 0 {
+                disconnected.push(index);
+                continue;
+            }
+            if fd.revents & libc::POLLIN == 0 {
+                continue;
+            }
+
+            let (device, source) = &mut self.devices[index];
+            let source = source.clone();
+            let events: Vec<InputEvent> = match device.fetch_events() {
+                Ok(events) => events.collect(),
+                Err(e) => {
+                    eprintln!("Failed to read input device: {}", e);
+                    disconnected.push(index);
+                    continue;
+                }
+            };
+
+            for event in events {
+                self.sort_event(event, &source);
+            }
+        }
+
+        for index in disconnected.into_iter().rev() {
+            self.devices.remove(index);
+        }
+        Ok(())
+    }
+
+    /// Queue keys, mouse buttons and wheel ticks for the engine;
+    /// buffer everything else for pass-through at the next SYN_REPORT
+    fn sort_event(&mut self, event: InputEvent, source: &SourceDevice) {
+        match event.event_type() {
+            EventType::KEY => {
+                let key = match KeyCode::from_evdev(event.code()).filter(|code| code.vk() != 0) {
+                    Some(code) => Some((code.vk(), code.scan_code())),
+                    None => mouse_button_from_code(event.code()).map(|button| (mouse_button_key(&button), 0)),
+                };
+                if let Some((key, scan)) = key {
+                    // Value 2 is auto-repeat, which is handled as another press
+                    let captured = CaptureEvent {
+                        key,
+                        scan,
+                        pressed: event.value() != 0,
+                        time: Instant::now(),
+                        injected: false,
+                        device: Some(source.clone()),
+                    };
+                    self.queued.push_back((captured, vec![event]));
+                } else {
+                    self.pending.push(event);
+                }
+            }
+            EventType::RELATIVE if event.value() != 0 && is_notch_axis(event.code()) => {
+                let vertical = event.code() == RelativeAxisType::REL_WHEEL.0;
+                let direction = match (vertical, event.value() > 0) {
+                    (true, true) => WheelDirection::Up,
+                    (true, false) => WheelDirection::Down,
+                    (false, true) => WheelDirection::Right,
+                    (false, false) => WheelDirection::Left,
+                };
+                // The high-resolution report of the same notch is suppressed or passed along with it
+                let hi_res = if vertical { RelativeAxisType::REL_WHEEL_HI_RES } else { RelativeAxisType::REL_HWHEEL_HI_RES };
+                let mut raw: Vec<InputEvent> = self.pending.iter()
+                    .filter(|pending| pending.event_type() == EventType::RELATIVE && pending.code() == hi_res.0)
+                    .copied()
+                    .collect();
+                self.pending.retain(|pending| pending.event_type() != EventType::RELATIVE || pending.code() != hi_res.0);
+                raw.push(event);
+
+                let captured = CaptureEvent {
+                    key: wheel_key(direction),
+                    scan: 0,
+                    pressed: true,
+                    time: Instant::now(),
+                    injected: false,
+                    device: Some(source.clone()),
+                };
+                self.queued.push_back((captured, raw));
+            }
+            EventType::SYNCHRONIZATION => {
+                if !self.pending.is_empty() {
+                    if let Err(e) = emit_raw(&self.pending) {
+                        eprintln!("Failed to pass through input: {}", e);
+                    }
+                    self.pending.clear();
+                }
+            }
+            // Scan code reports are regenerated by the kernel for the virtual device
+            EventType::MISC => {}
+            _ => self.pending.push(event),
+        }
+    }
+}
+
+/// Whether a relative axis reports whole wheel notches
+fn is_notch_axis(code: u16) -> bool {
+    code == RelativeAxisType::REL_WHEEL.0 || code == RelativeAxisType::REL_HWHEEL.0
+}
+
+impl InputSource for EvdevSource {
+    fn next_event(&mut self, timeout: Duration) -> Result<Option<CaptureEvent>, CaptureError> {
+        if self.queued.is_empty() {
+            self.read_devices(timeout)?;
+        }
+        Ok(self.queued.pop_front().map(|(captured, raw)| {
+            self.current = raw;
+            captured
+        }))
+    }
+
+    fn complete_event(&mut self, _event: &CaptureEvent, suppress: bool) -> Result<(), CaptureError> {
+        let raw = std::mem::take(&mut self.current);
+        if !suppress && !raw.is_empty() {
+            emit_raw(&raw).map_err(|e| CaptureError::Platform(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for EvdevSource {
+    fn drop(&mut self) {
+        for (device, _) in &mut self.devices {
+            let _ = device.ungrab();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_table_round_trips_primary_codes() {
+        assert_eq!(vk_to_evdev(0x41), Some(30));
+        assert_eq!(evdev_to_vk(30), Some(0x41));
+        assert_eq!(evdev_to_vk(54), Some(0xA1));
+        assert_eq!(vk_to_evdev(0x10), Some(42));
+        assert_eq!(vk_to_evdev(0x87), Some(194));
+    }
+
+    #[test]
+    fn partial_wheel_movement_adds_up_to_notches() {
+        let mut remainder = 0;
+        assert_eq!(take_notches(&mut remainder, 60), 0);
+        assert_eq!(take_notches(&mut remainder, 90), 1);
+        assert_eq!(remainder, 30);
+        assert_eq!(take_notches(&mut remainder, -300), -2);
+        assert_eq!(remainder, -30);
+    }
+
+    #[test]
+    fn mouse_buttons_round_trip_through_evdev_codes() {
+        for number in 1..=MAX_MOUSE_BUTTON {
+            let button = MouseButton::from_number(number).unwrap();
+            let code = mouse_button_code(&button).unwrap().code();
+            assert_eq!(mouse_button_from_code(code), Some(button));
+        }
+        assert_eq!(mouse_button_code(&MouseButton::Back), Some(Key::BTN_SIDE));
+        assert_eq!(mouse_button_code(&MouseButton::Extra(6)), Some(Key::BTN_FORWARD));
+        assert_eq!(mouse_button_code(&MouseButton::Extra(17)), None);
+    }
+
+    fn pressed_keys(raw: &[InputEvent]) -> Vec<KeyCode> {
+        raw.iter()
+            .filter(|event| event.event_type() == EventType::KEY && event.value() == 1)
+            .filter_map(|event| KeyCode::from_evdev(event.code()))
+            .collect()
+    }
+
+    #[test]
+    fn unicode_chars_are_typed_as_hex_code_points() {
+        let mut raw = Vec::new();
+        push_unicode_char(&mut raw, &KeyboardLayout::default(), 'é');
+
+        assert_eq!(pressed_keys(&raw), vec![
+            KeyCode::LeftCtrl,
+            KeyCode::LeftShift,
+            KeyCode::U,
+            KeyCode::E,
+            KeyCode::Digit9,
+            KeyCode::Space,
+        ]);
+    }
+
+    #[test]
+    fn text_is_typed_with_held_modifiers_and_caps_lock_lifted() {
+        let shift = KeyCode::LeftShift.evdev();
+        let mut keys = VirtualKeys::default();
+        keys.follow(&[InputEvent::new(EventType::KEY, shift, 1), InputEvent::new(EventType::KEY, KeyCode::A.evdev(), 1)]);
+        assert_eq!(keys.modifiers, vec![shift]);
+
+        let mut raw = Vec::new();
+        push_text_plainly(&mut raw, &keys, &KeyboardLayout::us(), "a");
+        let key_events: Vec<(u16, i32)> = raw.iter()
+            .filter(|event| event.event_type() == EventType::KEY)
+            .map(|event| (event.code(), event.value()))
+            .collect();
+        let a = KeyCode::A.evdev();
+        assert_eq!(key_events, vec![(shift, 0), (a, 1), (a, 0), (shift, 1)]);
+
+        // Caps Lock is toggled off around the text and back on after it
+        keys.follow(&[InputEvent::new(EventType::KEY, KeyCode::CapsLock.evdev(), 1)]);
+        let mut raw = Vec::new();
+        push_text_plainly(&mut raw, &keys, &KeyboardLayout::us(), "a");
+        assert_eq!(pressed_keys(&raw), vec![KeyCode::CapsLock, KeyCode::A, KeyCode::CapsLock, KeyCode::LeftShift]);
+        let mut after = keys.clone();
+        after.follow(&raw);
+        assert_eq!(after, keys);
+    }
+
+    #[test]
+    fn text_the_layout_can_type_uses_plain_keys() {
+        let mut raw = Vec::new();
+        push_text(&mut raw, &KeyboardLayout::us(), "a!\n");
+        assert_eq!(pressed_keys(&raw), vec![KeyCode::A, KeyCode::LeftShift, KeyCode::Digit1, KeyCode::Enter]);
+
+        // An unknown layout cannot be trusted to put the characters where the US layout has them
+        let mut raw = Vec::new();
+        push_text(&mut raw, &KeyboardLayout::default(), "a");
+        assert_eq!(pressed_keys(&raw), vec![
+            KeyCode::LeftCtrl,
+            KeyCode::LeftShift,
+            KeyCode::U,
+            KeyCode::Digit6,
+            KeyCode::Digit1,
+            KeyCode::Space,
+        ]);
+    }
+}
@@ -1,88 +1,39 @@
 // Keyfinitum/src/macro.rs
 
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use std::thread;
-use winapi::um::winuser::{
-    SendInput, INPUT, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_KEYUP,
-    INPUT_MOUSE, MOUSEINPUT, MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP,
-    GetAsyncKeyState, VK_LBUTTON, VK_RBUTTON,
-    VK_SPACE, VK_RETURN, VK_SHIFT, VK_CONTROL, VK_MENU, VK_ESCAPE
-};
 use serde::{Serialize, Deserialize};
+use crate::keycode::{KeyCode, Modifier};
+use crate::output::{self, OutputBackend, OutputError, OutputEvent};
+use crate::remapping::MouseButton;
+use crate::scroll::Scroll;
 
-// Define virtual key codes for letters and numbers
-#[allow(dead_code)]
-const VK_A: i32 = 0x41;
-#[allow(dead_code)]
-const VK_B: i32 = 0x42;
-#[allow(dead_code)]
-const VK_C: i32 = 0x43;
-#[allow(dead_code)]
-const VK_D: i32 = 0x44;
-#[allow(dead_code)]
-const VK_E: i32 = 0x45;
-#[allow(dead_code)]
-const VK_F: i32 = 0x46;
-#[allow(dead_code)]
-const VK_G: i32 = 0x47;
-#[allow(dead_code)]
-const VK_H: i32 = 0x48;
-#[allow(dead_code)]
-const VK_I: i32 = 0x49;
-#[allow(dead_code)]
-const VK_J: i32 = 0x4A;
-#[allow(dead_code)]
-const VK_K: i32 = 0x4B;
-#[allow(dead_code)]
-const VK_L: i32 = 0x4C;
-#[allow(dead_code)]
-const VK_M: i32 = 0x4D;
-#[allow(dead_code)]
-const VK_N: i32 = 0x4E;
-#[allow(dead_code)]
-const VK_O: i32 = 0x4F;
-#[allow(dead_code)]
-const VK_P: i32 = 0x50;
-#[allow(dead_code)]
-const VK_Q: i32 = 0x51;
-#[allow(dead_code)]
-const VK_R: i32 = 0x52;
-#[allow(dead_code)]
-const VK_S: i32 = 0x53;
-#[allow(dead_code)]
-const VK_T: i32 = 0x54;
-#[allow(dead_code)]
-const VK_U: i32 = 0x55;
-#[allow(dead_code)]
-const VK_V: i32 = 0x56;
-#[allow(dead_code)]
-const VK_W: i32 = 0x57;
-#[allow(dead_code)]
-const VK_X: i32 = 0x58;
-#[allow(dead_code)]
-const VK_Y: i32 = 0x59;
-#[allow(dead_code)]
-const VK_Z: i32 = 0x5A;
-#[allow(dead_code)]
-const VK_0: i32 = 0x30;
-#[allow(dead_code)]
-const VK_1: i32 = 0x31;
-#[allow(dead_code)]
-const VK_2: i32 = 0x32;
-#[allow(dead_code)]
-const VK_3: i32 = 0x33;
-#[allow(dead_code)]
-const VK_4: i32 = 0x34;
-#[allow(dead_code)]
-const VK_5: i32 = 0x35;
-#[allow(dead_code)]
-const VK_6: i32 = 0x36;
-#[allow(dead_code)]
-const VK_7: i32 = 0x37;
-#[allow(dead_code)]
-const VK_8: i32 = 0x38;
-#[allow(dead_code)]
-const VK_9: i32 = 0x39;
+// Virtual key codes for the mouse buttons and modifiers
+const VK_LBUTTON: i32 = 0x01;
+const VK_RBUTTON: i32 = 0x02;
+const VK_MBUTTON: i32 = 0x04;
+const VK_XBUTTON1: i32 = 0x05;
+const VK_XBUTTON2: i32 = 0x06;
+const VK_SHIFT: i32 = KeyCode::Shift.vk() as i32;
+const VK_CONTROL: i32 = KeyCode::Ctrl.vk() as i32;
+const VK_MENU: i32 = KeyCode::Alt.vk() as i32;
+const VK_LWIN: i32 = KeyCode::LeftMeta.vk() as i32;
+const VK_RWIN: i32 = KeyCode::RightMeta.vk() as i32;
+const MODIFIER_KEYS: [i32; 5] = [VK_SHIFT, VK_CONTROL, VK_MENU, VK_LWIN, VK_RWIN];
+
+/// Check whether a key or mouse button is currently held down
+#[cfg(windows)]
+fn is_key_down(key: i32) -> bool {
+    unsafe { winapi::um::winuser::GetAsyncKeyState(key) as u16 & 0x8000 != 0 }
+}
+
+/// Check whether a key or mouse button is currently held down
+#[cfg(not(windows))]
+fn is_key_down(_key: i32) -> bool {
+    false
+}
 
 /// Records user inputs and converts them into MacroActions
 #[allow(dead_code)]
@@ -154,70 +105,70 @@ impl MacroRecorder {
         // Record mouse clicks
         self.record_mouse_click(VK_LBUTTON, 0);
         self.record_mouse_click(VK_RBUTTON, 1);
+        self.record_mouse_click(VK_MBUTTON, 2);
+        self.record_mouse_click(VK_XBUTTON1, 3);
+        self.record_mouse_click(VK_XBUTTON2, 4);
 
-        // Record keyboard inputs
-        for key in [
-            VK_A, VK_B, VK_C, VK_D, VK_E, VK_F, VK_G, VK_H, VK_I, VK_J, VK_K, VK_L, VK_M,
-            VK_N, VK_O, VK_P, VK_Q, VK_R, VK_S, VK_T, VK_U, VK_V, VK_W, VK_X, VK_Y, VK_Z,
-            VK_0, VK_1, VK_2, VK_3, VK_4, VK_5, VK_6, VK_7, VK_8, VK_9,
-            VK_SPACE, VK_RETURN, VK_SHIFT, VK_CONTROL, VK_MENU, VK_ESCAPE
-        ].iter() {
-            self.record_key_press(*key);
+        // Record keyboard inputs; sided Shift, Ctrl and Alt are covered by their generic codes
+        let covered = |key: &KeyCode| matches!(
+            key.modifier(),
+            Some((Modifier::Shift | Modifier::Ctrl | Modifier::Alt, Some(_)))
+        );
+        for key in KeyCode::all().filter(|key| !covered(key) && key.vk() != 0) {
+            self.record_key_press(key.vk() as i32);
         }
     }
 
     fn record_mouse_click(&mut self, button: i32, button_id: u32) {
-        unsafe {
-            let state = GetAsyncKeyState(button);
-            if state & 0x8000u16 as i16 != 0 {
-                // Button is pressed
-                if !self.actions.iter().any(|a| matches!(a, MacroAction::MousePress(btn) if *btn == button_id)) {
-                    self.actions.push(MacroAction::MousePress(button_id));
-                }
-            } else {
-                // Button is released
-                if self.actions.iter().any(|a| matches!(a, MacroAction::MousePress(btn) if *btn == button_id)) {
-                    self.actions.push(MacroAction::MouseRelease(button_id));
-                }
+        if is_key_down(button) {
+            // Button is pressed
+            if !self.actions.iter().any(|a| matches!(a, MacroAction::MousePress(btn) if *btn == button_id)) {
+                self.actions.push(MacroAction::MousePress(button_id));
+            }
+        } else {
+            // Button is released
+            if self.actions.iter().any(|a| matches!(a, MacroAction::MousePress(btn) if *btn == button_id)) {
+                self.actions.push(MacroAction::MouseRelease(button_id));
             }
         }
     }
 
     fn record_key_press(&mut self, key: i32) {
-        unsafe {
-            let state = GetAsyncKeyState(key);
-            if state & 0x8000u16 as i16 != 0 {
-                // Key is pressed
-                if !self.actions.iter().any(|a| matches!(a, MacroAction::KeyPress(k) if *k == key as u32)) {
-                    // Update modifier states
-                    match key {
-                        VK_SHIFT => self.modifier_states.shift = true,
-                        VK_CONTROL => self.modifier_states.control = true,
-                        VK_MENU => self.modifier_states.alt = true,
-                        _ => {}
-                    }
-                    
-                    // Record the key press with modifier states
-                    self.actions.push(MacroAction::KeyPress(key as u32));
-                    
-                    // If this is a regular key (not modifier), record the current modifier combination
-                    if ![VK_SHIFT, VK_CONTROL, VK_MENU].contains(&key) {
-                        self.record_modifier_combination();
-                    }
+        if is_key_down(key) {
+            // Key is pressed
+            if !self.actions.iter().any(|a| matches!(a, MacroAction::KeyPress(k) if *k == key as u32)) {
+                // Update modifier states
+                match key {
+                    VK_SHIFT => self.modifier_states.shift = true,
+                    VK_CONTROL => self.modifier_states.control = true,
+                    VK_MENU => self.modifier_states.alt = true,
+                    VK_LWIN | VK_RWIN => self.modifier_states.windows = true,
+                    _ => {}
+                }
+                
+                // Record the key press with modifier states
+                self.actions.push(MacroAction::KeyPress(key as u32));
+                
+                // If this is a regular key (not modifier), record the current modifier combination
+                if !MODIFIER_KEYS.contains(&key) {
+                    self.record_modifier_combination();
                 }
-            } else {
-                // Key is released
-                if self.actions.iter().any(|a| matches!(a, MacroAction::KeyPress(k) if *k == key as u32)) {
-                    // Update modifier states
-                    match key {
-                        VK_SHIFT => self.modifier_states.shift = false,
-                        VK_CONTROL => self.modifier_states.control = false,
-                        VK_MENU => self.modifier_states.alt = false,
-                        _ => {}
+            }
+        } else {
+            // Key is released
+            if self.actions.iter().any(|a| matches!(a, MacroAction::KeyPress(k) if *k == key as u32)) {
+                // Update modifier states
+                match key {
+                    VK_SHIFT => self.modifier_states.shift = false,
+                    VK_CONTROL => self.modifier_states.control = false,
+                    VK_MENU => self.modifier_states.alt = false,
+                    VK_LWIN | VK_RWIN => {
+                        self.modifier_states.windows = is_key_down(VK_LWIN) || is_key_down(VK_RWIN);
                     }
-                    
-                    self.actions.push(MacroAction::KeyRelease(key as u32));
+                    _ => {}
                 }
+                
+                self.actions.push(MacroAction::KeyRelease(key as u32));
             }
         }
     }
@@ -233,6 +184,9 @@ impl MacroRecorder {
         if self.modifier_states.alt {
             modifiers.push(VK_MENU as u32);
         }
+        if self.modifier_states.windows {
+            modifiers.push(VK_LWIN as u32);
+        }
         
         if !modifiers.is_empty() {
             for modifier in modifiers {
@@ -252,6 +206,9 @@ pub enum MacroAction {
     MousePress(u32),
     MouseRelease(u32),
     Delay(Duration),
+    /// Type Unicode text, independent of the keyboard layout
+    TypeText(String),
+    Scroll(Scroll),
 }
 
 /// Represents a complete macro sequence
@@ -263,6 +220,7 @@ pub struct Macro {
 
 impl Macro {
     /// Create a new empty macro
+    #[allow(dead_code)]
     pub fn new(name: &str) -> Self {
         Macro {
             name: name.to_string(),
@@ -271,111 +229,125 @@ impl Macro {
     }
 
     /// Add an action to the macro
+    #[allow(dead_code)]
     pub fn add_action(&mut self, action: MacroAction) {
         self.actions.push(action);
     }
 
-    /// Execute the macro
+    /// Execute the macro using the platform's default output backend
     pub fn execute(&self) {
+        if let Err(e) = self.execute_with(output::default_backend().as_ref()) {
+            eprintln!("Failed to execute macro '{}': {}", self.name, e);
+        }
+    }
+
+    /// Execute the macro, emitting every event through the given backend
+    pub fn execute_with(&self, backend: &dyn OutputBackend) -> Result<(), OutputError> {
         for action in &self.actions {
             match action {
-                MacroAction::KeyPress(key) => self.send_key_event(*key, false),
-                MacroAction::KeyRelease(key) => self.send_key_event(*key, true),
-                MacroAction::MousePress(button) => self.send_mouse_event(*button, false),
-                MacroAction::MouseRelease(button) => self.send_mouse_event(*button, true),
+                MacroAction::KeyPress(key) => self.send_key_event(backend, *key, false)?,
+                MacroAction::KeyRelease(key) => self.send_key_event(backend, *key, true)?,
+                MacroAction::MousePress(button) => self.send_mouse_event(backend, *button, false)?,
+                MacroAction::MouseRelease(button) => self.send_mouse_event(backend, *button, true)?,
                 MacroAction::Delay(duration) => thread::sleep(*duration),
+                MacroAction::TypeText(text) => backend.send(&[OutputEvent::Text { text: text.clone() }])?,
+                MacroAction::Scroll(scroll) => {
+                    for (delay, events) in scroll.steps() {
+                        thread::sleep(delay);
+                        backend.send(&events)?;
+                    }
+                }
             }
         }
+        Ok(())
     }
 
-    fn send_key_event(&self, key: u32, key_up: bool) {
-        unsafe {
-            // Create an array to hold all inputs (main key + modifiers)
-            let mut inputs = Vec::new();
-            
-            // Check if this is a modifier key
-            let is_modifier = match key as i32 {
-                VK_SHIFT | VK_CONTROL | VK_MENU => true,
-                _ => false,
-            };
-            
-            // If this is a regular key press (not modifier), send modifier keys first
-            if !key_up && !is_modifier {
-                // Check which modifiers are active
-                let mut modifiers = Vec::new();
-                if GetAsyncKeyState(VK_SHIFT) & 0x8000u16 as i16 != 0 {
-                    modifiers.push(VK_SHIFT as u32);
-                }
-                if GetAsyncKeyState(VK_CONTROL) & 0x8000u16 as i16 != 0 {
-                    modifiers.push(VK_CONTROL as u32);
-                }
-                if GetAsyncKeyState(VK_MENU) & 0x8000u16 as i16 != 0 {
-                    modifiers.push(VK_MENU as u32);
-                }
-                
-                // Send modifier key presses
-                for modifier in modifiers {
-                    let mut input = INPUT {
-                        type_: INPUT_KEYBOARD,
-                        u: std::mem::zeroed(),
-                    };
-                    
-                    let keyboard_input = KEYBDINPUT {
-                        wVk: modifier as u16,
-                        wScan: 0,
-                        dwFlags: 0,
-                        time: 0,
-                        dwExtraInfo: 0,
-                    };
-                    
-                    *input.u.ki_mut() = keyboard_input;
-                    inputs.push(input);
+    fn send_key_event(&self, backend: &dyn OutputBackend, key: u32, key_up: bool) -> Result<(), OutputError> {
+        // Collect all events (modifiers + main key) so they are sent as one batch
+        let mut events = Vec::new();
+
+        // Check if this is a modifier key
+        let is_modifier = MODIFIER_KEYS.contains(&(key as i32));
+
+        // If this is a regular key press (not modifier), send the held modifier keys first
+        if !key_up && !is_modifier {
+            for modifier in MODIFIER_KEYS {
+                if is_key_down(modifier) {
+                    events.push(OutputEvent::Key { key: modifier as u32, up: false });
                 }
             }
-            
-            // Send the main key event
-            let mut input = INPUT {
-                type_: INPUT_KEYBOARD,
-                u: std::mem::zeroed(),
-            };
-            
-            let keyboard_input = KEYBDINPUT {
-                wVk: key as u16,
-                wScan: 0,
-                dwFlags: if key_up { KEYEVENTF_KEYUP } else { 0 },
-                time: 0,
-                dwExtraInfo: 0,
-            };
-            
-            *input.u.ki_mut() = keyboard_input;
-            inputs.push(input);
-            
-            // Send all inputs at once
-            SendInput(inputs.len() as u32, inputs.as_mut_ptr(), std::mem::size_of::<INPUT>() as i32);
         }
+
+        events.push(OutputEvent::Key { key, up: key_up });
+        backend.send(&events)
+    }
+
+    fn send_mouse_event(&self, backend: &dyn OutputBackend, button: u32, button_up: bool) -> Result<(), OutputError> {
+        // Macro buttons are numbered from 0, so 3 and 4 are Back and Forward
+        let Some(button) = u8::try_from(button).ok().and_then(|button| MouseButton::from_number(button.saturating_add(1))) else {
+            return Ok(());
+        };
+        backend.send(&[OutputEvent::MouseButton { button, up: button_up }])
+    }
+}
+
+/// A macro queued for playback and the backend it plays through
+type PlaybackJob = (Macro, Arc<dyn OutputBackend>);
+
+/// Plays macros one after another on a background thread, so input handling never waits for them
+#[derive(Debug, Clone)]
+pub struct MacroPlayer {
+    queue: Sender<PlaybackJob>,
+}
+
+impl MacroPlayer {
+    /// Start the playback thread; it exits once every handle is dropped
+    pub fn spawn() -> Self {
+        let (queue, jobs) = mpsc::channel::<PlaybackJob>();
+        thread::spawn(move || {
+            for (macro_seq, backend) in jobs {
+                if let Err(e) = macro_seq.execute_with(backend.as_ref()) {
+                    eprintln!("Failed to execute macro '{}': {}", macro_seq.name, e);
+                }
+            }
+        });
+        Self { queue }
     }
 
-    fn send_mouse_event(&self, button: u32, button_up: bool) {
-        unsafe {
-            let mut input = INPUT {
-                type_: INPUT_MOUSE,
-                u: std::mem::zeroed(),
-            };
-            
-            let mouse_input = MOUSEINPUT {
-                dx: 0,
-                dy: 0,
-                mouseData: 0,
-                dwFlags: match button {
-                    0 => if button_up { MOUSEEVENTF_LEFTUP } else { MOUSEEVENTF_LEFTDOWN },
-                    _ => 0,
-                },
-                time: 0,
-                dwExtraInfo: 0,
-            };
-            
-            *input.u.mi_mut() = mouse_input;
-            SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
+    /// Queue a macro to play after those already queued
+    pub fn play(&self, macro_seq: Macro, backend: Arc<dyn OutputBackend>) {
+        if self.queue.send((macro_seq, backend)).is_err() {
+            eprintln!("Macro playback thread has stopped");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::RecordingBackend;
+
+    #[test]
+    fn execute_with_emits_actions_in_order() {
+        let mut macro_seq = Macro::new("Test");
+        macro_seq.add_action(MacroAction::KeyPress(KeyCode::A.vk()));
+        macro_seq.add_action(MacroAction::KeyRelease(KeyCode::A.vk()));
+        macro_seq.add_action(MacroAction::MousePress(1));
+        macro_seq.add_action(MacroAction::MouseRelease(1));
+        macro_seq.add_action(MacroAction::TypeText("café ✓".to_string()));
+        macro_seq.add_action(MacroAction::Scroll(Scroll { horizontal: 1.0, vertical: -0.25, smooth: None }));
+
+        let recorder = RecordingBackend::new();
+        macro_seq.execute_with(&recorder).unwrap();
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: KeyCode::A.vk(), up: false },
+            OutputEvent::Key { key: KeyCode::A.vk(), up: true },
+            OutputEvent::MouseButton { button: MouseButton::Right, up: false },
+            OutputEvent::MouseButton { button: MouseButton::Right, up: true },
+            OutputEvent::Text { text: "café ✓".to_string() },
+            OutputEvent::MouseWheel { delta: -30 },
+            OutputEvent::MouseHWheel { delta: 120 },
+        ]);
+    }
+}
@@ -1,13 +1,26 @@
 use eframe::egui;
 use crate::ui::KeyfinitumApp;
 
+mod capture;
+mod command;
 mod device;
+mod hotstring;
 mod input_layer;
+mod keycode;
+mod layout;
+#[cfg(target_os = "linux")]
+mod linux_input;
 mod r#macro;
+mod mouse_keys;
+mod output;
 mod profile;
 mod profile_manager;
 mod remapping;
+mod scroll;
+mod settings;
 mod ui;
+mod validation;
+mod window;
 mod plugin;
 
 fn main() -> Result<(), eframe::Error> {
@@ -0,0 +1,228 @@
+// Keyfinitum/src/mouse_keys.rs
+
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use crate::output::OutputEvent;
+
+/// Wheel-delta units per notch
+const WHEEL_DELTA: f64 = 120.0;
+
+/// Speeds and timing of keys that move the pointer or scroll while held
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MouseKeysConfig {
+    /// Pointer speed in pixels per second when movement starts
+    pub initial_speed: f64,
+    /// Pointer speed in pixels per second once fully accelerated
+    pub max_speed: f64,
+    /// Scroll speed in notches per second when scrolling starts
+    pub initial_scroll_speed: f64,
+    /// Scroll speed in notches per second once fully accelerated
+    pub max_scroll_speed: f64,
+    /// Time from the initial to the maximum speed
+    pub acceleration_time: Duration,
+    /// Shape of the acceleration: 1.0 is linear, larger values stay slow for longer
+    pub acceleration_exponent: f64,
+    /// Scale diagonal movement down so it is as fast as straight movement
+    pub normalize_diagonals: bool,
+    /// Time between two movement events
+    pub interval: Duration,
+}
+
+impl Default for MouseKeysConfig {
+    fn default() -> Self {
+        Self {
+            initial_speed: 200.0,
+            max_speed: 1600.0,
+            initial_scroll_speed: 5.0,
+            max_scroll_speed: 40.0,
+            acceleration_time: Duration::from_millis(1000),
+            acceleration_exponent: 2.0,
+            normalize_diagonals: true,
+            interval: Duration::from_millis(16),
+        }
+    }
+}
+
+impl MouseKeysConfig {
+    /// How far acceleration has progressed after moving for `elapsed`, from 0.0 to 1.0
+    fn acceleration(&self, elapsed: Duration) -> f64 {
+        let total = self.acceleration_time.as_secs_f64();
+        if total <= 0.0 {
+            return 1.0;
+        }
+        (elapsed.as_secs_f64() / total).min(1.0).powf(self.acceleration_exponent)
+    }
+}
+
+/// Whether a held direction moves the pointer or scrolls
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotionKind {
+    Move,
+    Scroll,
+}
+
+/// Directions currently held and the movement they have built up
+#[derive(Debug, Clone)]
+pub struct PointerMotion {
+    moves: Vec<(i32, i32)>,
+    scrolls: Vec<(i32, i32)>,
+    started: Instant,
+    last_step: Instant,
+    /// Movement not yet emitted because it is smaller than one unit
+    move_remainder: (f64, f64),
+    scroll_remainder: (f64, f64),
+}
+
+impl PointerMotion {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            moves: Vec::new(),
+            scrolls: Vec::new(),
+            started: now,
+            last_step: now,
+            move_remainder: (0.0, 0.0),
+            scroll_remainder: (0.0, 0.0),
+        }
+    }
+
+    /// Start holding a direction; only the signs of `dx` and `dy` matter
+    pub fn press(&mut self, kind: MotionKind, dx: i32, dy: i32) {
+        let direction = (dx.signum(), dy.signum());
+        match kind {
+            MotionKind::Move => self.moves.push(direction),
+            MotionKind::Scroll => self.scrolls.push(direction),
+        }
+    }
+
+    /// Stop holding a direction
+    pub fn release(&mut self, kind: MotionKind, dx: i32, dy: i32) {
+        let direction = (dx.signum(), dy.signum());
+        let held = match kind {
+            MotionKind::Move => &mut self.moves,
+            MotionKind::Scroll => &mut self.scrolls,
+        };
+        if let Some(position) = held.iter().position(|held| *held == direction) {
+            held.remove(position);
+        }
+    }
+
+    /// Whether no direction is held any more
+    pub fn is_idle(&self) -> bool {
+        self.moves.is_empty() && self.scrolls.is_empty()
+    }
+
+    pub fn next_deadline(&self, config: &MouseKeysConfig) -> Instant {
+        self.last_step + config.interval
+    }
+
+    /// The events covering the movement since the last step
+    pub fn step(&mut self, config: &MouseKeysConfig, now: Instant) -> Vec<OutputEvent> {
+        let seconds = now.saturating_duration_since(self.last_step).as_secs_f64();
+        self.step_for(config, now, seconds)
+    }
+
+    /// The events for the first step of a new movement, so a short tap still moves
+    pub fn first_step(&mut self, config: &MouseKeysConfig, now: Instant) -> Vec<OutputEvent> {
+        self.step_for(config, now, config.interval.as_secs_f64())
+    }
+
+    fn step_for(&mut self, config: &MouseKeysConfig, now: Instant, seconds: f64) -> Vec<OutputEvent> {
+        let acceleration = config.acceleration(now.saturating_duration_since(self.started));
+        self.last_step = now;
+        let mut events = Vec::new();
+
+        if let Some((x, y)) = combine(&self.moves, config.normalize_diagonals) {
+            let speed = config.initial_speed + (config.max_speed - config.initial_speed) * acceleration;
+            let (dx, dy) = advance(&mut self.move_remainder, x * speed * seconds, y * speed * seconds);
+            if dx != 0 || dy != 0 {
+                events.push(OutputEvent::MouseMove { dx, dy });
+            }
+        }
+
+        if let Some((x, y)) = combine(&self.scrolls, config.normalize_diagonals) {
+            let speed = config.initial_scroll_speed
+                + (config.max_scroll_speed - config.initial_scroll_speed) * acceleration;
+            let distance = speed * WHEEL_DELTA * seconds;
+            let (right, up) = advance(&mut self.scroll_remainder, x * distance, y * distance);
+            if up != 0 {
+                events.push(OutputEvent::MouseWheel { delta: up });
+            }
+            if right != 0 {
+                events.push(OutputEvent::MouseHWheel { delta: right });
+            }
+        }
+        events
+    }
+}
+
+/// The direction of all held directions together, or `None` if they cancel out
+fn combine(directions: &[(i32, i32)], normalize: bool) -> Option<(f64, f64)> {
+    let (x, y) = directions.iter().fold((0, 0), |(x, y), (dx, dy)| (x + dx, y + dy));
+    let (x, y) = (x.clamp(-1, 1) as f64, y.clamp(-1, 1) as f64);
+    if x == 0.0 && y == 0.0 {
+        None
+    } else if normalize && x != 0.0 && y != 0.0 {
+        Some((x * std::f64::consts::FRAC_1_SQRT_2, y * std::f64::consts::FRAC_1_SQRT_2))
+    } else {
+        Some((x, y))
+    }
+}
+
+/// Add movement to a remainder and take out its whole units
+fn advance(remainder: &mut (f64, f64), x: f64, y: f64) -> (i32, i32) {
+    remainder.0 += x;
+    remainder.1 += y;
+    let whole = (remainder.0.trunc(), remainder.1.trunc());
+    remainder.0 -= whole.0;
+    remainder.1 -= whole.1;
+    (whole.0 as i32, whole.1 as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn movement_accelerates_to_max_speed_and_combines_diagonals() {
+        let config = MouseKeysConfig {
+            initial_speed: 100.0,
+            max_speed: 1000.0,
+            acceleration_time: Duration::from_millis(100),
+            acceleration_exponent: 1.0,
+            interval: Duration::from_millis(10),
+            ..MouseKeysConfig::default()
+        };
+        let start = Instant::now();
+        let mut motion = PointerMotion::new(start);
+        motion.press(MotionKind::Move, 5, 0);
+
+        assert_eq!(motion.first_step(&config, start), vec![OutputEvent::MouseMove { dx: 1, dy: 0 }]);
+        let at = |ms| start + Duration::from_millis(ms);
+        assert_eq!(motion.step(&config, at(50)), vec![OutputEvent::MouseMove { dx: 27, dy: 0 }]);
+        assert_eq!(motion.step(&config, at(200)), vec![OutputEvent::MouseMove { dx: 150, dy: 0 }]);
+
+        motion.press(MotionKind::Move, 0, 1);
+        assert_eq!(motion.step(&config, at(300)), vec![OutputEvent::MouseMove { dx: 71, dy: 70 }]);
+        motion.release(MotionKind::Move, 1, 0);
+        assert_eq!(motion.step(&config, at(310)), vec![OutputEvent::MouseMove { dx: 0, dy: 10 }]);
+        motion.release(MotionKind::Move, 0, 1);
+        assert!(motion.is_idle());
+    }
+
+    #[test]
+    fn scrolling_emits_wheel_delta_units() {
+        let config = MouseKeysConfig {
+            initial_scroll_speed: 10.0,
+            max_scroll_speed: 10.0,
+            ..MouseKeysConfig::default()
+        };
+        let start = Instant::now();
+        let mut motion = PointerMotion::new(start);
+        motion.press(MotionKind::Scroll, -1, 1);
+
+        assert_eq!(motion.step(&config, start + Duration::from_millis(100)), vec![
+            OutputEvent::MouseWheel { delta: 84 },
+            OutputEvent::MouseHWheel { delta: -84 },
+        ]);
+    }
+}
@@ -0,0 +1,479 @@
+// Keyfinitum/src/output.rs
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use crate::keycode::{consumer, KeyCode};
+use crate::layout::KeyboardLayout;
+use crate::remapping::MouseButton;
+
+/// A single synthetic input event produced by the remapping engine or a macro
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)]
+pub enum OutputEvent {
+    /// Key by virtual key code; backends add the matching scan code
+    Key { key: u32, up: bool },
+    /// Physical key by set 1 scan code, independent of the keyboard layout
+    ScanCode { scan: u16, up: bool },
+    MouseButton { button: MouseButton, up: bool },
+    MouseMove { dx: i32, dy: i32 },
+    /// Wheel movement in wheel-delta units (120 per notch)
+    MouseWheel { delta: i32 },
+    /// Horizontal wheel movement in wheel-delta units; positive scrolls right
+    MouseHWheel { delta: i32 },
+    /// Type arbitrary Unicode text, independent of the keyboard layout
+    Text { text: String },
+    /// Key on the HID consumer page, such as media and launch keys
+    Consumer { usage: u16, up: bool },
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum OutputError {
+    SendFailed(String),
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputError::SendFailed(msg) => write!(f, "Failed to send input: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for OutputError {}
+
+/// Marker placed in `dwExtraInfo` of every event Keyfinitum injects, so the hook can recognise its own output
+#[cfg(windows)]
+pub const INJECTED_TAG: usize = 0x4B46_4E54;
+
+/// Destination for every synthetic event emitted by Keyfinitum
+pub trait OutputBackend: Send + Sync + fmt::Debug {
+    /// Emit the given events in order, as one batch where the platform allows it
+    fn send(&self, events: &[OutputEvent]) -> Result<(), OutputError>;
+
+    /// Use a newly read keyboard layout for typing text, where the backend types text with the layout's keys
+    fn set_keyboard_layout(&self, _layout: &KeyboardLayout) {}
+
+    /// Whether the backend has a way to send a HID consumer page usage, checked when a configuration loads
+    fn supports_consumer(&self, _usage: u16) -> bool {
+        true
+    }
+}
+
+/// Whether a set 1 scan code carries the 0xE0 prefix and must be sent with the extended flag
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn is_extended_scan_code(scan: u16) -> bool {
+    scan & 0xFF00 == 0xE000
+}
+
+/// The scan code to send alongside a virtual key, and whether it is an extended key
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn key_scan_code(key: u32) -> Option<(u16, bool)> {
+    KeyCode::from_vk(key).map(|code| {
+        let scan = code.scan_code();
+        (scan & 0x00FF, is_extended_scan_code(scan))
+    })
+}
+
+/// The key sending a HID consumer page usage
+#[cfg_attr(not(any(windows, target_os = "linux")), allow(dead_code))]
+pub fn consumer_key(usage: u16) -> Option<KeyCode> {
+    KeyCode::from_hid(consumer(usage))
+}
+
+/// Consumer usages without a Windows virtual key that a `WM_APPCOMMAND` performs instead
+#[cfg(windows)]
+fn app_command(usage: u16) -> Option<i16> {
+    use winapi::um::winuser::{
+        APPCOMMAND_MEDIA_FAST_FORWARD, APPCOMMAND_MEDIA_PAUSE, APPCOMMAND_MEDIA_PLAY, APPCOMMAND_MEDIA_RECORD,
+        APPCOMMAND_MEDIA_REWIND,
+    };
+
+    match usage {
+        0xB0 => Some(APPCOMMAND_MEDIA_PLAY),
+        0xB1 => Some(APPCOMMAND_MEDIA_PAUSE),
+        0xB2 => Some(APPCOMMAND_MEDIA_RECORD),
+        0xB3 => Some(APPCOMMAND_MEDIA_FAST_FORWARD),
+        0xB4 => Some(APPCOMMAND_MEDIA_REWIND),
+        _ => None,
+    }
+}
+
+/// The consumer usages for display brightness increment and decrement
+#[cfg(windows)]
+const BRIGHTNESS_UP: u16 = 0x6F;
+#[cfg(windows)]
+const BRIGHTNESS_DOWN: u16 = 0x70;
+
+/// The key that types a control character in text, since these have no Unicode keystroke.
+/// `None` for printable characters; `\r` is skipped so CRLF text types a single newline.
+#[cfg_attr(not(any(windows, target_os = "linux")), allow(dead_code))]
+pub fn text_control_key(ch: char) -> Option<u32> {
+    match ch {
+        '\n' => Some(KeyCode::Enter.vk()),
+        '\t' => Some(KeyCode::Tab.vk()),
+        '\u{8}' => Some(KeyCode::Backspace.vk()),
+        _ => None,
+    }
+}
+
+/// Returns the native backend for the current platform
+pub fn default_backend() -> Arc<dyn OutputBackend> {
+    #[cfg(windows)]
+    {
+        Arc::new(SendInputBackend)
+    }
+    #[cfg(target_os = "linux")]
+    {
+        Arc::new(crate::linux_input::UinputBackend::new())
+    }
+    #[cfg(not(any(windows, target_os = "linux")))]
+    {
+        Arc::new(NullBackend)
+    }
+}
+
+/// Backend that injects events through the Windows `SendInput` API
+#[cfg(windows)]
+#[derive(Debug, Default)]
+pub struct SendInputBackend;
+
+#[cfg(windows)]
+impl OutputBackend for SendInputBackend {
+    fn send(&self, events: &[OutputEvent]) -> Result<(), OutputError> {
+        use winapi::um::winuser::{
+            INPUT, INPUT_KEYBOARD, INPUT_MOUSE, KEYBDINPUT, KEYEVENTF_EXTENDEDKEY,
+            KEYEVENTF_KEYUP, KEYEVENTF_SCANCODE, KEYEVENTF_UNICODE, MOUSEINPUT,
+            MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP, MOUSEEVENTF_RIGHTDOWN, MOUSEEVENTF_RIGHTUP,
+            MOUSEEVENTF_MIDDLEDOWN, MOUSEEVENTF_MIDDLEUP, MOUSEEVENTF_MOVE, MOUSEEVENTF_WHEEL,
+            MOUSEEVENTF_HWHEEL, MOUSEEVENTF_XDOWN, MOUSEEVENTF_XUP, XBUTTON1, XBUTTON2,
+        };
+
+        let mut inputs = Vec::with_capacity(events.len());
+
+        for event in events {
+            unsafe {
+                let mut input: INPUT = std::mem::zeroed();
+                match event {
+                    OutputEvent::Key { key, up } => {
+                        let (scan, extended) = key_scan_code(*key).unwrap_or((0, false));
+                        let mut flags = if *up { KEYEVENTF_KEYUP } else { 0 };
+                        if extended {
+                            flags |= KEYEVENTF_EXTENDEDKEY;
+                        }
+                        input.type_ = INPUT_KEYBOARD;
+                        *input.u.ki_mut() = KEYBDINPUT {
+                            wVk: *key as u16,
+                            wScan: scan,
+                            dwFlags: flags,
+                            time: 0,
+                            dwExtraInfo: INJECTED_TAG,
+                        };
+                    }
+                    OutputEvent::ScanCode { scan, up } => {
+                        let mut flags = KEYEVENTF_SCANCODE;
+                        if *up {
+                            flags |= KEYEVENTF_KEYUP;
+                        }
+                        if is_extended_scan_code(*scan) {
+                            flags |= KEYEVENTF_EXTENDEDKEY;
+                        }
+                        input.type_ = INPUT_KEYBOARD;
+                        *input.u.ki_mut() = KEYBDINPUT {
+                            wVk: 0,
+                            wScan: *scan & 0x00FF,
+                            dwFlags: flags,
+                            time: 0,
+                            dwExtraInfo: INJECTED_TAG,
+                        };
+                    }
+                    OutputEvent::MouseButton { button, up } => {
+                        let (flags, data) = match (button, up) {
+                            (MouseButton::Left, false) => (MOUSEEVENTF_LEFTDOWN, 0),
+                            (MouseButton::Left, true) => (MOUSEEVENTF_LEFTUP, 0),
+                            (MouseButton::Right, false) => (MOUSEEVENTF_RIGHTDOWN, 0),
+                            (MouseButton::Right, true) => (MOUSEEVENTF_RIGHTUP, 0),
+                            (MouseButton::Middle, false) => (MOUSEEVENTF_MIDDLEDOWN, 0),
+                            (MouseButton::Middle, true) => (MOUSEEVENTF_MIDDLEUP, 0),
+                            (MouseButton::Back, false) => (MOUSEEVENTF_XDOWN, XBUTTON1),
+                            (MouseButton::Back, true) => (MOUSEEVENTF_XUP, XBUTTON1),
+                            (MouseButton::Forward, false) => (MOUSEEVENTF_XDOWN, XBUTTON2),
+                            (MouseButton::Forward, true) => (MOUSEEVENTF_XUP, XBUTTON2),
+                            (MouseButton::Extra(number), _) => {
+                                return Err(OutputError::SendFailed(format!(
+                                    "SendInput cannot press mouse button {}; only buttons 1-5 exist",
+                                    number
+                                )));
+                            }
+                        };
+                        input.type_ = INPUT_MOUSE;
+                        *input.u.mi_mut() = MOUSEINPUT {
+                            dx: 0,
+                            dy: 0,
+                            mouseData: data as u32,
+                            dwFlags: flags,
+                            time: 0,
+                            dwExtraInfo: INJECTED_TAG,
+                        };
+                    }
+                    OutputEvent::MouseMove { dx, dy } => {
+                        input.type_ = INPUT_MOUSE;
+                        *input.u.mi_mut() = MOUSEINPUT {
+                            dx: *dx,
+                            dy: *dy,
+                            mouseData: 0,
+                            dwFlags: MOUSEEVENTF_MOVE,
+                            time: 0,
+                            dwExtraInfo: INJECTED_TAG,
+                        };
+                    }
+                    OutputEvent::Consumer { usage, up } => {
+                        let Some(key) = consumer_key(*usage).filter(|code| code.vk() != 0) else {
+                            // Usages without a virtual key act once, on press, after the input before them
+                            if !*up {
+                                send_inputs(&mut inputs)?;
+                                send_consumer_command(*usage)?;
+                            }
+                            continue;
+                        };
+                        let (scan, _) = key_scan_code(key.vk()).unwrap_or((0, false));
+                        // Every consumer key with a virtual key is an extended key
+                        let flags = KEYEVENTF_EXTENDEDKEY | if *up { KEYEVENTF_KEYUP } else { 0 };
+                        inputs.push(keyboard_input(key.vk() as u16, scan, flags));
+                        continue;
+                    }
+                    OutputEvent::Text { text } => {
+                        for ch in text.chars().filter(|ch| *ch != '\r') {
+                            if let Some(key) = text_control_key(ch) {
+                                let (scan, _) = key_scan_code(key).unwrap_or((0, false));
+                                inputs.push(keyboard_input(key as u16, scan, 0));
+                                inputs.push(keyboard_input(key as u16, scan, KEYEVENTF_KEYUP));
+                                continue;
+                            }
+                            // Characters outside the BMP are sent as a surrogate pair
+                            let mut units = [0u16; 2];
+                            for unit in ch.encode_utf16(&mut units).iter() {
+                                inputs.push(keyboard_input(0, *unit, KEYEVENTF_UNICODE));
+                                inputs.push(keyboard_input(0, *unit, KEYEVENTF_UNICODE | KEYEVENTF_KEYUP));
+                            }
+                        }
+                        continue;
+                    }
+                    OutputEvent::MouseWheel { delta } => {
+                        input.type_ = INPUT_MOUSE;
+                        *input.u.mi_mut() = MOUSEINPUT {
+                            dx: 0,
+                            dy: 0,
+                            mouseData: *delta as u32,
+                            dwFlags: MOUSEEVENTF_WHEEL,
+                            time: 0,
+                            dwExtraInfo: INJECTED_TAG,
+                        };
+                    }
+                    OutputEvent::MouseHWheel { delta } => {
+                        input.type_ = INPUT_MOUSE;
+                        *input.u.mi_mut() = MOUSEINPUT {
+                            dx: 0,
+                            dy: 0,
+                            mouseData: *delta as u32,
+                            dwFlags: MOUSEEVENTF_HWHEEL,
+                            time: 0,
+                            dwExtraInfo: INJECTED_TAG,
+                        };
+                    }
+                }
+                inputs.push(input);
+            }
+        }
+
+        send_inputs(&mut inputs)
+    }
+
+    fn supports_consumer(&self, usage: u16) -> bool {
+        consumer_key(usage).is_some_and(|code| code.vk() != 0)
+            || app_command(usage).is_some()
+            || usage == BRIGHTNESS_UP
+            || usage == BRIGHTNESS_DOWN
+    }
+}
+
+/// Send and clear the inputs collected so far
+#[cfg(windows)]
+fn send_inputs(inputs: &mut Vec<winapi::um::winuser::INPUT>) -> Result<(), OutputError> {
+    use winapi::um::winuser::{SendInput, INPUT};
+
+    if inputs.is_empty() {
+        return Ok(());
+    }
+    let sent = unsafe {
+        SendInput(inputs.len() as u32, inputs.as_mut_ptr(), std::mem::size_of::<INPUT>() as i32)
+    };
+    let count = std::mem::take(inputs).len();
+    if sent as usize == count {
+        Ok(())
+    } else {
+        Err(OutputError::SendFailed(format!("SendInput accepted {} of {} events", sent, count)))
+    }
+}
+
+/// Perform a consumer usage that has no virtual key to press
+#[cfg(windows)]
+fn send_consumer_command(usage: u16) -> Result<(), OutputError> {
+    use winapi::um::winuser::{GetForegroundWindow, PostMessageW, FAPPCOMMAND_KEY, WM_APPCOMMAND};
+
+    if usage == BRIGHTNESS_UP || usage == BRIGHTNESS_DOWN {
+        // DDC/CI takes tens of milliseconds per monitor, too long to hold up input
+        std::thread::spawn(move || {
+            if let Err(e) = step_brightness(usage == BRIGHTNESS_UP) {
+                eprintln!("{}", e);
+            }
+        });
+        return Ok(());
+    }
+    let command = app_command(usage).ok_or_else(|| {
+        OutputError::SendFailed(format!("Consumer usage {:#05x} has no Windows equivalent", usage))
+    })?;
+    // The focused window passes commands it does not handle on to the shell, which hands them to media players
+    let posted = unsafe {
+        let window = GetForegroundWindow();
+        let command = (command as u16 | FAPPCOMMAND_KEY) as isize;
+        PostMessageW(window, WM_APPCOMMAND, window as usize, command << 16)
+    };
+    if posted != 0 {
+        Ok(())
+    } else {
+        Err(OutputError::SendFailed(format!("Could not post the app command for consumer usage {:#05x}", usage)))
+    }
+}
+
+/// Step the brightness of every monitor that takes DDC/CI commands by a tenth of its range.
+/// Built-in laptop panels usually do not.
+#[cfg(windows)]
+fn step_brightness(up: bool) -> Result<(), OutputError> {
+    use std::ptr;
+    use winapi::shared::minwindef::{BOOL, DWORD, LPARAM, TRUE};
+    use winapi::shared::windef::{HDC, HMONITOR, LPRECT};
+    use winapi::um::highlevelmonitorconfigurationapi::{GetMonitorBrightness, SetMonitorBrightness};
+    use winapi::um::physicalmonitorenumerationapi::{
+        DestroyPhysicalMonitors, GetNumberOfPhysicalMonitorsFromHMONITOR, GetPhysicalMonitorsFromHMONITOR,
+        PHYSICAL_MONITOR,
+    };
+    use winapi::um::winuser::EnumDisplayMonitors;
+
+    unsafe extern "system" fn collect(monitor: HMONITOR, _: HDC, _: LPRECT, monitors: LPARAM) -> BOOL {
+        (*(monitors as *mut Vec<HMONITOR>)).push(monitor);
+        TRUE
+    }
+
+    let mut changed = 0;
+    // SAFETY: the callback only runs during the call, while `monitors` is alive, and every
+    // physical monitor array is sized by the count Windows reports and destroyed after use
+    unsafe {
+        let mut monitors: Vec<HMONITOR> = Vec::new();
+        EnumDisplayMonitors(ptr::null_mut(), ptr::null(), Some(collect), &mut monitors as *mut _ as LPARAM);
+        for monitor in monitors {
+            let mut count: DWORD = 0;
+            if GetNumberOfPhysicalMonitorsFromHMONITOR(monitor, &mut count) == 0 || count == 0 {
+                continue;
+            }
+            let mut physical = vec![std::mem::zeroed::<PHYSICAL_MONITOR>(); count as usize];
+            if GetPhysicalMonitorsFromHMONITOR(monitor, count, physical.as_mut_ptr()) == 0 {
+                continue;
+            }
+            for handle in physical.iter().map(|monitor| monitor.hPhysicalMonitor) {
+                let (mut min, mut current, mut max) = (0, 0, 0);
+                if GetMonitorBrightness(handle, &mut min, &mut current, &mut max) == 0 || max <= min {
+                    continue;
+                }
+                let step = ((max - min) / 10).max(1);
+                let target = if up { (current + step).min(max) } else { current.saturating_sub(step).max(min) };
+                if SetMonitorBrightness(handle, target) != 0 {
+                    changed += 1;
+                }
+            }
+            DestroyPhysicalMonitors(count, physical.as_mut_ptr());
+        }
+    }
+
+    if changed > 0 {
+        Ok(())
+    } else {
+        Err(OutputError::SendFailed("No monitor accepts brightness changes over DDC/CI".to_string()))
+    }
+}
+
+/// A tagged keyboard input for `SendInput`
+#[cfg(windows)]
+fn keyboard_input(vk: u16, scan: u16, flags: u32) -> winapi::um::winuser::INPUT {
+    use winapi::um::winuser::{INPUT, INPUT_KEYBOARD, KEYBDINPUT};
+
+    unsafe {
+        let mut input: INPUT = std::mem::zeroed();
+        input.type_ = INPUT_KEYBOARD;
+        *input.u.ki_mut() = KEYBDINPUT {
+            wVk: vk,
+            wScan: scan,
+            dwFlags: flags,
+            time: 0,
+            dwExtraInfo: INJECTED_TAG,
+        };
+        input
+    }
+}
+
+/// Backend that discards all events, used where no native backend exists
+#[derive(Debug, Default)]
+#[cfg_attr(any(windows, target_os = "linux"), allow(dead_code))]
+pub struct NullBackend;
+
+impl OutputBackend for NullBackend {
+    fn send(&self, _events: &[OutputEvent]) -> Result<(), OutputError> {
+        Ok(())
+    }
+}
+
+/// Backend that stores every emitted event in memory so tests can assert on the exact stream
+#[derive(Debug, Default)]
+pub struct RecordingBackend {
+    events: Mutex<Vec<OutputEvent>>,
+}
+
+#[allow(dead_code)]
+impl RecordingBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All events recorded so far, in emission order
+    pub fn events(&self) -> Vec<OutputEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// Remove and return all recorded events
+    pub fn take(&self) -> Vec<OutputEvent> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
+
+impl OutputBackend for RecordingBackend {
+    fn send(&self, events: &[OutputEvent]) -> Result<(), OutputError> {
+        self.events.lock().unwrap().extend_from_slice(events);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extended_flag_only_for_extended_keys() {
+        assert_eq!(key_scan_code(KeyCode::A.vk()), Some((0x1E, false)));
+        assert_eq!(key_scan_code(KeyCode::LeftCtrl.vk()), Some((0x1D, false)));
+        assert_eq!(key_scan_code(KeyCode::RightCtrl.vk()), Some((0x1D, true)));
+        assert_eq!(key_scan_code(KeyCode::Insert.vk()), Some((0x52, true)));
+        assert_eq!(key_scan_code(KeyCode::NumpadMultiply.vk()), Some((0x37, false)));
+        assert_eq!(key_scan_code(KeyCode::Enter.vk()), Some((0x1C, false)));
+        assert!(is_extended_scan_code(KeyCode::NumpadEnter.scan_code()));
+    }
+}
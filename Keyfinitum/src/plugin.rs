@@ -4,21 +4,25 @@ use std::sync::{Arc, Mutex};
 use crate::profile_manager::ProfileManager;
 use crate::device::DeviceManager;
 
+#[allow(dead_code)]
 pub struct PluginManager {
     plugins: HashMap<String, Box<dyn Plugin>>,
 }
 
-pub trait Plugin {
+#[allow(dead_code)]
+pub trait Plugin: Send {
     fn name(&self) -> &str;
     fn initialize(&mut self);
     fn execute(&self, context: PluginContext);
 }
 
+#[allow(dead_code)]
 pub struct PluginContext {
     pub profile_manager: Arc<Mutex<ProfileManager>>,
     pub device_manager: Arc<Mutex<DeviceManager>>,
 }
 
+#[allow(dead_code)]
 impl PluginManager {
     pub fn new() -> Self {
         Self {
@@ -26,22 +30,23 @@ impl PluginManager {
         }
     }
 
-    pub fn load_plugin(&mut self, path: &Path) -> Result<(), PluginError> {
+    pub fn load_plugin(&mut self, _path: &Path) -> Result<(), PluginError> {
         // TODO: Implement plugin loading
         Ok(())
     }
 
-    pub fn unload_plugin(&mut self, name: &str) -> Result<(), PluginError> {
+    pub fn unload_plugin(&mut self, _name: &str) -> Result<(), PluginError> {
         // TODO: Implement plugin unloading
         Ok(())
     }
 
-    pub fn get_plugin(&self, name: &str) -> Option<&Box<dyn Plugin>> {
-        self.plugins.get(name)
+    pub fn get_plugin(&self, name: &str) -> Option<&dyn Plugin> {
+        self.plugins.get(name).map(|plugin| plugin.as_ref())
     }
 }
 
 #[derive(Debug)]
+#[allow(dead_code, clippy::enum_variant_names)]
 pub enum PluginError {
     LoadError(String),
     UnloadError(String),
@@ -5,9 +5,11 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 use crate::r#macro::Macro;
 use crate::input_layer::InputLayer;
+use crate::hotstring::Hotstring;
 
 /// Represents a remapping configuration
 #[derive(Serialize, Deserialize, Clone)]
+#[allow(dead_code)]
 pub struct RemappingConfig {
     pub name: String,
     pub path: PathBuf,
@@ -22,6 +24,8 @@ pub struct Profile {
     pub input_layers: HashMap<String, InputLayer>, // Stores input layers for this profile
     pub active_profile: String,
     pub app_mappings: HashMap<String, usize>, // Maps application patterns to profile indices
+    #[serde(default)]
+    pub hotstrings: HashMap<String, Hotstring>, // Text expansions keyed by abbreviation
 }
 
 impl Profile {
@@ -33,9 +37,11 @@ impl Profile {
             input_layers: HashMap::new(),
             active_profile: "default".to_string(),
             app_mappings: HashMap::new(),
+            hotstrings: HashMap::new(),
         }
     }
 
+    #[allow(dead_code)]
     pub fn add_remapping_config(&mut self, name: &str, config_path: PathBuf) {
         self.remapping_config.insert(name.to_string(), config_path.to_string_lossy().to_string());
     }
@@ -142,6 +148,16 @@ impl Profile {
         self.input_layers.get(name)
     }
 
+    #[allow(dead_code)]
+    pub fn add_hotstring(&mut self, hotstring: Hotstring) {
+        self.hotstrings.insert(hotstring.abbreviation.clone(), hotstring);
+    }
+
+    #[allow(dead_code)]
+    pub fn remove_hotstring(&mut self, abbreviation: &str) -> Option<Hotstring> {
+        self.hotstrings.remove(abbreviation)
+    }
+
     #[allow(dead_code)]
     pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
         serde_json::from_str(json)
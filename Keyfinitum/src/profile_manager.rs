@@ -1,28 +1,129 @@
+use crate::command::{AllowedCommand, CommandPolicy, CommandSpec, Confirmation};
 use crate::profile::Profile;
 use crate::r#macro::Macro;
+use crate::remapping::{KeyCodeError, KeyRemapping};
+use crate::settings::MachineSettings;
+use crate::validation::ValidationReport;
 use active_win_pos_rs::get_active_window;
 use std::collections::HashMap;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
+/// How often the active config file is checked for edits
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 pub struct ProfileManager {
     pub(crate) profiles: Arc<Mutex<Vec<Profile>>>,
     active_profile_index: Arc<Mutex<usize>>,
     pub app_mappings: Arc<Mutex<HashMap<String, usize>>>,
     monitor_thread: Option<std::thread::JoinHandle<()>>,
     monitor_stop_signal: Arc<std::sync::atomic::AtomicBool>,
+    config_watcher_thread: Option<std::thread::JoinHandle<()>>,
+    config_watcher_stop_signal: Arc<std::sync::atomic::AtomicBool>,
+    remapping: Arc<Mutex<KeyRemapping>>,
+    settings: MachineSettings,
+    /// Where the machine-local settings are saved, if anywhere
+    settings_path: Option<PathBuf>,
+}
+
+/// Load a profile's active remapping configuration, input layers, hotstrings and macros into the shared engine.
+/// The engine is only touched once everything loaded, and keys held meanwhile keep working.
+fn load_profile_remapping(profile: &Profile, engine: &Mutex<KeyRemapping>) -> Result<(), String> {
+    let mut remapping = match profile.active_config_path() {
+        Some(path) => KeyRemapping::load(path).map_err(|e| match e {
+            KeyCodeError::InvalidConfig(report) => format!("Remapping config '{}' is invalid:\n{}", path, report),
+            e => format!("Failed to load remapping config '{}': {:?}", path, e),
+        })?,
+        None => KeyRemapping::new(),
+    };
+
+    let mut input_layers: Vec<_> = profile.input_layers.values().collect();
+    input_layers.sort_by(|a, b| a.name.cmp(&b.name));
+    for input_layer in input_layers {
+        remapping.add_input_layer(input_layer)
+            .map_err(|e| format!("Failed to load input layer '{}': {:?}", input_layer.name, e))?;
+    }
+    remapping.set_hotstrings(profile.hotstrings.values().cloned().collect());
+    remapping.set_macros(profile.macros.clone());
+
+    let unknown = remapping.unknown_macros();
+    if !unknown.is_empty() {
+        return Err(format!("Bindings trigger unknown macros: {}", unknown.join(", ")));
+    }
+
+    // Runtime state such as the command policy stays with the engine, not the profile
+    engine.lock().unwrap().swap_config(remapping)
+        .map_err(|e| format!("Failed to switch remapping config: {:?}", e))
+}
+
+/// What the config watcher last saw of a profile's config file
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ConfigStamp {
+    path: Option<String>,
+    /// Hash of the file's contents, which unlike its modification time never misses a quick second save
+    contents: Option<u64>,
+}
+
+impl ConfigStamp {
+    fn of(profile: &Profile) -> Self {
+        let path = profile.active_config_path().cloned();
+        let contents = path.as_ref().and_then(|path| fs::read(path).ok()).map(|contents| {
+            let mut hasher = DefaultHasher::new();
+            contents.hash(&mut hasher);
+            hasher.finish()
+        });
+        Self { path, contents }
+    }
+}
+
+/// Reload the profile's config if its file changed since `stamp` was taken.
+/// A different file, after a profile switch, was loaded by the switch and only updates the stamp.
+/// On failure the engine keeps the previous config.
+fn reload_if_changed(profile: &Profile, engine: &Mutex<KeyRemapping>, stamp: &mut ConfigStamp) -> Option<Result<(), String>> {
+    let current = ConfigStamp::of(profile);
+    if current == *stamp {
+        return None;
+    }
+    let same_file = current.path == stamp.path;
+    *stamp = current;
+    same_file.then(|| load_profile_remapping(profile, engine))
 }
 
 #[allow(dead_code)]
 impl ProfileManager {
     pub fn new() -> Self {
+        Self::with_settings_path(MachineSettings::default_path())
+    }
+
+    /// A manager whose machine-local settings, such as the command policy, are kept at `settings_path`.
+    /// A settings file that cannot be read is left untouched and the defaults are used instead.
+    pub fn with_settings_path(settings_path: Option<PathBuf>) -> Self {
+        let loaded = settings_path.as_ref().map(MachineSettings::load).transpose();
+        let (settings, settings_path) = match loaded {
+            Ok(settings) => (settings.unwrap_or_default(), settings_path),
+            Err(e) => {
+                eprintln!("{}; using default settings", e);
+                (MachineSettings::default(), None)
+            }
+        };
+        let mut remapping = KeyRemapping::new();
+        remapping.command_runner_mut().set_policy(settings.command_policy.clone());
+
         ProfileManager {
             profiles: Arc::new(Mutex::new(vec![Profile::new("Default")])),
             active_profile_index: Arc::new(Mutex::new(0)),
             app_mappings: Arc::new(Mutex::new(HashMap::new())),
             monitor_thread: None,
             monitor_stop_signal: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+            config_watcher_thread: None,
+            config_watcher_stop_signal: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+            remapping: Arc::new(Mutex::new(remapping)),
+            settings,
+            settings_path,
         }
     }
 
@@ -36,7 +137,50 @@ impl ProfileManager {
             return Err(format!("Profile index {} out of bounds", index));
         }
         *self.active_profile_index.lock().unwrap() = index;
-        Ok(())
+        load_profile_remapping(&profiles[index], &self.remapping)
+    }
+
+    /// Shared remapping engine that the input capture thread routes events through
+    pub fn remapping(&self) -> Arc<Mutex<KeyRemapping>> {
+        Arc::clone(&self.remapping)
+    }
+
+    /// Load the active profile's remapping configuration into the shared engine
+    pub fn load_active_remapping(&self) -> Result<(), String> {
+        load_profile_remapping(&self.active_profile(), &self.remapping)
+    }
+
+    /// Problems in the loaded remapping configuration, for display next to the bindings
+    pub fn validate_active_remapping(&self) -> ValidationReport {
+        self.remapping.lock().unwrap().validate()
+    }
+
+    /// Which commands bindings may run
+    pub fn command_policy(&self) -> CommandPolicy {
+        self.remapping.lock().unwrap().command_runner().policy().clone()
+    }
+
+    /// Set which commands bindings may run, for the loaded profile and every later one, and save it for this machine
+    pub fn set_command_policy(&mut self, policy: CommandPolicy) -> Result<(), String> {
+        self.remapping.lock().unwrap().command_runner_mut().set_policy(policy.clone());
+        self.settings.command_policy = policy;
+        match &self.settings_path {
+            Some(path) => self.settings.save(path).map_err(|e| e.to_string()),
+            None => Ok(()),
+        }
+    }
+
+    /// Let exactly this command run from now on without asking
+    pub fn always_allow_command(&mut self, spec: &CommandSpec) -> Result<(), String> {
+        let entry = AllowedCommand::exact(spec).ok_or_else(|| format!("Program not found: {}", spec.program))?;
+        let mut policy = self.command_policy();
+        policy.allow(entry);
+        self.set_command_policy(policy)
+    }
+
+    /// Set how the user is asked about commands the policy leaves to them
+    pub fn set_command_confirmation(&self, confirmation: Confirmation) {
+        self.remapping.lock().unwrap().command_runner_mut().set_confirmation(confirmation);
     }
 
     pub fn active_profile(&self) -> Profile {
@@ -122,6 +266,7 @@ impl ProfileManager {
         let app_mappings = Arc::clone(&self.app_mappings);
         let active_profile_index = Arc::clone(&self.active_profile_index);
         let stop_signal = Arc::clone(&self.monitor_stop_signal);
+        let remapping = Arc::clone(&self.remapping);
         
         if let Some(handle) = self.monitor_thread.take() {
             stop_signal.store(true, std::sync::atomic::Ordering::SeqCst);
@@ -147,6 +292,9 @@ impl ProfileManager {
                         if current_index != profile_index {
                             *active_profile_index.lock().unwrap() = profile_index;
                             println!("Switched to profile: {}", profiles[profile_index].name);
+                            if let Err(e) = load_profile_remapping(&profiles[profile_index], &remapping) {
+                                eprintln!("{}", e);
+                            }
                         }
                     }
                 }
@@ -161,4 +309,112 @@ impl ProfileManager {
             let _ = handle.join();
         }
     }
+
+    /// Watch the active profile's config file and reload it into the engine whenever it is saved
+    pub fn start_config_watcher(&mut self) {
+        let profiles = Arc::clone(&self.profiles);
+        let active_profile_index = Arc::clone(&self.active_profile_index);
+        let stop_signal = Arc::clone(&self.config_watcher_stop_signal);
+        let remapping = Arc::clone(&self.remapping);
+
+        self.stop_config_watcher();
+        stop_signal.store(false, std::sync::atomic::Ordering::SeqCst);
+
+        let mut stamp = ConfigStamp::of(&self.active_profile());
+        self.config_watcher_thread = Some(thread::spawn(move || {
+            while !stop_signal.load(std::sync::atomic::Ordering::SeqCst) {
+                thread::sleep(CONFIG_POLL_INTERVAL);
+                let profile = profiles.lock().unwrap()[*active_profile_index.lock().unwrap()].clone();
+                match reload_if_changed(&profile, &remapping, &mut stamp) {
+                    Some(Ok(())) => println!("Reloaded remapping config for profile: {}", profile.name),
+                    Some(Err(e)) => eprintln!("{}\nKeeping the previous remapping config", e),
+                    None => {}
+                }
+            }
+        }));
+    }
+
+    pub fn stop_config_watcher(&mut self) {
+        if let Some(handle) = self.config_watcher_thread.take() {
+            self.config_watcher_stop_signal.store(true, std::sync::atomic::Ordering::SeqCst);
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keycode::KeyCode;
+    use crate::output::{OutputEvent, RecordingBackend};
+    use crate::remapping::{Action, KeyBinding};
+
+    fn write_config(path: &std::path::Path, target: KeyCode) {
+        let mut config = KeyRemapping::new();
+        config.add_binding(KeyBinding::from_chord("J", Action::KeyPress(target.vk())).unwrap()).unwrap();
+        config.save(path).unwrap();
+    }
+
+    #[test]
+    fn edited_configs_reload_without_dropping_held_keys() {
+        let path = std::env::temp_dir().join(format!("keyfinitum-reload-{}.json", std::process::id()));
+        write_config(&path, KeyCode::F13);
+        let mut profile = Profile::new("Reload");
+        profile.add_remapping_config("default", path.clone());
+        let recorder = Arc::new(RecordingBackend::new());
+        let engine = Mutex::new(KeyRemapping::new());
+        engine.lock().unwrap().set_output_backend(recorder.clone());
+        load_profile_remapping(&profile, &engine).unwrap();
+        let mut stamp = ConfigStamp::of(&profile);
+        let j = KeyCode::J.vk();
+
+        assert_eq!(reload_if_changed(&profile, &engine, &mut stamp), None);
+        engine.lock().unwrap().handle_key_press(j).unwrap();
+        write_config(&path, KeyCode::F14);
+        assert_eq!(reload_if_changed(&profile, &engine, &mut stamp), Some(Ok(())));
+        engine.lock().unwrap().handle_key_release(j).unwrap();
+
+        fs::write(&path, "{ not json").unwrap();
+        assert!(matches!(reload_if_changed(&profile, &engine, &mut stamp), Some(Err(_))));
+        engine.lock().unwrap().handle_key_press(j).unwrap();
+        engine.lock().unwrap().handle_key_release(j).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: KeyCode::F13.vk(), up: false },
+            OutputEvent::Key { key: KeyCode::F13.vk(), up: true },
+            OutputEvent::Key { key: KeyCode::F14.vk(), up: false },
+            OutputEvent::Key { key: KeyCode::F14.vk(), up: true },
+        ]);
+    }
+
+    #[test]
+    fn command_policy_comes_from_the_machine_settings_file() {
+        let dir = std::env::temp_dir().join(format!("keyfinitum-settings-{}", std::process::id()));
+        let path = dir.join("settings.json");
+        let exe = std::env::current_exe().unwrap();
+        let settings = MachineSettings {
+            command_policy: CommandPolicy::Allowlist(vec![AllowedCommand::program(&exe)]),
+        };
+        settings.save(&path).unwrap();
+
+        let mut manager = ProfileManager::with_settings_path(Some(path.clone()));
+        manager.load_active_remapping().unwrap();
+        assert_eq!(*manager.remapping().lock().unwrap().command_runner().policy(), settings.command_policy);
+
+        let mut spec = CommandSpec::new(&exe.to_string_lossy(), &["--list"]);
+        spec.working_dir = Some(dir.clone());
+        manager.always_allow_command(&spec).unwrap();
+        let expected = CommandPolicy::Allowlist(vec![
+            AllowedCommand::program(&exe),
+            AllowedCommand::exact(&spec).unwrap(),
+        ]);
+        assert_eq!(manager.command_policy(), expected);
+        assert_eq!(ProfileManager::with_settings_path(Some(path)).command_policy(), expected);
+
+        // Without a settings file, commands outside the (empty) list are left to the user
+        let unset = ProfileManager::with_settings_path(Some(dir.join("missing.json")));
+        assert_eq!(unset.command_policy(), CommandPolicy::Confirm(Vec::new()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -1,49 +1,399 @@
 // Keyfinitum/src/remapping.rs
 
-use std::collections::HashMap;
-use serde::{Deserialize, Serialize};
-use winapi::um::winuser::{
-    SendInput, INPUT, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_KEYUP, KEYEVENTF_EXTENDEDKEY,
-    VK_SHIFT, VK_CONTROL, VK_MENU, MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP,
-    MOUSEEVENTF_RIGHTDOWN, MOUSEEVENTF_RIGHTUP, MOUSEEVENTF_MIDDLEDOWN, MOUSEEVENTF_MIDDLEUP,
-    MOUSEEVENTF_WHEEL, INPUT_MOUSE, MOUSEINPUT,
-};
+use std::collections::{HashMap, HashSet};
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::path::Path;
+use std::fmt;
 use std::fs;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::capture::{InputEvent, ATTRIBUTES_DEVICES};
+use crate::device::{DeviceFilter, SourceDevice};
+use crate::command::{CommandRunner, CommandSpec};
+use crate::hotstring::{Expansion, Hotstring, HotstringMatcher};
+use crate::input_layer::InputLayer;
+use crate::keycode::{KeyChord, KeyCode, KeyParseError, Modifier, Side};
+use crate::layout::KeyboardLayout;
+use crate::r#macro::{Macro, MacroPlayer};
+use crate::mouse_keys::{MotionKind, MouseKeysConfig, PointerMotion};
+use crate::output::{self, OutputBackend, OutputError, OutputEvent};
+use crate::scroll::Scroll;
+use crate::validation::{Location, ValidationReport};
+use crate::window::{AppCondition, WindowContext};
 
+// Engine key codes are Windows virtual key codes
+#[cfg(test)]
+const VK_CONTROL: u32 = KeyCode::Ctrl.vk();
+const VK_ESCAPE: u32 = KeyCode::Escape.vk();
+
+/// Modifier groups in the order of their bits in a modified key
+const MODIFIER_BITS: [Modifier; 4] = [Modifier::Shift, Modifier::Ctrl, Modifier::Alt, Modifier::Meta];
+
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyRemapping {
     layers: Vec<Layer>,
     active_layer_index: usize,
     #[serde(skip)]
     modifier_state: ModifierState,
+    #[serde(skip, default = "output::default_backend")]
+    backend: Arc<dyn OutputBackend>,
+    #[serde(skip)]
+    pending_tap_hold: Option<PendingTapHold>,
+    #[serde(skip)]
+    layer_stack: Vec<ActiveLayer>,
+    #[serde(skip)]
+    held_presses: HashMap<u32, PressRecord>,
+    #[serde(skip)]
+    recording: Option<Vec<HeldOutput>>,
+    #[serde(default)]
+    combos: Vec<Combo>,
+    #[serde(skip)]
+    pending_combo: Option<PendingCombo>,
+    #[serde(skip)]
+    held_combos: Vec<HeldCombo>,
+    #[serde(default)]
+    leader: Option<LeaderConfig>,
+    #[serde(skip)]
+    leader_state: Option<LeaderState>,
+    #[serde(skip)]
+    hotstrings: HotstringMatcher,
+    /// Layout that key presses are read as characters with, for hotstrings
+    #[serde(skip)]
+    layout: KeyboardLayout,
+    /// Whether Caps Lock is on, which also changes the case of typed letters
+    #[serde(skip)]
+    caps_lock: bool,
+    #[serde(default)]
+    mouse_keys: MouseKeysConfig,
+    #[serde(skip)]
+    pointer_motion: Option<PointerMotion>,
+    #[serde(skip)]
+    macros: HashMap<String, Macro>,
+    #[serde(skip)]
+    macro_player: Option<MacroPlayer>,
+    /// Not part of the config, so a shared or imported config cannot loosen the command policy
+    #[serde(skip)]
+    commands: CommandRunner,
+    /// Output waiting for its time, such as the later steps of a smooth scroll, in time order
+    #[serde(skip)]
+    scheduled_output: Vec<(Instant, Vec<OutputEvent>)>,
+    /// Time of the event or tick being processed
+    #[serde(skip, default = "Instant::now")]
+    clock: Instant,
+    #[serde(skip)]
+    window: WindowContext,
+    /// Number of batches sent through the backend so far
+    #[serde(skip)]
+    emitted: usize,
 }
 
+/// Modifier keys currently held, tracked per physical key
 #[derive(Debug, Clone, Default)]
 struct ModifierState {
-    shift: bool,
-    ctrl: bool,
-    alt: bool,
+    held: HashSet<u32>,
+}
+
+impl ModifierState {
+    /// Record a modifier key going down or up; other keys are ignored
+    fn update(&mut self, key: u32, pressed: bool) {
+        if is_modifier(key) {
+            if pressed {
+                self.held.insert(key);
+            } else {
+                self.held.remove(&key);
+            }
+        }
+    }
+
+    /// Binding requirements for a modifier group that the held keys satisfy, most specific first
+    fn satisfied(&self, modifier: Modifier) -> Vec<ModifierMatch> {
+        let sides: Vec<Option<Side>> = self.held.iter()
+            .filter_map(|key| KeyCode::from_vk(*key).and_then(KeyCode::modifier))
+            .filter(|(group, _)| *group == modifier)
+            .map(|(_, side)| side)
+            .collect();
+
+        if sides.is_empty() {
+            return vec![ModifierMatch::Off];
+        }
+        let mut satisfied = Vec::new();
+        if sides.contains(&Some(Side::Left)) {
+            satisfied.push(ModifierMatch::Left);
+        }
+        if sides.contains(&Some(Side::Right)) {
+            satisfied.push(ModifierMatch::Right);
+        }
+        satisfied.push(ModifierMatch::Either);
+        satisfied
+    }
+
+    /// Whether any key of the modifier group is held
+    fn is_held(&self, modifier: Modifier) -> bool {
+        self.held.iter()
+            .filter_map(|key| KeyCode::from_vk(*key).and_then(KeyCode::modifier))
+            .any(|(group, _)| group == modifier)
+    }
+
+    /// Every modifier combination a binding may require to match the held keys
+    fn matching_combinations(&self) -> Vec<KeyModifiers> {
+        let mut combinations = vec![KeyModifiers::default()];
+        for modifier in MODIFIER_BITS {
+            let options = self.satisfied(modifier);
+            combinations = combinations.iter()
+                .flat_map(|base| options.iter().map(move |option| {
+                    let mut combination = base.clone();
+                    combination.set(modifier, *option);
+                    combination
+                }))
+                .collect();
+        }
+        combinations
+    }
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Layer {
     name: String,
+    #[serde(deserialize_with = "deserialize_mappings")]
     mappings: HashMap<u32, Action>,
+    /// Limits the layer to matching windows; elsewhere lookups pass through it
+    #[serde(default)]
+    condition: Option<AppCondition>,
+    /// Limits the layer to events from matching devices, for which it is active on top of every other layer
+    #[serde(default)]
+    device: Option<DeviceFilter>,
+    /// Bindings that take the place of `mappings` in matching windows, the first match winning
+    #[serde(default)]
+    overrides: Vec<AppBinding>,
+}
+
+impl Layer {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            mappings: HashMap::new(),
+            condition: None,
+            device: None,
+            overrides: Vec::new(),
+        }
+    }
+
+    /// The action bound to an encoded key while `window` has focus
+    fn binding(&self, key: u32, window: &WindowContext) -> Option<&Action> {
+        self.overrides.iter()
+            .find(|binding| binding.key == key && binding.condition.matches(window))
+            .map(|binding| &binding.action)
+            .or_else(|| self.mappings.get(&key))
+    }
+}
+
+/// A binding that only applies in some windows
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AppBinding {
+    key: u32,
+    condition: AppCondition,
+    action: Action,
+}
+
+/// A layer pushed above the base layer and how it gets removed again
+#[derive(Debug, Clone, PartialEq)]
+struct ActiveLayer {
+    index: usize,
+    activation: LayerActivation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LayerActivation {
+    Momentary,
+    Toggle,
+    OneShot,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Action {
     KeyPress(u32),
+    /// Press a physical key by set 1 scan code, independent of the keyboard layout
+    ScanCodePress(u16),
     KeySequence(Vec<u32>),
     KeyCombination(Vec<u32>),
+    /// Type Unicode text, independent of the keyboard layout
+    TypeText(String),
+    /// Run a command line through the platform's default shell
     SystemCommand(String),
+    /// Run a program directly, with arguments, environment and limits
+    RunCommand(CommandSpec),
     MacroTrigger(String),
+    /// Make the layer the base layer, dropping every stacked layer
     LayerSwitch(usize),
+    /// Stack the layer while the key is held
+    MomentaryLayer(usize),
+    /// Stack the layer, or remove it if it is already toggled on
+    ToggleLayer(usize),
+    /// Stack the layer for the next key press only
+    OneShotLayer(usize),
+    /// Defer to the binding of the next lower active layer
+    Transparent,
     MouseButton(MouseButton),
     MouseMove { dx: i32, dy: i32 },
+    /// Move the pointer continuously while held, in the direction of the signs of `dx` and `dy`
+    ContinuousMouseMove { dx: i32, dy: i32 },
     MouseWheel(i32),
+    /// Scroll horizontally and vertically by fractional notches, optionally spread over time
+    Scroll(Scroll),
+    /// Scroll continuously while held; positive `dy` scrolls up and positive `dx` right
+    ContinuousScroll { dx: i32, dy: i32 },
     MediaControl(MediaAction),
+    /// Performs `tap` when the key is tapped and `hold` while it is held
+    TapHold {
+        tap: Box<Action>,
+        hold: Box<Action>,
+        timeout: Duration,
+        #[serde(default)]
+        policy: TapHoldPolicy,
+    },
+}
+
+/// How a tap-hold key decides between tap and hold before its timeout expires
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub enum TapHoldPolicy {
+    /// Hold only once the timeout expires; other keys are delayed until then
+    #[default]
+    Timeout,
+    /// Also hold when another key is pressed and released while the key is down
+    PermissiveHold,
+    /// Hold as soon as any other key is pressed
+    HoldOnOtherKeyPress,
+}
+
+/// A tap-hold key that is down but not yet decided
+#[derive(Debug, Clone)]
+struct PendingTapHold {
+    key: u32,
+    tap: Action,
+    hold: Action,
+    policy: TapHoldPolicy,
+    deadline: Instant,
+    buffered: Vec<InputEvent>,
+}
+
+/// A set of keys that performs an action when pressed together
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Combo {
+    pub keys: Vec<u32>,
+    pub action: Action,
+    /// How long after the first key the remaining keys may still arrive
+    pub window: Duration,
+}
+
+/// Presses of combo keys held back while waiting for the rest of a combo
+#[derive(Debug, Clone)]
+struct PendingCombo {
+    deadline: Instant,
+    buffered: Vec<InputEvent>,
+}
+
+/// A fired combo whose keys have not all been released yet
+#[derive(Debug, Clone)]
+struct HeldCombo {
+    keys: Vec<u32>,
+    /// Taken when the first of the keys is released
+    record: Option<PressRecord>,
+}
+
+/// An output that a press left down
+#[derive(Debug, Clone, PartialEq)]
+enum HeldOutput {
+    Key(u32),
+    ScanCode(u16),
+    MouseButton(MouseButton),
+}
+
+/// What a physical key's press did that its release has to undo
+#[derive(Debug, Clone, Default)]
+struct PressRecord {
+    action: Option<Action>,
+    /// Outputs still down, in the order they were pressed
+    outputs: Vec<HeldOutput>,
+}
+
+/// Leader key and the key sequences that may follow it
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderConfig {
+    pub key: u32,
+    /// Abandons the sequence typed so far
+    pub cancel_key: u32,
+    /// Maximum pause between two keys of a sequence
+    pub timeout: Duration,
+    pub sequences: SequenceTrie,
+}
+
+#[allow(dead_code)]
+impl LeaderConfig {
+    pub fn new(key: u32, timeout: Duration) -> Self {
+        Self {
+            key,
+            cancel_key: VK_ESCAPE,
+            timeout,
+            sequences: SequenceTrie::default(),
+        }
+    }
+
+    /// Bind the keys typed after the leader to an action
+    pub fn add_sequence(&mut self, keys: &[u32], action: Action) -> Result<(), KeyCodeError> {
+        if keys.is_empty() {
+            return Err(KeyCodeError::InvalidSequence(keys.to_vec()));
+        }
+        self.sequences.insert(keys, action);
+        Ok(())
+    }
+}
+
+/// Prefix tree of key sequences, each node optionally completing an action
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SequenceTrie {
+    #[serde(default)]
+    action: Option<Action>,
+    #[serde(default)]
+    children: HashMap<u32, SequenceTrie>,
+}
+
+impl SequenceTrie {
+    pub fn insert(&mut self, keys: &[u32], action: Action) {
+        match keys.split_first() {
+            Some((first, rest)) => self.children.entry(*first).or_default().insert(rest, action),
+            None => self.action = Some(action),
+        }
+    }
+
+    /// The node reached by following `keys` from this one
+    pub fn get(&self, keys: &[u32]) -> Option<&SequenceTrie> {
+        keys.iter().try_fold(self, |node, key| node.children.get(key))
+    }
+
+    /// Every sequence from this node down that completes an action, with its action
+    pub fn sequences(&self) -> Vec<(Vec<u32>, &Action)> {
+        let own = self.action.iter().map(|action| (Vec::new(), action));
+        let below = self.children.iter().flat_map(|(key, child)| {
+            child.sequences().into_iter().map(move |(mut keys, action)| {
+                keys.insert(0, *key);
+                (keys, action)
+            })
+        });
+        own.chain(below).collect()
+    }
+}
+
+/// Keys collected since the leader key was pressed
+#[derive(Debug, Clone)]
+struct LeaderState {
+    keys: Vec<u32>,
+    deadline: Instant,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -51,20 +401,120 @@ pub enum MouseButton {
     Left,
     Right,
     Middle,
+    /// XButton1, the thumb button nearest the user
     Back,
+    /// XButton2
     Forward,
+    /// Button 6 and up, by number
+    Extra(u8),
+}
+
+#[allow(dead_code)]
+impl MouseButton {
+    /// Button number, counting Left as 1 and Forward as 5
+    pub fn number(&self) -> u8 {
+        match self {
+            MouseButton::Left => 1,
+            MouseButton::Right => 2,
+            MouseButton::Middle => 3,
+            MouseButton::Back => 4,
+            MouseButton::Forward => 5,
+            MouseButton::Extra(number) => *number,
+        }
+    }
+
+    pub fn from_number(number: u8) -> Option<MouseButton> {
+        match number {
+            0 => None,
+            1 => Some(MouseButton::Left),
+            2 => Some(MouseButton::Right),
+            3 => Some(MouseButton::Middle),
+            4 => Some(MouseButton::Back),
+            5 => Some(MouseButton::Forward),
+            number => Some(MouseButton::Extra(number)),
+        }
+    }
+}
+
+/// Direction of a single mouse wheel tick
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum WheelDirection {
+    Up,
+    Down,
+    Left,
+    Right,
 }
 
+/// A key on the HID consumer usage page
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum MediaAction {
     PlayPause,
+    Play,
+    Pause,
+    Stop,
     NextTrack,
     PrevTrack,
+    FastForward,
+    Rewind,
+    Record,
+    Eject,
     VolumeUp,
     VolumeDown,
     Mute,
+    BrightnessUp,
+    BrightnessDown,
+    Calculator,
+    Mail,
+    MediaSelect,
+    MyComputer,
+    BrowserHome,
+    BrowserSearch,
+    BrowserBack,
+    BrowserForward,
+    BrowserRefresh,
+    BrowserStop,
+    BrowserFavorites,
+    /// Any other consumer page usage ID
+    Usage(u16),
+}
+
+impl MediaAction {
+    /// The HID consumer page usage ID
+    pub fn usage(&self) -> u16 {
+        match self {
+            MediaAction::PlayPause => 0xCD,
+            MediaAction::Play => 0xB0,
+            MediaAction::Pause => 0xB1,
+            MediaAction::Stop => 0xB7,
+            MediaAction::NextTrack => 0xB5,
+            MediaAction::PrevTrack => 0xB6,
+            MediaAction::FastForward => 0xB3,
+            MediaAction::Rewind => 0xB4,
+            MediaAction::Record => 0xB2,
+            MediaAction::Eject => 0xB8,
+            MediaAction::VolumeUp => 0xE9,
+            MediaAction::VolumeDown => 0xEA,
+            MediaAction::Mute => 0xE2,
+            MediaAction::BrightnessUp => 0x6F,
+            MediaAction::BrightnessDown => 0x70,
+            MediaAction::Calculator => 0x192,
+            MediaAction::Mail => 0x18A,
+            MediaAction::MediaSelect => 0x183,
+            MediaAction::MyComputer => 0x194,
+            MediaAction::BrowserHome => 0x223,
+            MediaAction::BrowserSearch => 0x221,
+            MediaAction::BrowserBack => 0x224,
+            MediaAction::BrowserForward => 0x225,
+            MediaAction::BrowserRefresh => 0x227,
+            MediaAction::BrowserStop => 0x226,
+            MediaAction::BrowserFavorites => 0x22A,
+            MediaAction::Usage(usage) => *usage,
+        }
+    }
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyBinding {
     pub key: u32,
@@ -72,33 +522,267 @@ pub struct KeyBinding {
     pub action: Action,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[allow(dead_code)]
+impl KeyBinding {
+    /// Bind a chord such as "Ctrl+Shift+K"
+    pub fn from_chord(chord: &str, action: Action) -> Result<Self, KeyCodeError> {
+        let chord: KeyChord = chord.parse()?;
+        Ok(KeyBinding {
+            key: chord.key.vk(),
+            modifiers: chord.modifiers,
+            action,
+        })
+    }
+
+    /// Bind a physical key by its set 1 scan code, so the binding survives keyboard layout changes
+    pub fn from_scan_code(scan: u16, modifiers: KeyModifiers, action: Action) -> Self {
+        KeyBinding {
+            key: scan_code_key(scan),
+            modifiers,
+            action,
+        }
+    }
+
+    /// Bind a mouse button, e.g. a side button to a layer or macro
+    pub fn from_mouse_button(button: &MouseButton, modifiers: KeyModifiers, action: Action) -> Self {
+        KeyBinding {
+            key: mouse_button_key(button),
+            modifiers,
+            action,
+        }
+    }
+
+    /// Bind each wheel tick in a direction
+    pub fn from_wheel(direction: WheelDirection, modifiers: KeyModifiers, action: Action) -> Self {
+        KeyBinding {
+            key: wheel_key(direction),
+            modifiers,
+            action,
+        }
+    }
+
+    /// The binding's trigger written as a chord, if its key has a name
+    pub fn chord(&self) -> Option<KeyChord> {
+        KeyCode::from_vk(self.key).map(|key| KeyChord {
+            modifiers: self.modifiers.clone(),
+            key,
+        })
+    }
+}
+
+/// Modifiers a binding requires; each group can require either side or one specific side
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
 pub struct KeyModifiers {
-    pub shift: bool,
-    pub ctrl: bool,
-    pub alt: bool,
+    #[serde(default)]
+    pub shift: ModifierMatch,
+    #[serde(default)]
+    pub ctrl: ModifierMatch,
+    #[serde(default)]
+    pub alt: ModifierMatch,
+    #[serde(default)]
+    pub meta: ModifierMatch,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub enum ModifierMatch {
+    #[default]
+    Off,
+    Either,
+    Left,
+    Right,
 }
 
+impl KeyModifiers {
+    pub fn get(&self, modifier: Modifier) -> ModifierMatch {
+        match modifier {
+            Modifier::Shift => self.shift,
+            Modifier::Ctrl => self.ctrl,
+            Modifier::Alt => self.alt,
+            Modifier::Meta => self.meta,
+        }
+    }
+
+    pub fn set(&mut self, modifier: Modifier, value: ModifierMatch) {
+        match modifier {
+            Modifier::Shift => self.shift = value,
+            Modifier::Ctrl => self.ctrl = value,
+            Modifier::Alt => self.alt = value,
+            Modifier::Meta => self.meta = value,
+        }
+    }
+}
+
+#[allow(dead_code)]
 #[derive(Debug)]
 pub enum KeyCodeError {
     InvalidKeyCode(u32),
     InvalidLayerIndex(usize),
-    SystemCommandFailed,
     FileError(String),
+    OutputFailed(String),
+    InvalidCombo(Vec<u32>),
+    InvalidSequence(Vec<u32>),
+    InvalidKeyName(String),
+    UnknownMacro(String),
+    InvalidConfig(ValidationReport),
+    /// The layer already binds the encoded key
+    DuplicateBinding(usize, u32),
+}
+
+impl From<KeyParseError> for KeyCodeError {
+    fn from(error: KeyParseError) -> Self {
+        KeyCodeError::InvalidKeyName(error.to_string())
+    }
+}
+
+impl From<OutputError> for KeyCodeError {
+    fn from(error: OutputError) -> Self {
+        KeyCodeError::OutputFailed(error.to_string())
+    }
+}
+
+/// Flag marking a binding key as a physical scan code rather than a virtual key code
+const SCAN_CODE_FLAG: u32 = 0x00100000;
+
+/// The binding key for a physical set 1 scan code
+pub fn scan_code_key(scan: u16) -> u32 {
+    SCAN_CODE_FLAG | scan as u32
+}
+
+/// Flag marking a binding key as a mouse button or wheel tick
+const MOUSE_FLAG: u32 = 0x00200000;
+
+/// Set alongside `MOUSE_FLAG` for wheel ticks
+const WHEEL_FLAG: u32 = 0x00000100;
+
+/// The binding key for a mouse button
+pub fn mouse_button_key(button: &MouseButton) -> u32 {
+    MOUSE_FLAG | button.number() as u32
+}
+
+/// The binding key for one wheel tick in a direction
+pub fn wheel_key(direction: WheelDirection) -> u32 {
+    MOUSE_FLAG | WHEEL_FLAG | direction as u32
+}
+
+/// Whether an event key is a wheel tick, which has no release of its own
+fn is_wheel_key(key: u32) -> bool {
+    key & (MOUSE_FLAG | WHEEL_FLAG) == MOUSE_FLAG | WHEEL_FLAG
+}
+
+/// Modifier bits of an encoded binding key, either side, left only and right only
+const MODIFIER_MASK: u32 = 0xFF0F0000;
+
+/// An encoded binding key split into its trigger and its modifier bits
+fn split_modifier_key(key: u32) -> (u32, u32) {
+    (key & !MODIFIER_MASK, key & MODIFIER_MASK)
+}
+
+/// A readable name for an encoded binding key, such as `Ctrl+K` or `Mouse4`
+pub fn describe_key(key: u32) -> String {
+    let base = split_modifier_key(key).0;
+    let mut prefix = String::new();
+    for (bit, modifier) in MODIFIER_BITS.iter().enumerate() {
+        if key & (0x01000000 << bit) != 0 {
+            prefix += &format!("{}+", modifier.name());
+        } else if key & (0x10000000 << bit) != 0 {
+            prefix += &format!("Left{}+", modifier.name());
+        } else if key & (0x00010000 << bit) != 0 {
+            prefix += &format!("Right{}+", modifier.name());
+        }
+    }
+    let name = if base & MOUSE_FLAG != 0 && is_wheel_key(base) {
+        format!("Wheel{}", ["Up", "Down", "Left", "Right"].get((base & 0xFF) as usize).unwrap_or(&"?"))
+    } else if base & MOUSE_FLAG != 0 {
+        format!("Mouse{}", base & 0xFF)
+    } else if base & SCAN_CODE_FLAG != 0 {
+        format!("Scan{:#04x}", base & 0xFFFF)
+    } else {
+        KeyCode::from_vk(base).map_or(format!("VK{:#04x}", base), |code| code.name().to_string())
+    };
+    format!("{}{}", prefix, name)
+}
+
+/// A layer's bindings, rejecting a key bound twice instead of keeping whichever binding comes last
+fn deserialize_mappings<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<u32, Action>, D::Error> {
+    struct MappingsVisitor;
+
+    impl<'de> Visitor<'de> for MappingsVisitor {
+        type Value = HashMap<u32, Action>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "a map of encoded keys to actions")
+        }
+
+        fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<Self::Value, M::Error> {
+            let mut mappings = HashMap::new();
+            while let Some((key, action)) = map.next_entry::<u32, Action>()? {
+                if mappings.insert(key, action).is_some() {
+                    return Err(de::Error::custom(format!("{} is bound more than once", describe_key(key))));
+                }
+            }
+            Ok(mappings)
+        }
+    }
+
+    deserializer.deserialize_map(MappingsVisitor)
+}
+
+/// The layer a layer action refers to
+fn layer_target(action: &Action) -> Option<usize> {
+    match action {
+        Action::LayerSwitch(index)
+        | Action::MomentaryLayer(index)
+        | Action::ToggleLayer(index)
+        | Action::OneShotLayer(index) => Some(*index),
+        _ => None,
+    }
+}
+
+fn is_modifier(key: u32) -> bool {
+    KeyCode::from_vk(key).and_then(KeyCode::modifier).is_some()
 }
 
+#[allow(dead_code)]
 impl KeyRemapping {
     pub fn new() -> Self {
-        let default_layer = Layer {
-            name: String::from("Default"),
-            mappings: HashMap::new(),
-        };
-        
+        let default_layer = Layer::new(String::from("Default"));
+
         Self {
             layers: vec![default_layer],
             active_layer_index: 0,
             modifier_state: ModifierState::default(),
+            backend: output::default_backend(),
+            pending_tap_hold: None,
+            layer_stack: Vec::new(),
+            held_presses: HashMap::new(),
+            recording: None,
+            combos: Vec::new(),
+            pending_combo: None,
+            held_combos: Vec::new(),
+            leader: None,
+            leader_state: None,
+            hotstrings: HotstringMatcher::default(),
+            layout: KeyboardLayout::default(),
+            caps_lock: false,
+            mouse_keys: MouseKeysConfig::default(),
+            pointer_motion: None,
+            macros: HashMap::new(),
+            macro_player: None,
+            commands: CommandRunner::default(),
+            scheduled_output: Vec::new(),
+            clock: Instant::now(),
+            window: WindowContext::default(),
+            emitted: 0,
+        }
+    }
+
+    /// Route all emitted events through the given backend
+    pub fn set_output_backend(&mut self, backend: Arc<dyn OutputBackend>) {
+        if !self.layout.is_fallback() {
+            backend.set_keyboard_layout(&self.layout);
         }
+        self.backend = backend;
     }
 
     /// Load remapping configuration from file
@@ -107,11 +791,13 @@ impl KeyRemapping {
             .map_err(|e| KeyCodeError::FileError(e.to_string()))?;
         let config: Self = serde_json::from_str(&content)
             .map_err(|e| KeyCodeError::FileError(e.to_string()))?;
+        config.check()?;
         Ok(config)
     }
 
     /// Save remapping configuration to file
     pub fn save(&self, path: impl AsRef<Path>) -> Result<(), KeyCodeError> {
+        self.check()?;
         let content = serde_json::to_string_pretty(self)
             .map_err(|e| KeyCodeError::FileError(e.to_string()))?;
         fs::write(path, content)
@@ -125,6 +811,9 @@ impl KeyRemapping {
         let modified_key = self.create_modifier_key(binding.key, &binding.modifiers);
         
         if let Some(layer) = self.layers.get_mut(active_layer_index) {
+            if layer.mappings.contains_key(&modified_key) {
+                return Err(KeyCodeError::DuplicateBinding(active_layer_index, modified_key));
+            }
             layer.mappings.insert(modified_key, binding.action);
             Ok(())
         } else {
@@ -132,229 +821,2144 @@ impl KeyRemapping {
         }
     }
 
-    /// Create a unique key that includes modifier information
-    fn create_modifier_key(&self, key: u32, modifiers: &KeyModifiers) -> u32 {
-        let mut modified_key = key;
-        if modifiers.shift { modified_key |= 0x01000000; }
-        if modifiers.ctrl { modified_key |= 0x02000000; }
-        if modifiers.alt { modified_key |= 0x04000000; }
-        modified_key
+    /// Add an empty layer, returning its index
+    pub fn add_layer(&mut self, name: impl Into<String>) -> usize {
+        self.layers.push(Layer::new(name.into()));
+        self.layers.len() - 1
     }
 
-    /// Update modifier state
-    pub fn update_modifier(&mut self, key: u32, pressed: bool) {
-        match key as i32 {
-            VK_SHIFT => self.modifier_state.shift = pressed,
-            VK_CONTROL => self.modifier_state.ctrl = pressed,
-            VK_MENU => self.modifier_state.alt = pressed,
-            _ => {}
+    /// Add a key binding to a specific layer
+    pub fn add_binding_to_layer(&mut self, layer_index: usize, binding: KeyBinding) -> Result<(), KeyCodeError> {
+        let modified_key = self.create_modifier_key(binding.key, &binding.modifiers);
+        let layer = self.layers.get_mut(layer_index)
+            .ok_or(KeyCodeError::InvalidLayerIndex(layer_index))?;
+        if layer.mappings.contains_key(&modified_key) {
+            return Err(KeyCodeError::DuplicateBinding(layer_index, modified_key));
         }
+        layer.mappings.insert(modified_key, binding.action);
+        Ok(())
     }
 
-    /// Handle key press event
-    pub fn handle_key_press(&mut self, key: u32) -> Result<(), KeyCodeError> {
-        self.update_modifier(key, true);
+    /// Add a binding that replaces the layer's binding of the same key while a matching window has focus
+    pub fn add_app_binding(&mut self, layer_index: usize, condition: AppCondition, binding: KeyBinding) -> Result<(), KeyCodeError> {
+        let key = self.create_modifier_key(binding.key, &binding.modifiers);
+        let layer = self.layers.get_mut(layer_index)
+            .ok_or(KeyCodeError::InvalidLayerIndex(layer_index))?;
+        layer.overrides.push(AppBinding { key, condition, action: binding.action });
+        Ok(())
+    }
 
-        let modified_key = self.create_modifier_key(key, &KeyModifiers {
-            shift: self.modifier_state.shift,
-            ctrl: self.modifier_state.ctrl,
-            alt: self.modifier_state.alt,
-        });
+    /// Limit a layer to windows matching `condition`, or lift the limit with `None`
+    pub fn set_layer_condition(&mut self, layer_index: usize, condition: Option<AppCondition>) -> Result<(), KeyCodeError> {
+        let layer = self.layers.get_mut(layer_index)
+            .ok_or(KeyCodeError::InvalidLayerIndex(layer_index))?;
+        layer.condition = condition;
+        Ok(())
+    }
 
-        if let Some(layer) = self.layers.get(self.active_layer_index) {
-            if let Some(action) = layer.mappings.get(&modified_key) {
-                match action {
-                    Action::KeyPress(target_key) => self.send_key_event(*target_key, false),
-                    Action::KeySequence(keys) => {
-                        for key in keys {
-                            self.send_key_event(*key, false)?;
-                            self.send_key_event(*key, true)?;
-                        }
-                        Ok(())
-                    },
-                    Action::KeyCombination(keys) => {
-                        for key in keys {
-                            self.send_key_event(*key, false)?;
-                        }
-                        for key in keys.iter().rev() {
-                            self.send_key_event(*key, true)?;
-                        }
-                        Ok(())
-                    },
-                    Action::SystemCommand(command) => {
-                        std::process::Command::new("cmd")
-                            .arg("/C")
-                            .arg(command)
-                            .spawn()
-                            .map_err(|_| KeyCodeError::SystemCommandFailed)?;
-                        Ok(())
-                    },
-                    Action::MacroTrigger(_name) => {
-                        // TODO: Integrate with macro system
-                        Ok(())
-                    },
-                    Action::LayerSwitch(layer_index) => {
-                        if *layer_index < self.layers.len() {
-                            self.active_layer_index = *layer_index;
-                            Ok(())
-                        } else {
-                            Err(KeyCodeError::InvalidLayerIndex(*layer_index))
-                        }
-                    },
-                    Action::MouseButton(button) => {
-                        self.send_mouse_button(button, false)
-                    },
-                    Action::MouseMove { dx, dy } => {
-                        self.send_mouse_move(*dx, *dy)
-                    },
-                    Action::MouseWheel(delta) => {
-                        self.send_mouse_wheel(*delta)
-                    },
-                    Action::MediaControl(action) => {
-                        self.send_media_control(action)
-                    },
-                }
-            } else {
-                self.send_key_event(key, false)
-            }
-        } else {
-            Err(KeyCodeError::InvalidKeyCode(key))
-        }
+    /// Limit a layer to events from devices matching `filter`, activating it for them, or lift the limit with `None`
+    pub fn set_layer_device(&mut self, layer_index: usize, filter: Option<DeviceFilter>) -> Result<(), KeyCodeError> {
+        let layer = self.layers.get_mut(layer_index)
+            .ok_or(KeyCodeError::InvalidLayerIndex(layer_index))?;
+        layer.device = filter;
+        Ok(())
     }
 
-    /// Handle key release event
-    pub fn handle_key_release(&mut self, key: u32) -> Result<(), KeyCodeError> {
-        self.update_modifier(key, false);
+    /// The foreground window that app conditions are matched against
+    pub fn window(&self) -> &WindowContext {
+        &self.window
+    }
 
-        let modified_key = self.create_modifier_key(key, &KeyModifiers {
-            shift: self.modifier_state.shift,
-            ctrl: self.modifier_state.ctrl,
-            alt: self.modifier_state.alt,
-        });
+    pub fn set_window(&mut self, window: WindowContext) {
+        self.window = window;
+    }
 
-        if let Some(layer) = self.layers.get(self.active_layer_index) {
-            if let Some(action) = layer.mappings.get(&modified_key) {
-                match action {
-                    Action::KeyPress(target_key) => self.send_key_event(*target_key, true),
-                    Action::MouseButton(button) => self.send_mouse_button(button, true),
-                    _ => Ok(()), // Other actions don't need release handling
-                }
-            } else {
-                self.send_key_event(key, true)
-            }
-        } else {
-            Err(KeyCodeError::InvalidKeyCode(key))
+    /// Add a profile input layer as an engine layer that is active while its modifier key is held.
+    /// The modifier key is bound on the base layer, replacing any binding it had there.
+    pub fn add_input_layer(&mut self, input_layer: &InputLayer) -> Result<usize, KeyCodeError> {
+        let index = self.add_layer(input_layer.name.clone());
+        for (from, action) in &input_layer.key_mappings {
+            self.add_binding_to_layer(index, KeyBinding {
+                key: *from,
+                modifiers: KeyModifiers::default(),
+                action: action.clone(),
+            })?;
         }
+        let base = self.layers.get_mut(self.active_layer_index)
+            .ok_or(KeyCodeError::InvalidLayerIndex(self.active_layer_index))?;
+        base.mappings.insert(input_layer.modifier_key, Action::MomentaryLayer(index));
+        Ok(index)
     }
 
-    /// Send a key event using Windows API
-    fn send_key_event(&self, key: u32, key_up: bool) -> Result<(), KeyCodeError> {
-        unsafe {
-            let mut input = INPUT {
-                type_: INPUT_KEYBOARD,
-                u: std::mem::zeroed(),
-            };
-            
-            let keyboard_input = KEYBDINPUT {
-                wVk: key as u16,
-                wScan: 0,
-                dwFlags: if key_up { KEYEVENTF_KEYUP } else { 0 } | KEYEVENTF_EXTENDEDKEY,
-                time: 0,
-                dwExtraInfo: 0,
-            };
-            
-            *input.u.ki_mut() = keyboard_input;
-            
-            SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
-            Ok(())
-        }
+    /// Indices of the active layers, from the base layer up to the topmost stacked layer
+    pub fn active_layers(&self) -> Vec<usize> {
+        std::iter::once(self.active_layer_index)
+            .chain(self.layer_stack.iter().map(|active| active.index))
+            .collect()
     }
 
-    /// Send a mouse button event
-    fn send_mouse_button(&self, button: &MouseButton, up: bool) -> Result<(), KeyCodeError> {
-        unsafe {
-            let mut input = INPUT {
-                type_: INPUT_MOUSE,
-                u: std::mem::zeroed(),
-            };
+    /// Add a combo that fires when all of its keys are pressed within its window
+    pub fn add_combo(&mut self, combo: Combo) -> Result<(), KeyCodeError> {
+        if combo.keys.len() < 2 {
+            return Err(KeyCodeError::InvalidCombo(combo.keys));
+        }
+        self.combos.push(combo);
+        Ok(())
+    }
 
-            let flags = match (button, up) {
-                (MouseButton::Left, false) => MOUSEEVENTF_LEFTDOWN,
-                (MouseButton::Left, true) => MOUSEEVENTF_LEFTUP,
-                (MouseButton::Right, false) => MOUSEEVENTF_RIGHTDOWN,
-                (MouseButton::Right, true) => MOUSEEVENTF_RIGHTUP,
-                (MouseButton::Middle, false) => MOUSEEVENTF_MIDDLEDOWN,
-                (MouseButton::Middle, true) => MOUSEEVENTF_MIDDLEUP,
-                _ => return Ok(()), // Other buttons not implemented yet
-            };
+    /// Configure the leader key and its sequences
+    pub fn set_leader(&mut self, leader: LeaderConfig) {
+        self.leader = Some(leader);
+        self.leader_state = None;
+    }
 
-            let mouse_input = MOUSEINPUT {
-                dx: 0,
-                dy: 0,
-                mouseData: 0,
-                dwFlags: flags,
-                time: 0,
-                dwExtraInfo: 0,
-            };
+    /// Replace the macros that `MacroTrigger` actions run, by name
+    pub fn set_macros(&mut self, macros: HashMap<String, Macro>) {
+        self.macros = macros;
+    }
 
-            *input.u.mi_mut() = mouse_input;
-            
-            SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
-            Ok(())
-        }
+    /// Runner that command actions go through, holding the policy of which commands may run
+    pub fn command_runner(&self) -> &CommandRunner {
+        &self.commands
     }
 
-    /// Send a mouse move event
-    fn send_mouse_move(&self, dx: i32, dy: i32) -> Result<(), KeyCodeError> {
-        unsafe {
-            let mut input = INPUT {
-                type_: INPUT_MOUSE,
-                u: std::mem::zeroed(),
-            };
+    pub fn command_runner_mut(&mut self) -> &mut CommandRunner {
+        &mut self.commands
+    }
 
-            let mouse_input = MOUSEINPUT {
-                dx,
-                dy,
-                mouseData: 0,
-                dwFlags: 0x0001, // MOUSEEVENTF_MOVE
-                time: 0,
-                dwExtraInfo: 0,
-            };
+    pub fn set_command_runner(&mut self, commands: CommandRunner) {
+        self.commands = commands;
+    }
 
-            *input.u.mi_mut() = mouse_input;
-            
-            SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
-            Ok(())
-        }
+    /// Names triggered by a binding, combo or leader sequence that match no macro, sorted
+    pub fn unknown_macros(&self) -> Vec<String> {
+        let mut unknown: Vec<String> = self.bound_actions()
+            .into_iter()
+            .filter_map(|action| match action {
+                Action::MacroTrigger(name) if !self.macros.contains_key(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        unknown.sort();
+        unknown.dedup();
+        unknown
     }
 
-    /// Send a mouse wheel event
-    fn send_mouse_wheel(&self, delta: i32) -> Result<(), KeyCodeError> {
-        unsafe {
-            let mut input = INPUT {
-                type_: INPUT_MOUSE,
-                u: std::mem::zeroed(),
-            };
+    /// Every action a key can trigger, including both halves of tap-hold actions
+    fn bound_actions(&self) -> Vec<&Action> {
+        self.located_actions().into_iter().map(|(_, action)| action).collect()
+    }
 
-            let mouse_input = MOUSEINPUT {
-                dx: 0,
-                dy: 0,
-                mouseData: (delta * 120) as u32, // Convert to wheel delta
-                dwFlags: MOUSEEVENTF_WHEEL,
-                time: 0,
-                dwExtraInfo: 0,
-            };
+    /// Every action a key can trigger, with where it is bound
+    fn located_actions(&self) -> Vec<(Location, &Action)> {
+        let bindings = self.layers.iter().enumerate().flat_map(|(layer, mappings)| {
+            let overrides = mappings.overrides.iter().map(|binding| (binding.key, &binding.action));
+            mappings.mappings.iter()
+                .map(|(key, action)| (*key, action))
+                .chain(overrides)
+                .map(move |(key, action)| (Location::Binding { layer, key }, action))
+        });
+        let combos = self.combos.iter().enumerate().map(|(index, combo)| (Location::Combo(index), &combo.action));
+        let sequences = self.leader.iter().flat_map(|leader| {
+            leader.sequences.sequences().into_iter().map(|(keys, action)| (Location::LeaderSequence(keys), action))
+        });
+        let mut pending: Vec<_> = bindings.chain(combos).chain(sequences).collect();
+        let mut actions = Vec::new();
+        while let Some((location, action)) = pending.pop() {
+            if let Action::TapHold { tap, hold, .. } = action {
+                pending.push((location.clone(), tap));
+                pending.push((location.clone(), hold));
+            }
+            actions.push((location, action));
+        }
+        actions
+    }
 
-            *input.u.mi_mut() = mouse_input;
-            
-            SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
-            Ok(())
+    /// Check the configuration for problems that would otherwise only show up while typing
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        if self.active_layer_index >= self.layers.len() {
+            report.error(Location::Config, format!("The active layer {} does not exist", self.active_layer_index));
+        }
+        for (location, action) in self.located_actions() {
+            if let Some(index) = layer_target(action).filter(|index| *index >= self.layers.len()) {
+                report.error(location.clone(), format!("Refers to layer {}, but there are only {} layers", index, self.layers.len()));
+            }
+            if let Action::MediaControl(media) = action {
+                if !self.backend.supports_consumer(media.usage()) {
+                    report.warning(location, format!("{:?} cannot be sent on this platform", media));
+                }
+            }
+        }
+        self.check_duplicate_bindings(&mut report);
+        self.check_combos(&mut report);
+        self.check_layer_exits(&mut report);
+        if !ATTRIBUTES_DEVICES {
+            for (index, layer) in self.layers.iter().enumerate() {
+                if layer.device.as_ref().is_some_and(|filter| *filter != DeviceFilter::default()) {
+                    report.warning(Location::Layer(index), "Events on this platform do not say which device they came from, so the layer never applies");
+                }
+            }
         }
+        if !self.hotstrings.is_empty() && self.layout.is_fallback() {
+            report.warning(Location::Config, "The keyboard layout could not be read, so hotstrings assume typing on a US layout");
+        }
+        report
     }
 
-    /// Send a media control event
-    fn send_media_control(&self, _action: &MediaAction) -> Result<(), KeyCodeError> {
-        // TODO: Implement media control using multimedia keys or system commands
+    /// Validate, logging warnings and failing on errors
+    fn check(&self) -> Result<(), KeyCodeError> {
+        let report = self.validate();
+        if report.has_errors() {
+            return Err(KeyCodeError::InvalidConfig(report));
+        }
+        for warning in report.warnings() {
+            eprintln!("{}", warning);
+        }
         Ok(())
     }
+
+    /// Bindings of one layer that another binding of the same layer takes precedence over
+    fn check_duplicate_bindings(&self, report: &mut ValidationReport) {
+        for (index, layer) in self.layers.iter().enumerate() {
+            let mut keys: Vec<u32> = layer.mappings.keys().copied().collect();
+            keys.sort();
+            for key in keys {
+                let (base, modifier_bits) = split_modifier_key(key);
+                if base & SCAN_CODE_FLAG != 0 {
+                    let vk = KeyCode::from_scan_code(base as u16).map(KeyCode::vk);
+                    if let Some(shadowed) = vk.map(|vk| vk | modifier_bits).filter(|vk| layer.mappings.contains_key(vk)) {
+                        report.warning(
+                            Location::Binding { layer: index, key: shadowed },
+                            format!("The scan code binding {} of the same physical key takes precedence", describe_key(key)),
+                        );
+                    }
+                }
+                for bit in 0..MODIFIER_BITS.len() {
+                    let (either, left, right) = (0x01000000 << bit, 0x10000000 << bit, 0x00010000 << bit);
+                    let other = (key & !left) | right;
+                    if key & left != 0 && layer.mappings.contains_key(&other) {
+                        report.warning(
+                            Location::Binding { layer: index, key: other },
+                            format!("While both sides are held, the left side binding {} takes precedence", describe_key(key)),
+                        );
+                    }
+                    let sided = [left, right].map(|side| (key & !either) | side);
+                    for sided in sided.into_iter().filter(|sided| key & either != 0 && layer.mappings.contains_key(sided)) {
+                        report.warning(
+                            Location::Binding { layer: index, key },
+                            format!("While that side is held, the side-specific binding {} takes precedence", describe_key(sided)),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Combos that can never fire and bindings that combos delay
+    fn check_combos(&self, report: &mut ValidationReport) {
+        for (index, combo) in self.combos.iter().enumerate() {
+            let mut keys = combo.keys.clone();
+            keys.sort();
+            keys.dedup();
+            if keys.len() < combo.keys.len() {
+                report.error(Location::Combo(index), "Lists a key twice, so it can never fire");
+            }
+            if let Some(earlier) = self.combos[..index].iter().position(|other| {
+                other.keys.len() == combo.keys.len() && other.keys.iter().all(|key| combo.keys.contains(key))
+            }) {
+                report.error(Location::Combo(index), format!("Has the same keys as combo {}, which fires instead", earlier));
+            }
+            if let Some(leader) = self.leader.as_ref().filter(|leader| combo.keys.contains(&leader.key)) {
+                report.warning(
+                    Location::Combo(index),
+                    format!("Cannot start with {}, which is the leader key", describe_key(leader.key)),
+                );
+            }
+        }
+
+        for (index, layer) in self.layers.iter().enumerate() {
+            let mut keys: Vec<u32> = layer.mappings.keys().copied().collect();
+            keys.sort();
+            for key in keys {
+                let base = split_modifier_key(key).0;
+                if let Some(combo) = self.combos.iter().position(|combo| combo.keys.contains(&base)) {
+                    report.warning(
+                        Location::Binding { layer: index, key },
+                        format!("The key is part of combo {}, so its binding waits for the combo to be ruled out", combo),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Layers that toggling or switching can enter but no reachable binding can leave
+    fn check_layer_exits(&self, report: &mut ValidationReport) {
+        let actions = self.bound_actions();
+        let global: Vec<&Action> = self.located_actions()
+            .into_iter()
+            .filter(|(location, _)| !matches!(location, Location::Binding { .. }))
+            .map(|(_, action)| action)
+            .collect();
+
+        for index in (0..self.layers.len()).filter(|index| *index != self.active_layer_index) {
+            let reachable: Vec<&Action> = self.reachable_actions(index).into_iter().chain(global.iter().copied()).collect();
+            let toggled = actions.iter().any(|action| matches!(action, Action::ToggleLayer(target) if *target == index));
+            let switched = actions.iter().any(|action| matches!(action, Action::LayerSwitch(target) if *target == index));
+
+            let can_untoggle = reachable.iter().any(|action| {
+                matches!(action, Action::LayerSwitch(_)) || matches!(action, Action::ToggleLayer(target) if *target == index)
+            });
+            if toggled && !can_untoggle {
+                report.warning(Location::Layer(index), "Can be toggled on, but no binding reachable from it toggles it off or switches layers");
+            }
+            let can_switch_away = reachable.iter().any(|action| matches!(action, Action::LayerSwitch(target) if *target != index));
+            if switched && !can_switch_away {
+                report.warning(Location::Layer(index), "Can be switched to, but no binding reachable from it switches to another layer");
+            }
+        }
+    }
+
+    /// Actions bound on a layer and on every layer its bindings can stack above it
+    fn reachable_actions(&self, start: usize) -> Vec<&Action> {
+        let mut visited = HashSet::from([start]);
+        let mut queue = vec![start];
+        let mut actions = Vec::new();
+        while let Some(index) = queue.pop() {
+            let layer_actions = self.located_actions().into_iter().filter_map(|(location, action)| match location {
+                Location::Binding { layer, .. } if layer == index => Some(action),
+                _ => None,
+            });
+            for action in layer_actions {
+                let stacked = match action {
+                    Action::MomentaryLayer(target) | Action::ToggleLayer(target) | Action::OneShotLayer(target) => Some(*target),
+                    _ => None,
+                };
+                if let Some(target) = stacked.filter(|target| *target < self.layers.len() && visited.insert(*target)) {
+                    queue.push(target);
+                }
+                actions.push(action);
+            }
+        }
+        actions
+    }
+
+    /// Configure the speed of continuous pointer movement and scrolling
+    pub fn set_mouse_keys(&mut self, config: MouseKeysConfig) {
+        self.mouse_keys = config;
+    }
+
+    /// Replace the hotstring table, forgetting anything typed so far
+    pub fn set_hotstrings(&mut self, hotstrings: Vec<Hotstring>) {
+        self.hotstrings.set_hotstrings(hotstrings);
+    }
+
+    pub fn keyboard_layout(&self) -> &KeyboardLayout {
+        &self.layout
+    }
+
+    /// Use a newly read keyboard layout, both for reading typed characters and for typing text
+    pub fn set_keyboard_layout(&mut self, layout: KeyboardLayout) {
+        self.backend.set_keyboard_layout(&layout);
+        self.layout = layout;
+    }
+
+    /// Set whether Caps Lock is on; from then on the engine follows the Caps Lock presses it sees
+    pub fn set_caps_lock(&mut self, on: bool) {
+        self.caps_lock = on;
+    }
+
+    /// Create a unique key that includes modifier information.
+    /// Either-side requirements use bits 24-27, left-only 28-31 and right-only 16-19.
+    fn create_modifier_key(&self, key: u32, modifiers: &KeyModifiers) -> u32 {
+        let mut modified_key = key;
+        for (bit, modifier) in MODIFIER_BITS.iter().enumerate() {
+            match modifiers.get(*modifier) {
+                ModifierMatch::Off => {}
+                ModifierMatch::Either => modified_key |= 0x01000000 << bit,
+                ModifierMatch::Left => modified_key |= 0x10000000 << bit,
+                ModifierMatch::Right => modified_key |= 0x00010000 << bit,
+            }
+        }
+        modified_key
+    }
+
+    /// Update modifier state
+    pub fn update_modifier(&mut self, key: u32, pressed: bool) {
+        self.modifier_state.update(key, pressed);
+    }
+
+    /// Handle a captured physical event.
+    /// Returns whether a binding consumed it, in which case the original event must be suppressed.
+    /// An event whose handling emitted output is always consumed and re-emitted after that output:
+    /// a platform may deliver the original before events injected while deciding about it.
+    pub fn handle_event(&mut self, event: &InputEvent) -> Result<bool, KeyCodeError> {
+        let emitted = self.emitted;
+        if self.process_event(event)? {
+            return Ok(true);
+        }
+        if self.emitted != emitted {
+            self.pass_through(event)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Handle an event, returning whether it was consumed, without the ordering guarantee of `handle_event`
+    fn process_event(&mut self, event: &InputEvent) -> Result<bool, KeyCodeError> {
+        self.clock = event.time;
+        if self.pending_tap_hold.is_some() {
+            return self.handle_pending_tap_hold(event);
+        }
+        if self.pending_combo.is_some() {
+            return self.handle_pending_combo(event);
+        }
+        if self.leader_state.is_some() {
+            return self.handle_leader_sequence(event);
+        }
+
+        let leader = self.leader.as_ref().filter(|leader| leader.key == event.key);
+        if let (true, Some(leader)) = (event.pressed, leader) {
+            self.leader_state = Some(LeaderState {
+                keys: Vec::new(),
+                deadline: event.time + leader.timeout,
+            });
+            self.held_presses.insert(event.key, PressRecord::default());
+            return Ok(true);
+        }
+
+        if event.pressed && self.could_start_combo(event.key) {
+            let window = self.combos.iter()
+                .filter(|combo| combo.keys.contains(&event.key))
+                .map(|combo| combo.window)
+                .max()
+                .unwrap_or_default();
+            self.pending_combo = Some(PendingCombo {
+                deadline: event.time + window,
+                buffered: vec![event.clone()],
+            });
+            return Ok(true);
+        }
+
+        self.dispatch_event(event)
+    }
+
+    /// Handle an event with the bindings of the active layer, bypassing combo detection
+    fn dispatch_event(&mut self, event: &InputEvent) -> Result<bool, KeyCodeError> {
+        if event.pressed && is_wheel_key(event.key) {
+            // A wheel tick has no release, so its binding is tapped
+            let consumed = self.press_key(event)?;
+            self.release_key(event.key)?;
+            return Ok(consumed);
+        }
+        if event.pressed {
+            self.press_key(event)
+        } else {
+            self.release_key(event.key)
+        }
+    }
+
+    /// Handle key press event, returning whether a binding consumed it
+    pub fn handle_key_press(&mut self, key: u32) -> Result<bool, KeyCodeError> {
+        self.handle_event(&InputEvent::press(key))
+    }
+
+    /// Handle key release event, returning whether a binding consumed it
+    pub fn handle_key_release(&mut self, key: u32) -> Result<bool, KeyCodeError> {
+        self.handle_event(&InputEvent::release(key))
+    }
+
+    /// The next instant at which `tick` has work to do, if any
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let tap_hold = self.pending_tap_hold.as_ref().map(|pending| pending.deadline);
+        let combo = self.pending_combo.as_ref().map(|pending| pending.deadline);
+        let leader = self.leader_state.as_ref().map(|state| state.deadline);
+        let motion = self.pointer_motion.as_ref().map(|motion| motion.next_deadline(&self.mouse_keys));
+        let scheduled = self.scheduled_output.first().map(|(time, _)| *time);
+        tap_hold.into_iter().chain(combo).chain(leader).chain(motion).chain(scheduled).min()
+    }
+
+    /// Resolve decisions whose deadline has passed
+    pub fn tick(&mut self, now: Instant) -> Result<(), KeyCodeError> {
+        self.clock = now;
+        if self.pending_tap_hold.as_ref().is_some_and(|pending| now >= pending.deadline) {
+            self.resolve_tap_hold(true)?;
+        }
+        if self.pending_combo.as_ref().is_some_and(|pending| now >= pending.deadline) {
+            self.resolve_combo()?;
+        }
+        if self.leader_state.as_ref().is_some_and(|state| now >= state.deadline) {
+            self.finish_leader_sequence()?;
+        }
+        if let Some(motion) = self.pointer_motion.as_mut() {
+            if now >= motion.next_deadline(&self.mouse_keys) {
+                let events = motion.step(&self.mouse_keys, now);
+                self.emit(&events)?;
+            }
+        }
+        let due = self.scheduled_output.partition_point(|(time, _)| *time <= now);
+        for (_, events) in self.scheduled_output.drain(..due).collect::<Vec<_>>() {
+            self.emit(&events)?;
+        }
+        Ok(())
+    }
+
+    /// Look up the action bound to a key under the current modifier state,
+    /// searching the layers of the event's device, then the layer stack from the top,
+    /// falling through transparent mappings and layers limited to other windows or devices.
+    /// Unbound keys also fall through device layers, so they can remap part of a device.
+    /// Bindings to the physical scan code take precedence over bindings to the virtual key.
+    fn lookup_action(&self, key: u32, scan: u16, device: Option<&SourceDevice>) -> Result<Option<Action>, KeyCodeError> {
+        let mut triggers = vec![key];
+        if scan != 0 {
+            triggers.insert(0, scan_code_key(scan));
+        }
+        let combinations = self.modifier_state.matching_combinations();
+        let modified_keys: Vec<u32> = triggers.iter()
+            .flat_map(|trigger| combinations.iter().map(|modifiers| self.create_modifier_key(*trigger, modifiers)))
+            .collect();
+
+        let device_layers = self.layers.iter()
+            .enumerate()
+            .filter(|(_, layer)| layer.device.as_ref().is_some_and(|filter| filter.matches(device)))
+            .map(|(index, _)| index);
+        let stacked = self.layer_stack.iter().rev().map(|active| active.index);
+        for index in device_layers.chain(stacked).chain(std::iter::once(self.active_layer_index)) {
+            let layer = self.layers.get(index).ok_or(KeyCodeError::InvalidLayerIndex(index))?;
+            if layer.condition.as_ref().is_some_and(|condition| !condition.matches(&self.window))
+                || layer.device.as_ref().is_some_and(|filter| !filter.matches(device)) {
+                continue;
+            }
+            match modified_keys.iter().find_map(|modified_key| layer.binding(*modified_key, &self.window)) {
+                Some(Action::Transparent) => continue,
+                None if layer.device.is_some() => continue,
+                action => return Ok(action.cloned()),
+            }
+        }
+        Ok(None)
+    }
+
+    fn press_key(&mut self, event: &InputEvent) -> Result<bool, KeyCodeError> {
+        let key = event.key;
+        if let Some(record) = self.held_presses.get(&key) {
+            // Auto-repeat repeats the original key output, whatever the bindings say now
+            if let Some(Action::KeyPress(target_key)) = record.action {
+                self.send_key_event(target_key, false)?;
+            }
+            return Ok(true);
+        }
+
+        // A modifier key's own binding is looked up without itself counted as held
+        let action = self.lookup_action(key, event.scan, event.device.as_ref())?;
+        self.update_modifier(key, true);
+        if !is_modifier(key) {
+            // One-shot layers apply to exactly one key press; modifiers don't use them up
+            self.layer_stack.retain(|active| active.activation != LayerActivation::OneShot);
+        }
+
+        if let Some(expansion) = self.track_typed_key(key, action.as_ref()) {
+            self.expand_hotstring(&expansion)?;
+            if expansion.consume_key {
+                self.held_presses.insert(key, PressRecord::default());
+                return Ok(true);
+            }
+            if action.is_none() {
+                // The terminator is held back and typed after the replacement, as AutoHotkey does
+                let record = self.press_recorded(&Action::KeyPress(key))?;
+                self.held_presses.insert(key, record);
+                return Ok(true);
+            }
+        }
+
+        match action {
+            Some(Action::TapHold { tap, hold, timeout, policy }) => {
+                self.pending_tap_hold = Some(PendingTapHold {
+                    key,
+                    tap: *tap,
+                    hold: *hold,
+                    policy,
+                    deadline: event.time + timeout,
+                    buffered: Vec::new(),
+                });
+                Ok(true)
+            }
+            Some(action) => {
+                let record = self.press_recorded(&action)?;
+                self.held_presses.insert(key, record);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Feed the character a key press types into the hotstring buffer, returning the expansion it completes.
+    /// Keys bound to anything but another key, chords and non-character keys reset the buffer.
+    fn track_typed_key(&mut self, key: u32, action: Option<&Action>) -> Option<Expansion> {
+        let typed_key = match action {
+            None => Some(key),
+            Some(Action::KeyPress(target)) => Some(*target),
+            Some(_) => None,
+        };
+        if typed_key == Some(KeyCode::CapsLock.vk()) {
+            self.caps_lock = !self.caps_lock;
+        }
+        if self.hotstrings.is_empty() || is_modifier(key) {
+            return None;
+        }
+        let Some(typed_key) = typed_key else {
+            self.hotstrings.reset();
+            return None;
+        };
+
+        let chord = [Modifier::Ctrl, Modifier::Alt, Modifier::Meta]
+            .into_iter()
+            .any(|modifier| self.modifier_state.is_held(modifier));
+        let code = KeyCode::from_vk(typed_key).filter(|_| !chord);
+        if code == Some(KeyCode::Backspace) {
+            self.hotstrings.backspace();
+            return None;
+        }
+        let shift = self.modifier_state.is_held(Modifier::Shift);
+        match code.and_then(|code| self.layout.typed_char(code, shift, self.caps_lock)) {
+            Some(ch) => self.hotstrings.type_char(ch),
+            None => {
+                self.hotstrings.reset();
+                None
+            }
+        }
+    }
+
+    /// Erase a hotstring's abbreviation and type its replacement
+    fn expand_hotstring(&mut self, expansion: &Expansion) -> Result<(), KeyCodeError> {
+        let backspace = KeyCode::Backspace.vk();
+        for _ in 0..expansion.erase {
+            self.send_key_event(backspace, false)?;
+            self.send_key_event(backspace, true)?;
+        }
+        if !expansion.text.is_empty() {
+            self.emit(&[OutputEvent::Text { text: expansion.text.clone() }])?;
+        }
+        Ok(())
+    }
+
+    fn release_key(&mut self, key: u32) -> Result<bool, KeyCodeError> {
+        self.update_modifier(key, false);
+
+        if let Some(record) = self.held_presses.remove(&key) {
+            self.release_recorded(record)?;
+            return Ok(true);
+        }
+
+        if let Some(index) = self.held_combos.iter().position(|held| held.keys.contains(&key)) {
+            // The action ends with the first released key; the other releases are swallowed
+            let held = &mut self.held_combos[index];
+            held.keys.retain(|k| *k != key);
+            let record = held.record.take();
+            if held.keys.is_empty() {
+                self.held_combos.remove(index);
+            }
+            if let Some(record) = record {
+                self.release_recorded(record)?;
+            }
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Perform the press half of an action
+    fn press_action(&mut self, action: &Action) -> Result<(), KeyCodeError> {
+        match action {
+            Action::KeyPress(target_key) => self.send_key_event(*target_key, false)?,
+            Action::ScanCodePress(scan) => self.send_scan_code(*scan, false)?,
+            Action::KeySequence(keys) => {
+                for key in keys {
+                    self.send_key_event(*key, false)?;
+                    self.send_key_event(*key, true)?;
+                }
+            },
+            Action::KeyCombination(keys) => {
+                for key in keys {
+                    self.send_key_event(*key, false)?;
+                }
+                for key in keys.iter().rev() {
+                    self.send_key_event(*key, true)?;
+                }
+            },
+            Action::TypeText(text) => {
+                self.emit(&[OutputEvent::Text { text: text.clone() }])?
+            },
+            Action::SystemCommand(command) => self.commands.spawn(CommandSpec::shell(command)),
+            Action::RunCommand(spec) => self.commands.spawn(spec.clone()),
+            Action::MacroTrigger(name) => {
+                let macro_seq = self.macros.get(name)
+                    .cloned()
+                    .ok_or_else(|| KeyCodeError::UnknownMacro(name.clone()))?;
+                self.macro_player
+                    .get_or_insert_with(MacroPlayer::spawn)
+                    .play(macro_seq, Arc::clone(&self.backend));
+            },
+            Action::LayerSwitch(layer_index) => {
+                self.check_layer_index(*layer_index)?;
+                self.active_layer_index = *layer_index;
+                self.layer_stack.clear();
+            },
+            Action::MomentaryLayer(layer_index) => {
+                self.push_layer(*layer_index, LayerActivation::Momentary)?
+            },
+            Action::ToggleLayer(layer_index) => {
+                let toggled = ActiveLayer { index: *layer_index, activation: LayerActivation::Toggle };
+                if let Some(position) = self.layer_stack.iter().position(|active| *active == toggled) {
+                    self.layer_stack.remove(position);
+                } else {
+                    self.push_layer(*layer_index, LayerActivation::Toggle)?;
+                }
+            },
+            Action::OneShotLayer(layer_index) => {
+                self.push_layer(*layer_index, LayerActivation::OneShot)?
+            },
+            Action::Transparent => {},
+            Action::MouseButton(button) => {
+                self.send_mouse_button(button, false)?
+            },
+            Action::MouseMove { dx, dy } => {
+                self.send_mouse_move(*dx, *dy)?
+            },
+            Action::ContinuousMouseMove { dx, dy } => {
+                self.start_motion(MotionKind::Move, *dx, *dy)?
+            },
+            Action::MouseWheel(delta) => {
+                self.send_mouse_wheel(*delta)?
+            },
+            Action::Scroll(scroll) => {
+                self.send_scroll(scroll)?
+            },
+            Action::ContinuousScroll { dx, dy } => {
+                self.start_motion(MotionKind::Scroll, *dx, *dy)?
+            },
+            Action::MediaControl(action) => {
+                self.send_media_control(action)?
+            },
+            Action::TapHold { tap, .. } => {
+                // Without a physical key to time, a tap-hold behaves as its tap action
+                self.tap_action(tap)?;
+            },
+        }
+        Ok(())
+    }
+
+    /// Perform an action's press, recording the outputs it leaves down
+    fn press_recorded(&mut self, action: &Action) -> Result<PressRecord, KeyCodeError> {
+        let outer = self.recording.replace(Vec::new());
+        let result = self.press_action(action);
+        let outputs = std::mem::replace(&mut self.recording, outer).unwrap_or_default();
+        result?;
+        Ok(PressRecord {
+            action: Some(action.clone()),
+            outputs,
+        })
+    }
+
+    /// Undo a recorded press: release exactly its outputs in reverse order, then end the action
+    fn release_recorded(&mut self, record: PressRecord) -> Result<(), KeyCodeError> {
+        for output in record.outputs.iter().rev() {
+            match output {
+                HeldOutput::Key(key) => self.send_key_event(*key, true)?,
+                HeldOutput::ScanCode(scan) => self.send_scan_code(*scan, true)?,
+                HeldOutput::MouseButton(button) => self.send_mouse_button(button, true)?,
+            }
+        }
+        if let Some(action) = &record.action {
+            self.release_action(action)?;
+        }
+        Ok(())
+    }
+
+    /// Press and immediately release an action
+    fn tap_action(&mut self, action: &Action) -> Result<(), KeyCodeError> {
+        let record = self.press_recorded(action)?;
+        self.release_recorded(record)
+    }
+
+    /// Take over the configuration of `config` while keeping this engine's runtime state,
+    /// so keys held through the old bindings still release what they pressed.
+    /// Undecided tap-holds, combos and leader sequences are settled under the old bindings first.
+    pub fn swap_config(&mut self, config: KeyRemapping) -> Result<(), KeyCodeError> {
+        if self.pending_tap_hold.is_some() {
+            self.resolve_tap_hold(true)?;
+        }
+        self.resolve_combo()?;
+        self.finish_leader_sequence()?;
+
+        self.layers = config.layers;
+        self.active_layer_index = config.active_layer_index;
+        self.combos = config.combos;
+        self.leader = config.leader;
+        self.mouse_keys = config.mouse_keys;
+        self.hotstrings = config.hotstrings;
+        self.macros = config.macros;
+        let layer_count = self.layers.len();
+        self.layer_stack.retain(|active| active.index < layer_count);
+        Ok(())
+    }
+
+    /// Release every output held by a physical key, e.g. before the engine stops receiving input
+    pub fn release_held_keys(&mut self) -> Result<(), KeyCodeError> {
+        let presses: Vec<PressRecord> = self.held_presses.drain().map(|(_, record)| record).collect();
+        let combos: Vec<PressRecord> = self.held_combos.drain(..).filter_map(|held| held.record).collect();
+        for record in presses.into_iter().chain(combos) {
+            self.release_recorded(record)?;
+        }
+        Ok(())
+    }
+
+    /// End the effects of an action that outlive its outputs, such as a momentary layer
+    fn release_action(&mut self, action: &Action) -> Result<(), KeyCodeError> {
+        match action {
+            Action::MomentaryLayer(layer_index) => {
+                let momentary = ActiveLayer { index: *layer_index, activation: LayerActivation::Momentary };
+                if let Some(position) = self.layer_stack.iter().rposition(|active| *active == momentary) {
+                    self.layer_stack.remove(position);
+                }
+            }
+            Action::ContinuousMouseMove { dx, dy } => self.stop_motion(MotionKind::Move, *dx, *dy),
+            Action::ContinuousScroll { dx, dy } => self.stop_motion(MotionKind::Scroll, *dx, *dy),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Hold a mouse-keys direction; a new movement steps once right away so a tap still moves
+    fn start_motion(&mut self, kind: MotionKind, dx: i32, dy: i32) -> Result<(), KeyCodeError> {
+        let now = self.clock;
+        let starting = self.pointer_motion.is_none();
+        let motion = self.pointer_motion.get_or_insert_with(|| PointerMotion::new(now));
+        motion.press(kind, dx, dy);
+        if starting {
+            let events = motion.first_step(&self.mouse_keys, now);
+            self.emit(&events)?;
+        }
+        Ok(())
+    }
+
+    /// Release a mouse-keys direction, ending the movement and its acceleration once none is held
+    fn stop_motion(&mut self, kind: MotionKind, dx: i32, dy: i32) {
+        if let Some(motion) = self.pointer_motion.as_mut() {
+            motion.release(kind, dx, dy);
+            if motion.is_idle() {
+                self.pointer_motion = None;
+            }
+        }
+    }
+
+    fn check_layer_index(&self, layer_index: usize) -> Result<(), KeyCodeError> {
+        if layer_index < self.layers.len() {
+            Ok(())
+        } else {
+            Err(KeyCodeError::InvalidLayerIndex(layer_index))
+        }
+    }
+
+    fn push_layer(&mut self, index: usize, activation: LayerActivation) -> Result<(), KeyCodeError> {
+        self.check_layer_index(index)?;
+        self.layer_stack.push(ActiveLayer { index, activation });
+        Ok(())
+    }
+
+    /// Decide an undecided tap-hold key from the next event
+    fn handle_pending_tap_hold(&mut self, event: &InputEvent) -> Result<bool, KeyCodeError> {
+        let Some(pending) = self.pending_tap_hold.as_mut() else {
+            return Ok(false);
+        };
+
+        if event.time >= pending.deadline {
+            self.resolve_tap_hold(true)?;
+            return self.process_event(event);
+        }
+
+        if event.key == pending.key {
+            if !event.pressed {
+                self.update_modifier(event.key, false);
+                self.resolve_tap_hold(false)?;
+            }
+            // Auto-repeat of the undecided key is swallowed
+            return Ok(true);
+        }
+
+        if event.pressed {
+            if pending.policy == TapHoldPolicy::HoldOnOtherKeyPress {
+                self.resolve_tap_hold(true)?;
+                return self.process_event(event);
+            }
+        } else {
+            let pressed_while_pending = pending.buffered.iter().any(|e| e.key == event.key && e.pressed);
+            if pressed_while_pending && pending.policy == TapHoldPolicy::PermissiveHold {
+                self.resolve_tap_hold(true)?;
+                return self.process_event(event);
+            }
+        }
+
+        pending.buffered.push(event.clone());
+        Ok(true)
+    }
+
+    /// Settle the pending tap-hold as a hold or a tap, then replay the events held back meanwhile
+    fn resolve_tap_hold(&mut self, hold: bool) -> Result<(), KeyCodeError> {
+        let Some(pending) = self.pending_tap_hold.take() else {
+            return Ok(());
+        };
+
+        if hold {
+            let record = self.press_recorded(&pending.hold)?;
+            self.held_presses.insert(pending.key, record);
+        } else {
+            self.tap_action(&pending.tap)?;
+        }
+
+        for event in &pending.buffered {
+            self.replay_event(event)?;
+        }
+        Ok(())
+    }
+
+    /// Collect the next key of a leader sequence, firing its action once the sequence is complete
+    fn handle_leader_sequence(&mut self, event: &InputEvent) -> Result<bool, KeyCodeError> {
+        let (Some(state), Some(leader)) = (self.leader_state.as_mut(), self.leader.as_ref()) else {
+            self.leader_state = None;
+            return self.process_event(event);
+        };
+
+        if event.time >= state.deadline {
+            self.finish_leader_sequence()?;
+            return self.process_event(event);
+        }
+
+        // Releases and modifiers pass through so held keys stay balanced
+        if !event.pressed || is_modifier(event.key) {
+            return self.dispatch_event(event);
+        }
+
+        self.held_presses.insert(event.key, PressRecord::default());
+        if event.key == leader.cancel_key {
+            self.leader_state = None;
+            return Ok(true);
+        }
+
+        state.keys.push(event.key);
+        state.deadline = event.time + leader.timeout;
+        match leader.sequences.get(&state.keys) {
+            Some(node) if node.children.is_empty() => self.finish_leader_sequence()?,
+            Some(_) => {} // Wait for a longer sequence
+            None => self.leader_state = None,
+        }
+        Ok(true)
+    }
+
+    /// Leave leader mode, performing the action of the sequence typed so far if it has one
+    fn finish_leader_sequence(&mut self) -> Result<(), KeyCodeError> {
+        let Some(state) = self.leader_state.take() else {
+            return Ok(());
+        };
+        let action = self.leader.as_ref()
+            .and_then(|leader| leader.sequences.get(&state.keys))
+            .and_then(|node| node.action.clone());
+
+        if let Some(action) = action {
+            self.tap_action(&action)?;
+        }
+        Ok(())
+    }
+
+    /// Whether a press of `key` may be the first key of some combo
+    fn could_start_combo(&self, key: u32) -> bool {
+        self.combos.iter().any(|combo| combo.keys.contains(&key))
+    }
+
+    /// The distinct keys pressed while the combo is pending, in press order
+    fn pending_combo_keys(&self) -> Vec<u32> {
+        let mut keys = Vec::new();
+        for event in self.pending_combo.iter().flat_map(|pending| &pending.buffered) {
+            if event.pressed && !keys.contains(&event.key) {
+                keys.push(event.key);
+            }
+        }
+        keys
+    }
+
+    /// Index of the combo whose key set is exactly `keys`
+    fn matching_combo(&self, keys: &[u32]) -> Option<usize> {
+        self.combos.iter().position(|combo| {
+            combo.keys.len() == keys.len() && keys.iter().all(|key| combo.keys.contains(key))
+        })
+    }
+
+    /// Collect the next event into the pending combo, or settle it if the event cannot belong to one
+    fn handle_pending_combo(&mut self, event: &InputEvent) -> Result<bool, KeyCodeError> {
+        let Some(pending) = self.pending_combo.as_ref() else {
+            return Ok(false);
+        };
+
+        // An event that settles the combo is held back and replayed after the buffered presses
+        if event.time >= pending.deadline || !event.pressed {
+            self.resolve_combo()?;
+            self.replay_event(event)?;
+            return Ok(true);
+        }
+
+        let mut keys = self.pending_combo_keys();
+        if keys.contains(&event.key) {
+            // Auto-repeat while the combo is undecided is swallowed
+            return Ok(true);
+        }
+        keys.push(event.key);
+
+        let extendable = |combo: &Combo| {
+            combo.keys.len() > keys.len() && keys.iter().all(|key| combo.keys.contains(key))
+        };
+        let can_grow = self.combos.iter().any(extendable);
+        let exact = self.matching_combo(&keys);
+
+        if exact.is_none() && !can_grow {
+            self.resolve_combo()?;
+            self.replay_event(event)?;
+            return Ok(true);
+        }
+
+        if let Some(pending) = self.pending_combo.as_mut() {
+            pending.buffered.push(event.clone());
+        }
+        if exact.is_some() && !can_grow {
+            self.resolve_combo()?;
+        }
+        Ok(true)
+    }
+
+    /// Fire the combo matching the held-back presses, or replay them in order if none matches
+    fn resolve_combo(&mut self) -> Result<(), KeyCodeError> {
+        let keys = self.pending_combo_keys();
+        let Some(pending) = self.pending_combo.take() else {
+            return Ok(());
+        };
+
+        if let Some(index) = self.matching_combo(&keys) {
+            let action = self.combos[index].action.clone();
+            let record = self.press_recorded(&action)?;
+            self.held_combos.push(HeldCombo { keys, record: Some(record) });
+            return Ok(());
+        }
+
+        // The first key is settled as a plain press; the rest may still start another combo
+        let mut events = pending.buffered.into_iter();
+        if let Some(first) = events.next() {
+            if !self.dispatch_event(&first)? {
+                self.pass_through(&first)?;
+            }
+        }
+        for event in events {
+            self.replay_event(&event)?;
+        }
+        Ok(())
+    }
+
+    /// Process an event whose original was suppressed, emitting it ourselves if nothing consumes it
+    fn replay_event(&mut self, event: &InputEvent) -> Result<(), KeyCodeError> {
+        if !self.process_event(event)? {
+            self.pass_through(event)?;
+        }
+        Ok(())
+    }
+
+    /// Emit a suppressed event unchanged, as the system would have received it
+    fn pass_through(&mut self, event: &InputEvent) -> Result<(), KeyCodeError> {
+        if event.key & MOUSE_FLAG == 0 {
+            // The scan code keeps keys that share a virtual key apart, such as NumpadEnter and Enter
+            if event.scan != 0 {
+                return self.send_scan_code(event.scan, !event.pressed);
+            }
+            return self.send_key_event(event.key, !event.pressed);
+        }
+        if is_wheel_key(event.key) {
+            let wheel = match event.key & 0x3 {
+                0 => OutputEvent::MouseWheel { delta: 120 },
+                1 => OutputEvent::MouseWheel { delta: -120 },
+                2 => OutputEvent::MouseHWheel { delta: -120 },
+                _ => OutputEvent::MouseHWheel { delta: 120 },
+            };
+            self.emit(&[wheel])?;
+            return Ok(());
+        }
+        match MouseButton::from_number((event.key & 0xFF) as u8) {
+            Some(button) => self.send_mouse_button(&button, !event.pressed),
+            None => Ok(()),
+        }
+    }
+
+    /// Track an output going down or up while a press is being recorded
+    fn note_output(&mut self, output: HeldOutput, up: bool) {
+        let Some(recording) = self.recording.as_mut() else {
+            return;
+        };
+        if !up {
+            recording.push(output);
+        } else if let Some(position) = recording.iter().rposition(|held| *held == output) {
+            recording.remove(position);
+        }
+    }
+
+    /// Send events through the output backend, counting them for `handle_event`
+    fn emit(&mut self, events: &[OutputEvent]) -> Result<(), OutputError> {
+        self.emitted += 1;
+        self.backend.send(events)
+    }
+
+    /// Send a key event through the output backend
+    fn send_key_event(&mut self, key: u32, key_up: bool) -> Result<(), KeyCodeError> {
+        self.emit(&[OutputEvent::Key { key, up: key_up }])?;
+        self.note_output(HeldOutput::Key(key), key_up);
+        Ok(())
+    }
+
+    /// Send a physical key event by scan code
+    fn send_scan_code(&mut self, scan: u16, up: bool) -> Result<(), KeyCodeError> {
+        self.emit(&[OutputEvent::ScanCode { scan, up }])?;
+        self.note_output(HeldOutput::ScanCode(scan), up);
+        Ok(())
+    }
+
+    /// Send a mouse button event
+    fn send_mouse_button(&mut self, button: &MouseButton, up: bool) -> Result<(), KeyCodeError> {
+        self.emit(&[OutputEvent::MouseButton { button: button.clone(), up }])?;
+        self.note_output(HeldOutput::MouseButton(button.clone()), up);
+        Ok(())
+    }
+
+    /// Send a mouse move event
+    fn send_mouse_move(&mut self, dx: i32, dy: i32) -> Result<(), KeyCodeError> {
+        self.emit(&[OutputEvent::MouseMove { dx, dy }])?;
+        Ok(())
+    }
+
+    /// Send a mouse wheel event
+    fn send_mouse_wheel(&mut self, delta: i32) -> Result<(), KeyCodeError> {
+        // Convert notches to wheel delta
+        self.emit(&[OutputEvent::MouseWheel { delta: delta * 120 }])?;
+        Ok(())
+    }
+
+    /// Send the first step of a scroll and schedule the rest for `tick`
+    fn send_scroll(&mut self, scroll: &Scroll) -> Result<(), KeyCodeError> {
+        let mut at = self.clock;
+        for (delay, events) in scroll.steps() {
+            at += delay;
+            if at <= self.clock {
+                self.emit(&events)?;
+            } else {
+                let index = self.scheduled_output.partition_point(|(time, _)| *time <= at);
+                self.scheduled_output.insert(index, (at, events));
+            }
+        }
+        Ok(())
+    }
+
+    /// Tap a consumer control key
+    fn send_media_control(&mut self, action: &MediaAction) -> Result<(), KeyCodeError> {
+        let usage = action.usage();
+        self.emit(&[
+            OutputEvent::Consumer { usage, up: false },
+            OutputEvent::Consumer { usage, up: true },
+        ])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keycode::consumer;
+    use crate::r#macro::MacroAction;
+    use crate::device::DeviceType;
+    use crate::validation::Severity;
+    use crate::output::RecordingBackend;
+
+    fn engine_with_recorder() -> (KeyRemapping, Arc<RecordingBackend>) {
+        let recorder = Arc::new(RecordingBackend::new());
+        let mut engine = KeyRemapping::new();
+        engine.set_output_backend(recorder.clone());
+        (engine, recorder)
+    }
+
+    fn bind(engine: &mut KeyRemapping, key: u32, action: Action) {
+        engine.add_binding(KeyBinding {
+            key,
+            modifiers: KeyModifiers::default(),
+            action,
+        }).unwrap();
+    }
+
+    #[test]
+    fn key_press_binding_emits_target_key() {
+        let (mut engine, recorder) = engine_with_recorder();
+        bind(&mut engine, 0x41, Action::KeyPress(0x42));
+
+        engine.handle_key_press(0x41).unwrap();
+        engine.handle_key_release(0x41).unwrap();
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: 0x42, up: false },
+            OutputEvent::Key { key: 0x42, up: true },
+        ]);
+    }
+
+    #[test]
+    fn chord_binding_matches_held_modifiers() {
+        let (mut engine, recorder) = engine_with_recorder();
+        let binding = KeyBinding::from_chord("Ctrl+Shift+K", Action::KeyPress(KeyCode::F13.vk())).unwrap();
+        assert_eq!(binding.chord().unwrap().to_string(), "Ctrl+Shift+K");
+        engine.add_binding(binding).unwrap();
+
+        assert!(!engine.handle_key_press(KeyCode::K.vk()).unwrap());
+        engine.handle_key_press(KeyCode::LeftCtrl.vk()).unwrap();
+        engine.handle_key_press(KeyCode::RightShift.vk()).unwrap();
+        assert!(engine.handle_key_press(KeyCode::K.vk()).unwrap());
+
+        assert_eq!(recorder.events(), vec![OutputEvent::Key { key: KeyCode::F13.vk(), up: false }]);
+        assert!(matches!(
+            KeyBinding::from_chord("Ctrl+Nope", Action::Transparent),
+            Err(KeyCodeError::InvalidKeyName(_))
+        ));
+    }
+
+    #[test]
+    fn sided_modifier_binding_ignores_the_other_side() {
+        let (mut engine, recorder) = engine_with_recorder();
+        engine.add_binding(KeyBinding::from_chord("AltGr+E", Action::KeyPress(KeyCode::F14.vk())).unwrap()).unwrap();
+
+        engine.handle_key_press(KeyCode::LeftAlt.vk()).unwrap();
+        assert!(!engine.handle_key_press(KeyCode::E.vk()).unwrap());
+        engine.handle_key_release(KeyCode::E.vk()).unwrap();
+        engine.handle_key_release(KeyCode::LeftAlt.vk()).unwrap();
+
+        engine.handle_key_press(KeyCode::RightAlt.vk()).unwrap();
+        assert!(engine.handle_key_press(KeyCode::E.vk()).unwrap());
+
+        assert_eq!(recorder.events(), vec![OutputEvent::Key { key: KeyCode::F14.vk(), up: false }]);
+    }
+
+    #[test]
+    fn releasing_one_side_keeps_the_other_held() {
+        let (mut engine, recorder) = engine_with_recorder();
+        engine.add_binding(KeyBinding::from_chord("Shift+Super+S", Action::KeyPress(KeyCode::F15.vk())).unwrap()).unwrap();
+
+        engine.handle_key_press(KeyCode::LeftShift.vk()).unwrap();
+        engine.handle_key_press(KeyCode::RightShift.vk()).unwrap();
+        engine.handle_key_release(KeyCode::LeftShift.vk()).unwrap();
+        engine.handle_key_press(KeyCode::RightMeta.vk()).unwrap();
+        assert!(engine.handle_key_press(KeyCode::S.vk()).unwrap());
+
+        assert_eq!(recorder.events(), vec![OutputEvent::Key { key: KeyCode::F15.vk(), up: false }]);
+    }
+
+    #[test]
+    fn modifier_key_binding_matches_without_itself_held() {
+        let (mut engine, recorder) = engine_with_recorder();
+        bind(&mut engine, KeyCode::RightCtrl.vk(), Action::KeyPress(KeyCode::Menu.vk()));
+
+        assert!(engine.handle_key_press(KeyCode::RightCtrl.vk()).unwrap());
+        assert!(engine.handle_key_release(KeyCode::RightCtrl.vk()).unwrap());
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: KeyCode::Menu.vk(), up: false },
+            OutputEvent::Key { key: KeyCode::Menu.vk(), up: true },
+        ]);
+    }
+
+    #[test]
+    fn release_after_modifier_change_releases_original_output() {
+        let (mut engine, recorder) = engine_with_recorder();
+        engine.add_binding(KeyBinding::from_chord("Ctrl+A", Action::KeyPress(KeyCode::F13.vk())).unwrap()).unwrap();
+        bind(&mut engine, KeyCode::A.vk(), Action::KeyPress(KeyCode::F14.vk()));
+
+        engine.handle_key_press(KeyCode::LeftCtrl.vk()).unwrap();
+        engine.handle_key_press(KeyCode::A.vk()).unwrap();
+        engine.handle_key_release(KeyCode::LeftCtrl.vk()).unwrap();
+        assert!(engine.handle_key_release(KeyCode::A.vk()).unwrap());
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: KeyCode::F13.vk(), up: false },
+            OutputEvent::Key { key: KeyCode::F13.vk(), up: true },
+        ]);
+    }
+
+    #[test]
+    fn auto_repeat_is_released_once() {
+        let (mut engine, recorder) = engine_with_recorder();
+        bind(&mut engine, 0x41, Action::KeyPress(0x42));
+
+        engine.handle_key_press(0x41).unwrap();
+        engine.handle_key_press(0x41).unwrap();
+        engine.handle_key_release(0x41).unwrap();
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: 0x42, up: false },
+            OutputEvent::Key { key: 0x42, up: false },
+            OutputEvent::Key { key: 0x42, up: true },
+        ]);
+    }
+
+    #[test]
+    fn release_held_keys_releases_everything_still_down() {
+        let (mut engine, recorder) = engine_with_recorder();
+        bind(&mut engine, 0x41, Action::KeyPress(0x42));
+        bind(&mut engine, 0x43, Action::MouseButton(MouseButton::Left));
+        bind(&mut engine, 0x44, Action::KeySequence(vec![0x45]));
+
+        engine.handle_key_press(0x41).unwrap();
+        engine.handle_key_press(0x43).unwrap();
+        engine.handle_key_press(0x44).unwrap();
+        recorder.take();
+        engine.release_held_keys().unwrap();
+
+        let mut released = recorder.events();
+        released.sort_by_key(|event| format!("{:?}", event));
+        assert_eq!(released, vec![
+            OutputEvent::Key { key: 0x42, up: true },
+            OutputEvent::MouseButton { button: MouseButton::Left, up: true },
+        ]);
+        assert!(!engine.handle_key_release(0x41).unwrap());
+    }
+
+    #[test]
+    fn scan_code_binding_follows_the_physical_key() {
+        let (mut engine, recorder) = engine_with_recorder();
+        bind(&mut engine, KeyCode::Q.vk(), Action::KeyPress(KeyCode::F13.vk()));
+        engine.add_binding(KeyBinding::from_scan_code(
+            KeyCode::A.scan_code(),
+            KeyModifiers::default(),
+            Action::ScanCodePress(KeyCode::NumpadEnter.scan_code()),
+        )).unwrap();
+
+        // On AZERTY the key in the A position reports VK_Q
+        let azerty_a = InputEvent { scan: KeyCode::A.scan_code(), ..InputEvent::press(KeyCode::Q.vk()) };
+        assert!(engine.handle_event(&azerty_a).unwrap());
+        assert!(engine.handle_event(&InputEvent { pressed: false, ..azerty_a }).unwrap());
+        tap(&mut engine, KeyCode::Q.vk());
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::ScanCode { scan: 0xE01C, up: false },
+            OutputEvent::ScanCode { scan: 0xE01C, up: true },
+            OutputEvent::Key { key: KeyCode::F13.vk(), up: false },
+            OutputEvent::Key { key: KeyCode::F13.vk(), up: true },
+        ]);
+    }
+
+    #[test]
+    fn unmapped_key_is_not_consumed() {
+        let (mut engine, recorder) = engine_with_recorder();
+
+        assert!(!engine.handle_key_press(0x43).unwrap());
+        assert!(!engine.handle_key_release(0x43).unwrap());
+        assert!(recorder.events().is_empty());
+    }
+
+    #[test]
+    fn key_combination_releases_in_reverse_order() {
+        let (mut engine, recorder) = engine_with_recorder();
+        bind(&mut engine, 0x70, Action::KeyCombination(vec![VK_CONTROL, 0x43]));
+
+        engine.handle_key_press(0x70).unwrap();
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: VK_CONTROL, up: false },
+            OutputEvent::Key { key: 0x43, up: false },
+            OutputEvent::Key { key: 0x43, up: true },
+            OutputEvent::Key { key: VK_CONTROL, up: true },
+        ]);
+    }
+
+    #[test]
+    fn mouse_wheel_is_scaled_to_wheel_delta() {
+        let (mut engine, recorder) = engine_with_recorder();
+        bind(&mut engine, 0x71, Action::MouseWheel(-2));
+
+        engine.handle_key_press(0x71).unwrap();
+
+        assert_eq!(recorder.events(), vec![OutputEvent::MouseWheel { delta: -240 }]);
+    }
+
+    #[test]
+    fn media_actions_tap_their_consumer_usage() {
+        let (mut engine, recorder) = engine_with_recorder();
+        bind(&mut engine, 0x73, Action::MediaControl(MediaAction::BrightnessUp));
+        bind(&mut engine, 0x74, Action::MediaControl(MediaAction::Usage(0x1A7)));
+
+        tap(&mut engine, 0x73);
+        tap(&mut engine, 0x74);
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Consumer { usage: 0x6F, up: false },
+            OutputEvent::Consumer { usage: 0x6F, up: true },
+            OutputEvent::Consumer { usage: 0x1A7, up: false },
+            OutputEvent::Consumer { usage: 0x1A7, up: true },
+        ]);
+        assert_eq!(KeyCode::from_hid(consumer(MediaAction::PlayPause.usage())), Some(KeyCode::MediaPlayPause));
+        assert_eq!(KeyCode::from_hid(consumer(MediaAction::Calculator.usage())), Some(KeyCode::LaunchApp2));
+    }
+
+    /// A backend that, like SendInput, has no way to eject media
+    #[derive(Debug)]
+    struct NoEjectBackend;
+
+    impl OutputBackend for NoEjectBackend {
+        fn send(&self, _events: &[OutputEvent]) -> Result<(), OutputError> {
+            Ok(())
+        }
+
+        fn supports_consumer(&self, usage: u16) -> bool {
+            usage != MediaAction::Eject.usage()
+        }
+    }
+
+    #[test]
+    fn media_actions_the_backend_cannot_send_are_reported() {
+        let mut engine = KeyRemapping::new();
+        engine.set_output_backend(Arc::new(NoEjectBackend));
+        bind(&mut engine, 0x73, Action::MediaControl(MediaAction::PlayPause));
+        bind(&mut engine, 0x74, Action::MediaControl(MediaAction::Eject));
+
+        let flagged: Vec<Location> = engine.validate().warnings().map(|warning| warning.location.clone()).collect();
+        assert_eq!(flagged, vec![Location::Binding { layer: 0, key: 0x74 }]);
+    }
+
+    #[test]
+    fn mouse_buttons_and_wheel_ticks_trigger_bindings() {
+        let (mut engine, recorder) = engine_with_recorder();
+        let layer = engine.add_layer("Mouse");
+        let back = mouse_button_key(&MouseButton::Back);
+        let wheel_up = InputEvent::press(wheel_key(WheelDirection::Up));
+        engine.add_binding(KeyBinding::from_mouse_button(&MouseButton::Back, KeyModifiers::default(), Action::MomentaryLayer(layer))).unwrap();
+        engine.add_binding(KeyBinding::from_wheel(WheelDirection::Up, KeyModifiers::default(), Action::KeyPress(VK_J))).unwrap();
+        engine.add_binding_to_layer(layer, KeyBinding::from_wheel(
+            WheelDirection::Up,
+            KeyModifiers::default(),
+            Action::MouseButton(MouseButton::Forward),
+        )).unwrap();
+
+        // A wheel tick has no release event, so its binding is tapped
+        assert!(engine.handle_event(&wheel_up).unwrap());
+        assert!(engine.handle_key_press(back).unwrap());
+        assert!(engine.handle_event(&wheel_up).unwrap());
+        assert!(engine.handle_key_release(back).unwrap());
+        assert!(!engine.handle_event(&InputEvent::press(wheel_key(WheelDirection::Down))).unwrap());
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: VK_J, up: false },
+            OutputEvent::Key { key: VK_J, up: true },
+            OutputEvent::MouseButton { button: MouseButton::Forward, up: false },
+            OutputEvent::MouseButton { button: MouseButton::Forward, up: true },
+        ]);
+        assert_eq!(engine.active_layers(), vec![0]);
+    }
+
+    #[test]
+    fn mouse_keys_move_until_released() {
+        let (mut engine, recorder) = engine_with_recorder();
+        engine.set_mouse_keys(MouseKeysConfig {
+            initial_speed: 1000.0,
+            max_speed: 1000.0,
+            interval: Duration::from_millis(10),
+            ..MouseKeysConfig::default()
+        });
+        bind(&mut engine, VK_J, Action::ContinuousMouseMove { dx: 0, dy: 1 });
+        let start = Instant::now();
+
+        engine.handle_event(&event_at(VK_J, true, start)).unwrap();
+        assert_eq!(engine.next_deadline(), Some(start + Duration::from_millis(10)));
+        engine.tick(start + Duration::from_millis(30)).unwrap();
+        engine.handle_event(&event_at(VK_J, false, start + Duration::from_millis(35))).unwrap();
+        assert_eq!(engine.next_deadline(), None);
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::MouseMove { dx: 0, dy: 10 },
+            OutputEvent::MouseMove { dx: 0, dy: 30 },
+        ]);
+    }
+
+    #[test]
+    fn smooth_scrolls_are_spread_over_ticks() {
+        let (mut engine, recorder) = engine_with_recorder();
+        bind(&mut engine, VK_J, Action::Scroll(Scroll::horizontal(0.5).smooth(Duration::from_millis(30))));
+        let start = Instant::now();
+
+        engine.handle_event(&event_at(VK_J, true, start)).unwrap();
+        assert_eq!(recorder.take(), vec![OutputEvent::MouseHWheel { delta: 20 }]);
+        assert_eq!(engine.next_deadline(), Some(start + Duration::from_millis(10)));
+
+        engine.tick(start + Duration::from_millis(25)).unwrap();
+        assert_eq!(recorder.take(), vec![
+            OutputEvent::MouseHWheel { delta: 20 },
+            OutputEvent::MouseHWheel { delta: 20 },
+        ]);
+        assert_eq!(engine.next_deadline(), None);
+    }
+
+    #[test]
+    fn macro_triggers_play_profile_macros_in_the_background() {
+        let (mut engine, recorder) = engine_with_recorder();
+        let mut greeting = Macro::new("greet");
+        greeting.add_action(MacroAction::TypeText("hello".to_string()));
+        engine.set_macros(HashMap::from([("greet".to_string(), greeting)]));
+        bind(&mut engine, VK_J, Action::MacroTrigger("greet".to_string()));
+        bind(&mut engine, VK_K, Action::TapHold {
+            tap: Box::new(Action::MacroTrigger("missing".to_string())),
+            hold: Box::new(Action::MacroTrigger("greet".to_string())),
+            timeout: Duration::from_millis(200),
+            policy: TapHoldPolicy::Timeout,
+        });
+
+        assert_eq!(engine.unknown_macros(), vec!["missing".to_string()]);
+
+        tap(&mut engine, VK_J);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while recorder.events().is_empty() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(recorder.events(), vec![OutputEvent::Text { text: "hello".to_string() }]);
+    }
+
+    #[test]
+    fn type_text_is_sent_once_per_press() {
+        let (mut engine, recorder) = engine_with_recorder();
+        bind(&mut engine, 0x72, Action::TypeText("Grüße 👋".to_string()));
+
+        tap(&mut engine, 0x72);
+
+        assert_eq!(recorder.events(), vec![OutputEvent::Text { text: "Grüße 👋".to_string() }]);
+    }
+
+    #[test]
+    fn hotstrings_erase_the_abbreviation_and_type_the_replacement() {
+        let (mut engine, recorder) = engine_with_recorder();
+        let mut at = Hotstring::new("@@", "me@example.com");
+        at.options.immediate = true;
+        engine.set_hotstrings(vec![Hotstring::new(";sig", "Regards"), at]);
+        let backspace = [
+            OutputEvent::Key { key: KeyCode::Backspace.vk(), up: false },
+            OutputEvent::Key { key: KeyCode::Backspace.vk(), up: true },
+        ];
+
+        for key in [KeyCode::Semicolon, KeyCode::S, KeyCode::I, KeyCode::G] {
+            assert!(!engine.handle_key_press(key.vk()).unwrap());
+            assert!(!engine.handle_key_release(key.vk()).unwrap());
+        }
+        // The terminator is held back and reaches the system after the replacement
+        assert!(engine.handle_key_press(KeyCode::Space.vk()).unwrap());
+        assert!(engine.handle_key_release(KeyCode::Space.vk()).unwrap());
+        let mut expected: Vec<OutputEvent> = backspace.iter().cycle().take(8).cloned().collect();
+        expected.push(OutputEvent::Text { text: "Regards".to_string() });
+        expected.push(OutputEvent::Key { key: KeyCode::Space.vk(), up: false });
+        expected.push(OutputEvent::Key { key: KeyCode::Space.vk(), up: true });
+        assert_eq!(recorder.take(), expected);
+
+        let shift = KeyCode::LeftShift.vk();
+        engine.handle_key_press(shift).unwrap();
+        tap(&mut engine, KeyCode::Digit2.vk());
+        assert!(engine.handle_key_press(KeyCode::Digit2.vk()).unwrap());
+        assert!(engine.handle_key_release(KeyCode::Digit2.vk()).unwrap());
+        let mut expected = backspace.to_vec();
+        expected.push(OutputEvent::Text { text: "me@example.com".to_string() });
+        assert_eq!(recorder.take(), expected);
+
+        // Without the system layout, typing is read as if on a US layout, and validation says so
+        assert!(engine.keyboard_layout().is_fallback());
+        assert_eq!(engine.validate().warnings().count(), 1);
+    }
+
+    #[test]
+    fn hotstrings_read_letters_typed_with_caps_lock_in_upper_case() {
+        let (mut engine, recorder) = engine_with_recorder();
+        let mut btw = Hotstring::new("btw", "by the way");
+        btw.options.erase = false;
+        engine.set_hotstrings(vec![btw]);
+        let type_btw = |engine: &mut KeyRemapping| {
+            for key in [KeyCode::B, KeyCode::T, KeyCode::W, KeyCode::Space] {
+                tap(engine, key.vk());
+            }
+        };
+
+        tap(&mut engine, KeyCode::CapsLock.vk());
+        type_btw(&mut engine);
+        tap(&mut engine, KeyCode::CapsLock.vk());
+        type_btw(&mut engine);
+
+        let texts: Vec<OutputEvent> = recorder.events().into_iter()
+            .filter(|event| matches!(event, OutputEvent::Text { .. }))
+            .collect();
+        assert_eq!(texts, vec![
+            OutputEvent::Text { text: "BY THE WAY".to_string() },
+            OutputEvent::Text { text: "by the way".to_string() },
+        ]);
+    }
+
+    const VK_CAPITAL: u32 = 0x14;
+
+    /// What passing a key through emits: its scan code, which tells keys sharing a virtual key apart
+    fn passed_through(key: u32, up: bool) -> OutputEvent {
+        OutputEvent::ScanCode { scan: KeyCode::from_vk(key).unwrap().scan_code(), up }
+    }
+
+    fn event_at(key: u32, pressed: bool, time: Instant) -> InputEvent {
+        let event = if pressed { InputEvent::press(key) } else { InputEvent::release(key) };
+        InputEvent { time, ..event }
+    }
+
+    fn bind_caps_tap_hold(engine: &mut KeyRemapping, policy: TapHoldPolicy) {
+        bind(engine, VK_CAPITAL, Action::TapHold {
+            tap: Box::new(Action::KeyPress(VK_ESCAPE)),
+            hold: Box::new(Action::KeyPress(VK_CONTROL)),
+            timeout: Duration::from_millis(200),
+            policy,
+        });
+    }
+
+    #[test]
+    fn tap_hold_taps_when_released_before_timeout() {
+        let (mut engine, recorder) = engine_with_recorder();
+        bind_caps_tap_hold(&mut engine, TapHoldPolicy::Timeout);
+        let t0 = Instant::now();
+
+        assert!(engine.handle_event(&event_at(VK_CAPITAL, true, t0)).unwrap());
+        assert!(recorder.events().is_empty());
+        assert!(engine.handle_event(&event_at(VK_CAPITAL, false, t0 + Duration::from_millis(50))).unwrap());
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: VK_ESCAPE, up: false },
+            OutputEvent::Key { key: VK_ESCAPE, up: true },
+        ]);
+    }
+
+    #[test]
+    fn tap_hold_holds_after_timeout() {
+        let (mut engine, recorder) = engine_with_recorder();
+        bind_caps_tap_hold(&mut engine, TapHoldPolicy::Timeout);
+        let t0 = Instant::now();
+
+        engine.handle_event(&event_at(VK_CAPITAL, true, t0)).unwrap();
+        assert_eq!(engine.next_deadline(), Some(t0 + Duration::from_millis(200)));
+        engine.tick(t0 + Duration::from_millis(100)).unwrap();
+        assert!(recorder.events().is_empty());
+        engine.tick(t0 + Duration::from_millis(200)).unwrap();
+        engine.handle_event(&event_at(VK_CAPITAL, false, t0 + Duration::from_millis(400))).unwrap();
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: VK_CONTROL, up: false },
+            OutputEvent::Key { key: VK_CONTROL, up: true },
+        ]);
+    }
+
+    #[test]
+    fn tap_hold_timeout_policy_delays_other_keys_until_decided() {
+        let (mut engine, recorder) = engine_with_recorder();
+        bind_caps_tap_hold(&mut engine, TapHoldPolicy::Timeout);
+        let t0 = Instant::now();
+
+        engine.handle_event(&event_at(VK_CAPITAL, true, t0)).unwrap();
+        assert!(engine.handle_event(&event_at(0x41, true, t0 + Duration::from_millis(20))).unwrap());
+        assert!(engine.handle_event(&event_at(0x41, false, t0 + Duration::from_millis(40))).unwrap());
+        engine.handle_event(&event_at(VK_CAPITAL, false, t0 + Duration::from_millis(60))).unwrap();
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: VK_ESCAPE, up: false },
+            OutputEvent::Key { key: VK_ESCAPE, up: true },
+            passed_through(0x41, false),
+            passed_through(0x41, true),
+        ]);
+    }
+
+    #[test]
+    fn tap_hold_permissive_hold_on_nested_tap() {
+        let (mut engine, recorder) = engine_with_recorder();
+        bind_caps_tap_hold(&mut engine, TapHoldPolicy::PermissiveHold);
+        let t0 = Instant::now();
+
+        engine.handle_event(&event_at(VK_CAPITAL, true, t0)).unwrap();
+        engine.handle_event(&event_at(0x41, true, t0 + Duration::from_millis(20))).unwrap();
+        // The release decides the hold and is re-sent after it
+        assert!(engine.handle_event(&event_at(0x41, false, t0 + Duration::from_millis(40))).unwrap());
+        engine.handle_event(&event_at(VK_CAPITAL, false, t0 + Duration::from_millis(60))).unwrap();
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: VK_CONTROL, up: false },
+            passed_through(0x41, false),
+            passed_through(0x41, true),
+            OutputEvent::Key { key: VK_CONTROL, up: true },
+        ]);
+    }
+
+    #[test]
+    fn tap_hold_hold_on_other_key_press() {
+        let (mut engine, recorder) = engine_with_recorder();
+        bind_caps_tap_hold(&mut engine, TapHoldPolicy::HoldOnOtherKeyPress);
+        let t0 = Instant::now();
+
+        engine.handle_event(&event_at(VK_CAPITAL, true, t0)).unwrap();
+        assert!(engine.handle_event(&event_at(0x41, true, t0 + Duration::from_millis(20))).unwrap());
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: VK_CONTROL, up: false },
+            passed_through(0x41, false),
+        ]);
+    }
+
+    #[test]
+    fn keys_deciding_a_tap_hold_pass_through_as_the_same_physical_key() {
+        let (mut engine, recorder) = engine_with_recorder();
+        bind_caps_tap_hold(&mut engine, TapHoldPolicy::HoldOnOtherKeyPress);
+        let t0 = Instant::now();
+        // NumpadEnter shares its virtual key with Enter
+        let numpad_enter = InputEvent { scan: KeyCode::NumpadEnter.scan_code(), ..event_at(0x0D, true, t0 + Duration::from_millis(20)) };
+
+        engine.handle_event(&event_at(VK_CAPITAL, true, t0)).unwrap();
+        assert!(engine.handle_event(&numpad_enter).unwrap());
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: VK_CONTROL, up: false },
+            OutputEvent::ScanCode { scan: 0xE01C, up: false },
+        ]);
+    }
+
+    const VK_J: u32 = 0x4A;
+    const VK_K: u32 = 0x4B;
+
+    fn engine_with_jk_combo() -> (KeyRemapping, Arc<RecordingBackend>) {
+        let (mut engine, recorder) = engine_with_recorder();
+        engine.add_combo(Combo {
+            keys: vec![VK_J, VK_K],
+            action: Action::KeyPress(VK_ESCAPE),
+            window: Duration::from_millis(30),
+        }).unwrap();
+        (engine, recorder)
+    }
+
+    #[test]
+    fn combo_fires_when_keys_pressed_within_window() {
+        let (mut engine, recorder) = engine_with_jk_combo();
+        let t0 = Instant::now();
+
+        assert!(engine.handle_event(&event_at(VK_J, true, t0)).unwrap());
+        assert!(engine.handle_event(&event_at(VK_K, true, t0 + Duration::from_millis(10))).unwrap());
+        assert!(engine.handle_event(&event_at(VK_J, false, t0 + Duration::from_millis(80))).unwrap());
+        assert!(engine.handle_event(&event_at(VK_K, false, t0 + Duration::from_millis(90))).unwrap());
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: VK_ESCAPE, up: false },
+            OutputEvent::Key { key: VK_ESCAPE, up: true },
+        ]);
+    }
+
+    #[test]
+    fn combo_replays_presses_when_window_expires() {
+        let (mut engine, recorder) = engine_with_jk_combo();
+        let t0 = Instant::now();
+
+        engine.handle_event(&event_at(VK_J, true, t0)).unwrap();
+        assert_eq!(engine.next_deadline(), Some(t0 + Duration::from_millis(30)));
+        engine.tick(t0 + Duration::from_millis(30)).unwrap();
+        assert_eq!(recorder.take(), vec![passed_through(VK_J, false)]);
+
+        // A late K starts a fresh combo attempt and is replayed once released alone
+        assert!(engine.handle_event(&event_at(VK_K, true, t0 + Duration::from_millis(40))).unwrap());
+        assert!(engine.handle_event(&event_at(VK_K, false, t0 + Duration::from_millis(50))).unwrap());
+        assert_eq!(recorder.events(), vec![
+            passed_through(VK_K, false),
+            passed_through(VK_K, true),
+        ]);
+    }
+
+    #[test]
+    fn combo_replays_in_original_order_when_other_key_interrupts() {
+        let (mut engine, recorder) = engine_with_jk_combo();
+        let t0 = Instant::now();
+
+        engine.handle_event(&event_at(VK_J, true, t0)).unwrap();
+        assert!(engine.handle_event(&event_at(0x4C, true, t0 + Duration::from_millis(5))).unwrap());
+        engine.handle_event(&event_at(VK_J, false, t0 + Duration::from_millis(50))).unwrap();
+
+        assert_eq!(recorder.events(), vec![
+            passed_through(VK_J, false),
+            passed_through(0x4C, false),
+        ]);
+    }
+
+    #[test]
+    fn combo_replays_press_when_released_early() {
+        let (mut engine, recorder) = engine_with_jk_combo();
+        let t0 = Instant::now();
+
+        engine.handle_event(&event_at(VK_J, true, t0)).unwrap();
+        assert!(engine.handle_event(&event_at(VK_J, false, t0 + Duration::from_millis(10))).unwrap());
+
+        assert_eq!(recorder.events(), vec![
+            passed_through(VK_J, false),
+            passed_through(VK_J, true),
+        ]);
+    }
+
+    #[test]
+    fn combo_replays_keys_by_their_captured_scan_code() {
+        let (mut engine, recorder) = engine_with_recorder();
+        let numpad_add = KeyCode::NumpadAdd.vk();
+        engine.add_combo(Combo {
+            keys: vec![0x0D, numpad_add],
+            action: Action::KeyPress(VK_ESCAPE),
+            window: Duration::from_millis(30),
+        }).unwrap();
+        let t0 = Instant::now();
+        let numpad_enter = |pressed, time| InputEvent { scan: KeyCode::NumpadEnter.scan_code(), ..event_at(0x0D, pressed, time) };
+
+        engine.handle_event(&numpad_enter(true, t0)).unwrap();
+        assert!(engine.handle_event(&event_at(0x4C, true, t0 + Duration::from_millis(5))).unwrap());
+        assert!(!engine.handle_event(&numpad_enter(false, t0 + Duration::from_millis(10))).unwrap());
+        assert!(engine.handle_event(&numpad_enter(true, t0 + Duration::from_millis(20))).unwrap());
+        engine.tick(t0 + Duration::from_millis(50)).unwrap();
+
+        // Replayed as NumpadEnter, not as the Enter key its virtual key also stands for
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::ScanCode { scan: 0xE01C, up: false },
+            passed_through(0x4C, false),
+            OutputEvent::ScanCode { scan: 0xE01C, up: false },
+        ]);
+    }
+
+    fn engine_with_leader() -> (KeyRemapping, Arc<RecordingBackend>) {
+        let (mut engine, recorder) = engine_with_recorder();
+        let mut leader = LeaderConfig::new(0x20, Duration::from_millis(500));
+        leader.add_sequence(&[0x47, 0x53], Action::KeyPress(0x70)).unwrap();
+        leader.add_sequence(&[0x47], Action::KeyPress(0x71)).unwrap();
+        engine.set_leader(leader);
+        (engine, recorder)
+    }
+
+    fn tap_at(engine: &mut KeyRemapping, key: u32, time: Instant) -> bool {
+        let consumed = engine.handle_event(&event_at(key, true, time)).unwrap();
+        engine.handle_event(&event_at(key, false, time)).unwrap() && consumed
+    }
+
+    #[test]
+    fn leader_sequence_fires_action() {
+        let (mut engine, recorder) = engine_with_leader();
+        let t0 = Instant::now();
+
+        assert!(tap_at(&mut engine, 0x20, t0));
+        assert!(tap_at(&mut engine, 0x47, t0 + Duration::from_millis(100)));
+        assert!(tap_at(&mut engine, 0x53, t0 + Duration::from_millis(200)));
+        assert!(engine.next_deadline().is_none());
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: 0x70, up: false },
+            OutputEvent::Key { key: 0x70, up: true },
+        ]);
+    }
+
+    #[test]
+    fn leader_prefix_fires_on_timeout() {
+        let (mut engine, recorder) = engine_with_leader();
+        let t0 = Instant::now();
+
+        tap_at(&mut engine, 0x20, t0);
+        tap_at(&mut engine, 0x47, t0 + Duration::from_millis(100));
+        assert!(recorder.events().is_empty());
+        engine.tick(t0 + Duration::from_millis(600)).unwrap();
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: 0x71, up: false },
+            OutputEvent::Key { key: 0x71, up: true },
+        ]);
+    }
+
+    #[test]
+    fn leader_cancel_key_and_unknown_keys_end_the_sequence() {
+        let (mut engine, recorder) = engine_with_leader();
+        let t0 = Instant::now();
+
+        tap_at(&mut engine, 0x20, t0);
+        assert!(tap_at(&mut engine, VK_ESCAPE, t0 + Duration::from_millis(10)));
+        assert!(engine.next_deadline().is_none());
+
+        tap_at(&mut engine, 0x20, t0 + Duration::from_millis(20));
+        assert!(tap_at(&mut engine, 0x51, t0 + Duration::from_millis(30)));
+        // Back to normal handling afterwards
+        assert!(!tap_at(&mut engine, 0x47, t0 + Duration::from_millis(40)));
+
+        assert!(recorder.events().is_empty());
+    }
+
+    fn bind_on(engine: &mut KeyRemapping, layer: usize, key: u32, action: Action) {
+        engine.add_binding_to_layer(layer, KeyBinding {
+            key,
+            modifiers: KeyModifiers::default(),
+            action,
+        }).unwrap();
+    }
+
+    fn tap(engine: &mut KeyRemapping, key: u32) {
+        engine.handle_key_press(key).unwrap();
+        engine.handle_key_release(key).unwrap();
+    }
+
+    #[test]
+    fn momentary_layer_is_active_while_held() {
+        let (mut engine, recorder) = engine_with_recorder();
+        let nav = engine.add_layer("Nav");
+        bind(&mut engine, 0x14, Action::MomentaryLayer(nav));
+        bind_on(&mut engine, nav, 0x48, Action::KeyPress(0x25));
+
+        engine.handle_key_press(0x14).unwrap();
+        assert_eq!(engine.active_layers(), vec![0, nav]);
+        tap(&mut engine, 0x48);
+        engine.handle_key_release(0x14).unwrap();
+        assert_eq!(engine.active_layers(), vec![0]);
+        tap(&mut engine, 0x48);
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: 0x25, up: false },
+            OutputEvent::Key { key: 0x25, up: true },
+        ]);
+    }
+
+    #[test]
+    fn key_released_after_layer_change_releases_what_it_pressed() {
+        let (mut engine, recorder) = engine_with_recorder();
+        let nav = engine.add_layer("Nav");
+        bind(&mut engine, 0x14, Action::MomentaryLayer(nav));
+        bind_on(&mut engine, nav, 0x48, Action::KeyPress(0x25));
+
+        engine.handle_key_press(0x14).unwrap();
+        engine.handle_key_press(0x48).unwrap();
+        engine.handle_key_release(0x14).unwrap();
+        assert!(engine.handle_key_release(0x48).unwrap());
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: 0x25, up: false },
+            OutputEvent::Key { key: 0x25, up: true },
+        ]);
+    }
+
+    #[test]
+    fn toggle_and_one_shot_layers() {
+        let (mut engine, _recorder) = engine_with_recorder();
+        let num = engine.add_layer("Num");
+        bind(&mut engine, 0x70, Action::ToggleLayer(num));
+        bind(&mut engine, 0x71, Action::OneShotLayer(num));
+        bind_on(&mut engine, num, 0x70, Action::ToggleLayer(num));
+
+        tap(&mut engine, 0x70);
+        assert_eq!(engine.active_layers(), vec![0, num]);
+        tap(&mut engine, 0x70);
+        assert_eq!(engine.active_layers(), vec![0]);
+
+        tap(&mut engine, 0x71);
+        assert_eq!(engine.active_layers(), vec![0, num]);
+        tap(&mut engine, 0x41);
+        assert_eq!(engine.active_layers(), vec![0]);
+    }
+
+    #[test]
+    fn layer_switch_replaces_base_and_clears_stack() {
+        let (mut engine, _recorder) = engine_with_recorder();
+        let num = engine.add_layer("Num");
+        let game = engine.add_layer("Game");
+        bind(&mut engine, 0x70, Action::ToggleLayer(num));
+        bind_on(&mut engine, num, 0x71, Action::LayerSwitch(game));
+
+        tap(&mut engine, 0x70);
+        tap(&mut engine, 0x71);
+        assert_eq!(engine.active_layers(), vec![game]);
+        assert!(matches!(
+            engine.add_binding_to_layer(7, KeyBinding { key: 0x41, modifiers: KeyModifiers::default(), action: Action::Transparent }),
+            Err(KeyCodeError::InvalidLayerIndex(7))
+        ));
+    }
+
+    #[test]
+    fn transparent_mapping_falls_through_to_lower_layer() {
+        let (mut engine, recorder) = engine_with_recorder();
+        let upper = engine.add_layer("Upper");
+        bind(&mut engine, 0x70, Action::ToggleLayer(upper));
+        bind(&mut engine, 0x41, Action::KeyPress(0x42));
+        bind_on(&mut engine, upper, 0x41, Action::Transparent);
+
+        tap(&mut engine, 0x70);
+        tap(&mut engine, 0x41);
+        // Keys without any mapping in the upper layer are not looked up below it
+        assert!(!engine.handle_key_press(0x70).unwrap());
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: 0x42, up: false },
+            OutputEvent::Key { key: 0x42, up: true },
+        ]);
+    }
+
+    #[test]
+    fn input_layer_is_active_while_its_modifier_key_is_held() {
+        let (mut engine, recorder) = engine_with_recorder();
+        let mut nav = InputLayer::new("Nav", 0x14);
+        nav.add_key_mapping(0x48, 0x25);
+        nav.add_mapping(0x4A, Action::MouseWheel(-1));
+        let index = engine.add_input_layer(&nav).unwrap();
+
+        engine.handle_key_press(0x14).unwrap();
+        assert_eq!(engine.active_layers(), vec![0, index]);
+        tap(&mut engine, 0x48);
+        tap(&mut engine, 0x4A);
+        engine.handle_key_release(0x14).unwrap();
+        assert!(!engine.handle_key_press(0x48).unwrap());
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: 0x25, up: false },
+            OutputEvent::Key { key: 0x25, up: true },
+            OutputEvent::MouseWheel { delta: -120 },
+        ]);
+    }
+
+    #[test]
+    fn validation_reports_bad_layers_and_conflicts_with_locations() {
+        let mut engine = KeyRemapping::new();
+        let trap = engine.add_layer("Trap");
+        let nav = engine.add_layer("Nav");
+        bind(&mut engine, VK_J, Action::ToggleLayer(trap));
+        bind(&mut engine, VK_K, Action::LayerSwitch(7));
+        bind(&mut engine, 0x4E, Action::ToggleLayer(nav));
+        bind_on(&mut engine, trap, VK_J, Action::KeyPress(VK_ESCAPE));
+        bind_on(&mut engine, nav, VK_J, Action::ToggleLayer(nav));
+        engine.add_binding(KeyBinding::from_chord("LeftCtrl+L", Action::KeyPress(VK_ESCAPE)).unwrap()).unwrap();
+        engine.add_binding(KeyBinding::from_chord("RightCtrl+L", Action::KeyPress(VK_ESCAPE)).unwrap()).unwrap();
+        engine.add_binding(KeyBinding::from_chord("Ctrl+M", Action::KeyPress(VK_ESCAPE)).unwrap()).unwrap();
+        engine.add_binding(KeyBinding::from_chord("LeftCtrl+M", Action::KeyPress(VK_ESCAPE)).unwrap()).unwrap();
+        for _ in 0..2 {
+            engine.add_combo(Combo { keys: vec![VK_J, VK_K], action: Action::KeyPress(VK_ESCAPE), window: Duration::from_millis(50) }).unwrap();
+        }
+
+        let report = engine.validate();
+        let found = |location: Location| report.diagnostics.iter()
+            .filter(|diagnostic| diagnostic.location == location)
+            .map(|diagnostic| diagnostic.severity)
+            .collect::<Vec<_>>();
+
+        assert_eq!(found(Location::Binding { layer: 0, key: VK_K }), vec![Severity::Error, Severity::Warning]);
+        assert_eq!(found(Location::Layer(trap)), vec![Severity::Warning]);
+        assert!(found(Location::Layer(nav)).is_empty());
+        assert_eq!(found(Location::Combo(1)), vec![Severity::Error]);
+        let right_ctrl_l = KeyBinding::from_chord("RightCtrl+L", Action::Transparent).unwrap();
+        let right_ctrl_l = engine.create_modifier_key(right_ctrl_l.key, &right_ctrl_l.modifiers);
+        assert_eq!(describe_key(right_ctrl_l), "RightCtrl+L");
+        assert_eq!(found(Location::Binding { layer: 0, key: right_ctrl_l }), vec![Severity::Warning]);
+        let ctrl_m = KeyBinding::from_chord("Ctrl+M", Action::Transparent).unwrap();
+        let ctrl_m = engine.create_modifier_key(ctrl_m.key, &ctrl_m.modifiers);
+        assert_eq!(found(Location::Binding { layer: 0, key: ctrl_m }), vec![Severity::Warning]);
+
+        let path = std::env::temp_dir().join(format!("keyfinitum-invalid-{}.json", std::process::id()));
+        assert!(matches!(engine.save(&path), Err(KeyCodeError::InvalidConfig(report)) if report.has_errors()));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn a_key_bound_twice_is_rejected_when_added_and_when_loaded() {
+        let mut engine = KeyRemapping::new();
+        engine.add_binding(KeyBinding::from_chord("Ctrl+A", Action::KeyPress(0x42)).unwrap()).unwrap();
+        let again = KeyBinding::from_chord("Ctrl+A", Action::KeyPress(0x43)).unwrap();
+        let ctrl_a = engine.create_modifier_key(again.key, &again.modifiers);
+
+        assert!(matches!(engine.add_binding(again.clone()), Err(KeyCodeError::DuplicateBinding(0, key)) if key == ctrl_a));
+        assert!(matches!(engine.add_binding_to_layer(0, again), Err(KeyCodeError::DuplicateBinding(0, _))));
+        assert!(matches!(engine.layers[0].mappings.get(&ctrl_a), Some(Action::KeyPress(0x42))));
+
+        // JSON objects may repeat a key, which would otherwise silently keep only the last binding
+        let entry = format!(r#""{}":{{"KeyPress":66}}"#, ctrl_a);
+        let json = serde_json::to_string(&engine).unwrap();
+        assert!(json.contains(&entry));
+        let json = json.replace(&entry, &format!(r#"{},"{}":{{"KeyPress":67}}"#, entry, ctrl_a));
+        let error = serde_json::from_str::<KeyRemapping>(&json).unwrap_err();
+        assert!(error.to_string().contains("Ctrl+A is bound more than once"), "{}", error);
+    }
+
+    #[test]
+    fn app_bindings_and_layers_follow_the_focused_window() {
+        let (mut engine, recorder) = engine_with_recorder();
+        let f5 = KeyCode::F5.vk();
+        engine.add_binding(KeyBinding::from_chord("F5", Action::KeyCombination(vec![VK_CONTROL, 0x52])).unwrap()).unwrap();
+        engine.add_app_binding(0, AppCondition::process("WindowsTerminal"), KeyBinding::from_chord("F5", Action::KeyPress(f5)).unwrap()).unwrap();
+        let browser = engine.add_layer("Browser");
+        engine.set_layer_condition(browser, Some(AppCondition::title("* - Firefox"))).unwrap();
+        bind_on(&mut engine, browser, VK_J, Action::KeyPress(VK_ESCAPE));
+        bind(&mut engine, VK_K, Action::ToggleLayer(browser));
+        tap(&mut engine, VK_K);
+
+        tap(&mut engine, f5);
+        engine.set_window(WindowContext {
+            process: "WindowsTerminal.exe".to_string(),
+            title: "PowerShell".to_string(),
+            ..WindowContext::default()
+        });
+        tap(&mut engine, f5);
+        assert!(!engine.handle_key_press(VK_J).unwrap());
+        engine.handle_key_release(VK_J).unwrap();
+        engine.set_window(WindowContext {
+            process: "firefox.exe".to_string(),
+            title: "Docs - Firefox".to_string(),
+            ..WindowContext::default()
+        });
+        tap(&mut engine, VK_J);
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: VK_CONTROL, up: false },
+            OutputEvent::Key { key: 0x52, up: false },
+            OutputEvent::Key { key: 0x52, up: true },
+            OutputEvent::Key { key: VK_CONTROL, up: true },
+            OutputEvent::Key { key: f5, up: false },
+            OutputEvent::Key { key: f5, up: true },
+            OutputEvent::Key { key: VK_ESCAPE, up: false },
+            OutputEvent::Key { key: VK_ESCAPE, up: true },
+        ]);
+    }
+
+    #[test]
+    fn device_layers_apply_only_to_events_from_their_device() {
+        let (mut engine, recorder) = engine_with_recorder();
+        let pad = engine.add_layer("Macro pad");
+        engine.set_layer_device(pad, Some(DeviceFilter::product(0x1234, 0x0001))).unwrap();
+        bind_on(&mut engine, pad, VK_J, Action::KeyPress(KeyCode::F13.vk()));
+        bind(&mut engine, VK_K, Action::KeyPress(VK_ESCAPE));
+        let from = |device: Option<SourceDevice>, key: u32| {
+            [InputEvent { device: device.clone(), ..InputEvent::press(key) }, InputEvent { device, ..InputEvent::release(key) }]
+        };
+        let macro_pad = Some(SourceDevice { vendor_id: 0x1234, product_id: 0x0001, device_type: DeviceType::Keyboard });
+        let keyboard = Some(SourceDevice { vendor_id: 0x1234, product_id: 0x0002, device_type: DeviceType::Keyboard });
+
+        for event in from(macro_pad.clone(), VK_J).iter().chain(&from(macro_pad, VK_K)) {
+            assert!(engine.handle_event(event).unwrap());
+        }
+        for event in from(keyboard, VK_J).iter().chain(&from(None, VK_J)) {
+            assert!(!engine.handle_event(event).unwrap());
+        }
+
+        assert_eq!(recorder.events(), vec![
+            OutputEvent::Key { key: KeyCode::F13.vk(), up: false },
+            OutputEvent::Key { key: KeyCode::F13.vk(), up: true },
+            OutputEvent::Key { key: VK_ESCAPE, up: false },
+            OutputEvent::Key { key: VK_ESCAPE, up: true },
+        ]);
+    }
+
+    #[test]
+    fn device_layers_are_reported_where_events_have_no_device() {
+        let mut engine = KeyRemapping::new();
+        let pad = engine.add_layer("Macro pad");
+        engine.set_layer_device(pad, Some(DeviceFilter::product(0x1234, 0x0001))).unwrap();
+        let any = engine.add_layer("Any device");
+        engine.set_layer_device(any, Some(DeviceFilter::default())).unwrap();
+
+        let flagged: Vec<Location> = engine.validate().warnings().map(|warning| warning.location.clone()).collect();
+        let expected = if ATTRIBUTES_DEVICES { vec![] } else { vec![Location::Layer(pad)] };
+        assert_eq!(flagged, expected);
+    }
 }
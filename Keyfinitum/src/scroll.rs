@@ -0,0 +1,116 @@
+// Keyfinitum/src/scroll.rs
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use crate::output::OutputEvent;
+
+/// Wheel-delta units per notch
+const WHEEL_DELTA: f64 = 120.0;
+
+/// Time between two events of a smooth scroll
+const SMOOTH_STEP_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A scroll by a possibly fractional number of notches on each axis
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct Scroll {
+    /// Notches to scroll right; negative scrolls left
+    #[serde(default)]
+    pub horizontal: f64,
+    /// Notches to scroll up; negative scrolls down
+    #[serde(default)]
+    pub vertical: f64,
+    /// Spread the scroll over this long in small events instead of sending it at once
+    #[serde(default)]
+    pub smooth: Option<Duration>,
+}
+
+#[allow(dead_code)]
+impl Scroll {
+    pub fn vertical(notches: f64) -> Self {
+        Self { vertical: notches, ..Self::default() }
+    }
+
+    pub fn horizontal(notches: f64) -> Self {
+        Self { horizontal: notches, ..Self::default() }
+    }
+
+    /// Spread the scroll over `duration`
+    pub fn smooth(self, duration: Duration) -> Self {
+        Self { smooth: Some(duration), ..self }
+    }
+
+    /// The wheel events of the scroll, each batch with its delay after the previous one.
+    /// Rounding is carried between steps so the steps add up to the whole scroll.
+    pub fn steps(&self) -> Vec<(Duration, Vec<OutputEvent>)> {
+        let count = match self.smooth {
+            Some(duration) => (duration.as_millis() / SMOOTH_STEP_INTERVAL.as_millis()).max(1) as u32,
+            None => 1,
+        };
+        let total = (self.horizontal * WHEEL_DELTA, self.vertical * WHEEL_DELTA);
+        let mut sent = (0, 0);
+        let mut steps = Vec::new();
+
+        for step in 1..=count {
+            let fraction = step as f64 / count as f64;
+            let target = ((total.0 * fraction).round() as i32, (total.1 * fraction).round() as i32);
+            let mut events = Vec::new();
+            if target.1 != sent.1 {
+                events.push(OutputEvent::MouseWheel { delta: target.1 - sent.1 });
+            }
+            if target.0 != sent.0 {
+                events.push(OutputEvent::MouseHWheel { delta: target.0 - sent.0 });
+            }
+            sent = target;
+
+            let delay = if step == 1 { Duration::ZERO } else { SMOOTH_STEP_INTERVAL };
+            steps.push((delay, events));
+        }
+
+        merge_empty_steps(steps)
+    }
+}
+
+/// Drop steps without events, adding their delay to the step after them
+fn merge_empty_steps(steps: Vec<(Duration, Vec<OutputEvent>)>) -> Vec<(Duration, Vec<OutputEvent>)> {
+    let mut merged = Vec::new();
+    let mut carried = Duration::ZERO;
+    for (delay, events) in steps {
+        if events.is_empty() {
+            carried += delay;
+        } else {
+            merged.push((carried + delay, events));
+            carried = Duration::ZERO;
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fractional_scrolls_round_to_wheel_units() {
+        assert_eq!(Scroll { horizontal: -0.5, vertical: 1.25, smooth: None }.steps(), vec![(
+            Duration::ZERO,
+            vec![OutputEvent::MouseWheel { delta: 150 }, OutputEvent::MouseHWheel { delta: -60 }],
+        )]);
+    }
+
+    #[test]
+    fn smooth_scrolls_add_up_to_the_whole_scroll() {
+        let steps = Scroll::vertical(-1.0).smooth(Duration::from_millis(70)).steps();
+
+        assert_eq!(steps.len(), 7);
+        assert_eq!(steps[0].0, Duration::ZERO);
+        assert!(steps[1..].iter().all(|(delay, _)| *delay == SMOOTH_STEP_INTERVAL));
+        let total: i32 = steps.iter()
+            .flat_map(|(_, events)| events)
+            .map(|event| match event {
+                OutputEvent::MouseWheel { delta } => *delta,
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(total, -120);
+    }
+}
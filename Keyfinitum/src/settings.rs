@@ -0,0 +1,71 @@
+// Keyfinitum/src/settings.rs
+
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use crate::command::CommandPolicy;
+
+/// Settings that belong to this machine rather than to a profile, so sharing a profile never shares them
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct MachineSettings {
+    /// Which commands bindings may run
+    #[serde(default)]
+    pub command_policy: CommandPolicy,
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    FileError(String),
+    ParseError(String),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::FileError(msg) => write!(f, "Settings file error: {}", msg),
+            SettingsError::ParseError(msg) => write!(f, "Invalid settings file: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+#[allow(dead_code)]
+impl MachineSettings {
+    /// `%APPDATA%\Keyfinitum\settings.json` on Windows, and `$XDG_CONFIG_HOME/keyfinitum/settings.json`
+    /// or `~/.config/keyfinitum/settings.json` elsewhere
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = if cfg!(windows) {
+            PathBuf::from(env::var_os("APPDATA")?).join("Keyfinitum")
+        } else {
+            env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?
+                .join("keyfinitum")
+        };
+        Some(dir.join("settings.json"))
+    }
+
+    /// Read the settings, or the defaults if the file does not exist yet
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SettingsError> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(SettingsError::FileError(e.to_string())),
+        };
+        serde_json::from_str(&content).map_err(|e| SettingsError::ParseError(e.to_string()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SettingsError> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| SettingsError::FileError(e.to_string()))?;
+        }
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| SettingsError::ParseError(e.to_string()))?;
+        fs::write(path, content).map_err(|e| SettingsError::FileError(e.to_string()))
+    }
+}
@@ -1,10 +1,14 @@
 // Keyfinitum/src/ui.rs
 
 use eframe::egui;
+use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
+use crate::command::CommandSpec;
 use crate::profile_manager::ProfileManager;
 use crate::plugin::PluginManager;
+use crate::capture::{self, InputCapture};
 use crate::device::{DeviceManager, DeviceType, DeviceCapabilities};
+use crate::window::WindowTracker;
 
 mod editor {
     use super::*;
@@ -14,6 +18,7 @@ mod editor {
     }
 
     impl MacroEditor {
+        #[allow(dead_code)]
         pub fn show(&mut self, _ui: &mut egui::Ui) -> Option<()> {
             None // TODO: Implement show
         }
@@ -45,7 +50,14 @@ struct DeviceInfo {
     dpi_step: u16,
 }
 
+/// A command waiting for the user to decide whether it may run
+struct CommandPrompt {
+    spec: CommandSpec,
+    answer: mpsc::Sender<bool>,
+}
+
 /// Main application UI
+#[allow(dead_code)]
 pub struct KeyfinitumApp {
     profile_manager: Arc<Mutex<ProfileManager>>,
     plugin_manager: Arc<Mutex<PluginManager>>,
@@ -62,14 +74,34 @@ pub struct KeyfinitumApp {
     new_app_name: String,
     selected_profile: usize,
     device_manager: DeviceManager,
+    input_capture: Option<InputCapture>,
+    window_tracker: Option<WindowTracker>,
+    command_prompts: mpsc::Receiver<CommandPrompt>,
+    command_prompt: Option<CommandPrompt>,
 }
 
 impl KeyfinitumApp {
-    pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
+    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
         let (tx, rx) = std::sync::mpsc::channel();
         let profile_manager = Arc::new(Mutex::new(ProfileManager::new()));
         let plugin_manager = Arc::new(Mutex::new(PluginManager::new()));
-        Self {
+        if let Err(e) = profile_manager.lock().unwrap().load_active_remapping() {
+            eprintln!("{}", e);
+        }
+        profile_manager.lock().unwrap().start_config_watcher();
+
+        // Commands run on their own threads, which wait here for the user's answer
+        let (prompt_sender, command_prompts) = mpsc::channel();
+        let ctx = cc.egui_ctx.clone();
+        profile_manager.lock().unwrap().set_command_confirmation(Arc::new(move |spec: &CommandSpec| {
+            let (answer, reply) = mpsc::channel();
+            if prompt_sender.send(CommandPrompt { spec: spec.clone(), answer }).is_err() {
+                return false;
+            }
+            ctx.request_repaint();
+            reply.recv().unwrap_or(false)
+        }));
+        let mut app = Self {
             profile_manager,
             plugin_manager,
             profile_switch_sender: tx,
@@ -84,6 +116,34 @@ impl KeyfinitumApp {
             new_app_name: String::new(),
             selected_profile: 0,
             device_manager: DeviceManager::new(),
+            input_capture: None,
+            window_tracker: None,
+            command_prompts,
+            command_prompt: None,
+        };
+        app.start_input_capture();
+        app
+    }
+
+    /// Start routing physical input through the active profile's remapping engine
+    fn start_input_capture(&mut self) {
+        match capture::platform_source() {
+            Ok(source) => {
+                let engine = self.profile_manager.lock().unwrap().remapping();
+                self.window_tracker = Some(WindowTracker::start(Arc::clone(&engine)));
+                self.input_capture = Some(InputCapture::start(source, engine));
+            }
+            Err(e) => eprintln!("Failed to start input capture: {}", e),
+        }
+    }
+
+    /// Stop input capture, returning all input to the system unchanged
+    fn stop_input_capture(&mut self) {
+        if let Some(mut input_capture) = self.input_capture.take() {
+            input_capture.stop();
+        }
+        if let Some(mut window_tracker) = self.window_tracker.take() {
+            window_tracker.stop();
         }
     }
 }
@@ -91,6 +151,22 @@ impl KeyfinitumApp {
 impl eframe::App for KeyfinitumApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         egui::CentralPanel::default().show(ctx, |ui| {
+            // Input capture section
+            ui.collapsing("Input Capture", |ui| {
+                let running = self.input_capture.as_ref().is_some_and(|capture| capture.is_running());
+                ui.horizontal(|ui| {
+                    ui.label(if running { "Status: Capturing" } else { "Status: Stopped" });
+                    if running {
+                        if ui.button("Stop Capture").clicked() {
+                            self.stop_input_capture();
+                        }
+                    } else if ui.button("Start Capture").clicked() {
+                        self.stop_input_capture();
+                        self.start_input_capture();
+                    }
+                });
+            });
+
             // Devices section
             ui.collapsing("Devices", |ui| {
                 ui.horizontal(|ui| {
@@ -198,5 +274,61 @@ impl eframe::App for KeyfinitumApp {
                 }
             }
         }
+
+        self.show_command_prompt(ctx);
+    }
+}
+
+impl KeyfinitumApp {
+    /// Ask about the next command the policy leaves to the user, one at a time
+    fn show_command_prompt(&mut self, ctx: &egui::Context) {
+        if self.command_prompt.is_none() {
+            self.command_prompt = self.command_prompts.try_recv().ok();
+        }
+        let Some(prompt) = &self.command_prompt else {
+            return;
+        };
+
+        // Whether to run the command, and whether to always allow it
+        let mut decision = None;
+        egui::Window::new("Run Command?")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("A binding wants to run a command that is not allowed yet:");
+                ui.monospace(prompt.spec.command_line());
+                if let Some(dir) = &prompt.spec.working_dir {
+                    ui.label(format!("In: {}", dir.display()));
+                }
+                let mut env: Vec<_> = prompt.spec.env.iter().collect();
+                env.sort();
+                for (name, value) in env {
+                    ui.label(format!("With: {}={}", name, value));
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Run Once").clicked() {
+                        decision = Some((true, false));
+                    }
+                    if ui.button("Always Allow").clicked() {
+                        decision = Some((true, true));
+                    }
+                    if ui.button("Deny").clicked() {
+                        decision = Some((false, false));
+                    }
+                });
+            });
+
+        let Some((run, always)) = decision else {
+            return;
+        };
+        let Some(prompt) = self.command_prompt.take() else {
+            return;
+        };
+        if always {
+            if let Err(e) = self.profile_manager.lock().unwrap().always_allow_command(&prompt.spec) {
+                eprintln!("Failed to allow command: {}", e);
+            }
+        }
+        let _ = prompt.answer.send(run);
     }
 }
@@ -0,0 +1,106 @@
+// Keyfinitum/src/validation.rs
+
+use std::fmt;
+use crate::remapping::describe_key;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Works, but probably not as intended
+    Warning,
+    /// Cannot work as configured
+    Error,
+}
+
+/// Where in a remapping configuration a problem was found
+#[derive(Debug, Clone, PartialEq)]
+pub enum Location {
+    /// A layer's binding of an encoded key, modifier bits included
+    Binding { layer: usize, key: u32 },
+    Layer(usize),
+    Combo(usize),
+    /// A sequence typed after the leader key
+    LeaderSequence(Vec<u32>),
+    /// The configuration as a whole
+    Config,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Binding { layer, key } => write!(f, "layer {}, {}", layer, describe_key(*key)),
+            Location::Layer(layer) => write!(f, "layer {}", layer),
+            Location::Combo(index) => write!(f, "combo {}", index),
+            Location::LeaderSequence(keys) => {
+                let keys: Vec<String> = keys.iter().map(|key| describe_key(*key)).collect();
+                write!(f, "leader sequence {}", keys.join(" "))
+            },
+            Location::Config => f.write_str("config"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub location: Location,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{} at {}: {}", severity, self.location, self.message)
+    }
+}
+
+/// Problems found in a remapping configuration, in the order they were found
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidationReport {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[allow(dead_code)]
+impl ValidationReport {
+    pub fn error(&mut self, location: Location, message: impl Into<String>) {
+        self.push(Severity::Error, location, message.into());
+    }
+
+    pub fn warning(&mut self, location: Location, message: impl Into<String>) {
+        self.push(Severity::Warning, location, message.into());
+    }
+
+    fn push(&mut self, severity: Severity, location: Location, message: String) {
+        self.diagnostics.push(Diagnostic { severity, location, message });
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|diagnostic| diagnostic.severity == Severity::Warning)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, diagnostic) in self.diagnostics.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
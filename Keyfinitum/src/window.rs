@@ -0,0 +1,216 @@
+// Keyfinitum/src/window.rs
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use crate::layout::{caps_lock_on, KeyboardLayout};
+use crate::remapping::KeyRemapping;
+
+/// How often the foreground window is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(150);
+
+/// The foreground window that app conditions are matched against
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WindowContext {
+    /// Executable file name, e.g. `WindowsTerminal.exe`
+    pub process: String,
+    /// Display name of the application, where the platform provides one
+    pub app_name: String,
+    pub title: String,
+    /// Window class name; only available on Windows
+    pub class: String,
+}
+
+/// Which windows a binding or layer applies to; every given part must match
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct AppCondition {
+    /// Executable or application name, ignoring case and a trailing `.exe`
+    #[serde(default)]
+    pub process: Option<String>,
+    /// Window title pattern, ignoring case, where `*` matches any text and `?` any one character
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Exact window class name
+    #[serde(default)]
+    pub class: Option<String>,
+}
+
+#[allow(dead_code)]
+impl AppCondition {
+    pub fn process(name: &str) -> Self {
+        Self { process: Some(name.to_string()), ..Self::default() }
+    }
+
+    pub fn title(pattern: &str) -> Self {
+        Self { title: Some(pattern.to_string()), ..Self::default() }
+    }
+
+    pub fn class(name: &str) -> Self {
+        Self { class: Some(name.to_string()), ..Self::default() }
+    }
+
+    pub fn matches(&self, window: &WindowContext) -> bool {
+        let process_matches = self.process.as_ref().is_none_or(|name| {
+            let name = strip_exe(name);
+            name.eq_ignore_ascii_case(strip_exe(&window.process)) || name.eq_ignore_ascii_case(&window.app_name)
+        });
+        let title_matches = self.title.as_ref().is_none_or(|pattern| {
+            let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+            let title: Vec<char> = window.title.to_lowercase().chars().collect();
+            glob_matches(&pattern, &title)
+        });
+        let class_matches = self.class.as_ref().is_none_or(|class| *class == window.class);
+        process_matches && title_matches && class_matches
+    }
+}
+
+fn strip_exe(name: &str) -> &str {
+    let len = name.len();
+    if len > 4 && name.is_char_boundary(len - 4) && name[len - 4..].eq_ignore_ascii_case(".exe") {
+        &name[..len - 4]
+    } else {
+        name
+    }
+}
+
+/// Whether `text` matches a pattern of literal characters, `*` and `?`
+fn glob_matches(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and the text position it was tried at
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p + 1, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            backtrack = Some((star_p, star_t + 1));
+            p = star_p;
+            t = star_t + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// The window that currently has keyboard focus
+pub fn foreground_window() -> Option<WindowContext> {
+    let window = active_win_pos_rs::get_active_window().ok()?;
+    let process = Path::new(&window.process_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Some(WindowContext {
+        process,
+        app_name: window.app_name,
+        title: window.title,
+        class: foreground_class(),
+    })
+}
+
+#[cfg(windows)]
+fn foreground_class() -> String {
+    use winapi::um::winuser::{GetClassNameW, GetForegroundWindow};
+
+    let mut buffer = [0u16; 256];
+    // SAFETY: the buffer length passed matches the buffer, and a null window just yields 0
+    let len = unsafe { GetClassNameW(GetForegroundWindow(), buffer.as_mut_ptr(), buffer.len() as i32) };
+    String::from_utf16_lossy(&buffer[..len.max(0) as usize])
+}
+
+#[cfg(not(windows))]
+fn foreground_class() -> String {
+    String::new()
+}
+
+/// Keeps the engine's view of the foreground window and its keyboard layout current,
+/// for app-specific bindings and hotstrings
+pub struct WindowTracker {
+    tracker_thread: Option<thread::JoinHandle<()>>,
+    stop_signal: Arc<AtomicBool>,
+}
+
+#[allow(dead_code)]
+impl WindowTracker {
+    pub fn start(engine: Arc<Mutex<KeyRemapping>>) -> Self {
+        let stop_signal = Arc::new(AtomicBool::new(false));
+        let thread_stop_signal = Arc::clone(&stop_signal);
+
+        let tracker_thread = thread::spawn(move || {
+            while !thread_stop_signal.load(Ordering::SeqCst) {
+                // Read on every poll: switching layouts, such as with Win+Space, leaves the window focused
+                let layout = KeyboardLayout::current();
+                let window = foreground_window();
+                {
+                    let mut engine = engine.lock().unwrap();
+                    if *engine.keyboard_layout() != layout {
+                        engine.set_keyboard_layout(layout);
+                    }
+                }
+
+                if let Some(window) = window {
+                    // Compared against the engine, so a freshly loaded engine is brought up to date too
+                    let changed = *engine.lock().unwrap().window() != window;
+                    if changed {
+                        // The engine follows Caps Lock presses itself, but only from the state it starts with
+                        let caps_lock = caps_lock_on();
+                        let mut engine = engine.lock().unwrap();
+                        if let Some(on) = caps_lock {
+                            engine.set_caps_lock(on);
+                        }
+                        engine.set_window(window);
+                    }
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        });
+
+        Self {
+            tracker_thread: Some(tracker_thread),
+            stop_signal,
+        }
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(handle) = self.tracker_thread.take() {
+            self.stop_signal.store(true, Ordering::SeqCst);
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for WindowTracker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conditions_match_process_title_pattern_and_class() {
+        let terminal = WindowContext {
+            process: "WindowsTerminal.exe".to_string(),
+            app_name: "Windows Terminal".to_string(),
+            title: "PowerShell - build".to_string(),
+            class: "CASCADIA_HOSTING_WINDOW_CLASS".to_string(),
+        };
+
+        assert!(AppCondition::process("windowsterminal").matches(&terminal));
+        assert!(AppCondition::process("Windows Terminal").matches(&terminal));
+        assert!(!AppCondition::process("Terminal").matches(&terminal));
+        assert!(AppCondition::title("*shell - ?uild").matches(&terminal));
+        assert!(!AppCondition::title("*shell").matches(&terminal));
+        assert!(AppCondition::class("CASCADIA_HOSTING_WINDOW_CLASS").matches(&terminal));
+        assert!(!AppCondition { class: Some("Notepad".to_string()), ..AppCondition::process("WindowsTerminal") }.matches(&terminal));
+        assert!(AppCondition::default().matches(&WindowContext::default()));
+    }
+}